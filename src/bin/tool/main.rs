@@ -11,6 +11,7 @@ pub mod create_net_ocl;
 #[cfg(feature = "opencl")]
 pub mod train_ocl;
 pub mod dataset_info;
+pub mod summary;
 pub mod train;
 pub mod test;
 pub mod train_tui;
@@ -44,6 +45,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .takes_value(true)
                 .require_equals(true)
         )
+        .arg(
+            Arg::new("PartialState")
+                .long("partial_state")
+                .help("Load model state non-strictly, layers missing in state or with another shape keep initialization")
+        )
         .arg(
             Arg::new("ModelCfg")
                 .long("model")
@@ -208,6 +214,23 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .value_parser(clap::value_parser!(bool))
                 .takes_value(false)
         ))
+        .subcommand(Command::new("summary").about("Show model layers shapes, parameters, FLOPs and memory")
+        .arg(
+            Arg::new("ModelCfg")
+            .long("model_cfg")
+            .help("Provide model configuration yaml file")
+            .takes_value(true)
+            .require_equals(true)
+            .required(true)
+        )
+        .arg(
+            Arg::new("BatchSize")
+            .long("batch_size")
+            .help("Batch size for FLOPs and memory estimation, model batch size by default")
+            .takes_value(true)
+            .require_equals(true)
+            .value_parser(clap::value_parser!(usize))
+        ))
         .after_help("after help message. TODO : expand with examples")
         .get_matches();
 
@@ -251,6 +274,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         let (_subcmd, args) = matches.subcommand().unwrap();
         train::gen_init_state(&args)?;
     }
+    if cmd.0 == "summary" {
+        let (_subcmd, args) = matches.subcommand().unwrap();
+        summary::model_summary(&args)?;
+    }
     if cmd.0 == "create_net" {
         let (_subcmd, args) = matches.subcommand().unwrap();
         create_net::create_net(&args)?;
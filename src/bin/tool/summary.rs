@@ -0,0 +1,35 @@
+use std::error::Error;
+
+use clap::ArgMatches;
+
+use nevermind_neu::models::*;
+
+use crate::train::check_model_type;
+
+/// Prints per-layer summary of the model configuration
+#[allow(unreachable_code)]
+pub fn model_summary(args: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let model_cfg = args.get_one::<String>("ModelCfg").unwrap();
+    let batch_size = args.get_one::<usize>("BatchSize");
+
+    let mdl_type = check_model_type(model_cfg)?;
+
+    if mdl_type.as_str() == "mdl_sequential_ocl" {
+        #[cfg(feature = "opencl")]
+        {
+            let model = SequentialOcl::from_file(model_cfg)?;
+            let batch_size = batch_size.cloned().unwrap_or(model.batch_size());
+
+            println!("{}", model.summary(batch_size));
+            return Ok(());
+        }
+        panic!("Compiled without opencl support");
+    }
+
+    let model = Sequential::from_file(model_cfg)?;
+    let batch_size = batch_size.cloned().unwrap_or(model.batch_size());
+
+    println!("{}", model.summary(batch_size));
+
+    Ok(())
+}
@@ -106,7 +106,15 @@ pub fn create_net_from_cmd_args(
     let mut model = Sequential::from_file(&model_cfg)?;
 
     if let Some(model_state) = args.get_one::<String>("ModelState") {
-        model.load_state(&model_state)?;
+        if args.contains_id("PartialState") {
+            let report = model.load_state_with(&model_state, false)?;
+            info!(
+                "Loaded layers : {:?}, missing : {:?}, mismatched : {:?}",
+                report.loaded, report.missing, report.mismatched
+            );
+        } else {
+            model.load_state(&model_state)?;
+        }
     }
 
     if let Some(optimizer_cfg) = args.get_one::<String>("OptCfg") {
@@ -187,7 +195,7 @@ pub fn create_net_from_cmd_args(
     Ok(net)
 }
 
-fn check_model_type(mdl_cfg: &str) -> Result<String, Box<dyn Error>> {
+pub fn check_model_type(mdl_cfg: &str) -> Result<String, Box<dyn Error>> {
     let mdl_file = File::open(mdl_cfg)?;
     let mdl: SerdeSequentialModel = serde_yaml::from_reader(mdl_file)?;
     return Ok(mdl.mdl_type);
@@ -78,7 +78,15 @@ pub fn create_net_ocl_from_cmd_args(
     let mut model = SequentialOcl::from_file(&model_cfg)?;
 
     if let Some(model_state) = args.get_one::<String>("ModelState") {
-        model.load_state(&model_state)?;
+        if args.contains_id("PartialState") {
+            let report = model.load_state_with(&model_state, false)?;
+            info!(
+                "Loaded layers : {:?}, missing : {:?}, mismatched : {:?}",
+                report.loaded, report.missing, report.mismatched
+            );
+        } else {
+            model.load_state(&model_state)?;
+        }
     }
 
     if let Some(optimizer_cfg) = args.get_one::<String>("OptCfg") {
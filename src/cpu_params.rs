@@ -23,6 +23,17 @@ pub enum TypeBuffer {
     Bias = 3,
     NeuGrad = 4,
     BiasGrad = 5, // averaged neuron gradient
+    InputGrad = 6, // gradient with respect to the layer input
+    RunningMean = 7, // not trainable, used by normalization layers in eval mode
+    RunningVar = 8,
+    RecurrentWeights = 9, // hidden to hidden weights of recurrent layers
+    RecurrentWeightsGrad = 10,
+    ProjWeights = 11, // output projection of attention layers
+    ProjWeightsGrad = 12,
+    ProjBias = 13,
+    ProjBiasGrad = 14,
+    PosEncoding = 15, // positional encoding added to the sequence input
+    PosEncodingGrad = 16,
 }
 
 #[derive(Clone)]
@@ -86,6 +97,28 @@ impl From<i32> for TypeBuffer {
             return TypeBuffer::NeuGrad;
         } else if value == 5 {
             return TypeBuffer::BiasGrad;
+        } else if value == 6 {
+            return TypeBuffer::InputGrad;
+        } else if value == 7 {
+            return TypeBuffer::RunningMean;
+        } else if value == 8 {
+            return TypeBuffer::RunningVar;
+        } else if value == 9 {
+            return TypeBuffer::RecurrentWeights;
+        } else if value == 10 {
+            return TypeBuffer::RecurrentWeightsGrad;
+        } else if value == 11 {
+            return TypeBuffer::ProjWeights;
+        } else if value == 12 {
+            return TypeBuffer::ProjWeightsGrad;
+        } else if value == 13 {
+            return TypeBuffer::ProjBias;
+        } else if value == 14 {
+            return TypeBuffer::ProjBiasGrad;
+        } else if value == 15 {
+            return TypeBuffer::PosEncoding;
+        } else if value == 16 {
+            return TypeBuffer::PosEncodingGrad;
         } else {
             panic!("Invalid integer to convert");
         }
@@ -217,6 +250,60 @@ impl CpuParams {
         }
     }
 
+    /// Same as new_with_bias(...), but output and neuron gradient have their own size.
+    /// Used by layers which output isn't a value per weights row (convolutions and etc.)
+    pub fn new_with_bias_and_output(size: usize, prev_size: usize, output_size: usize) -> Self {
+        let mut lp = CpuParams::new_with_bias(size, prev_size);
+
+        let output =
+            VariantParamArc::Array2(Arc::new(RefCell::new(Array2D::zeros((1, output_size)))));
+        let neu_grad =
+            VariantParamArc::Array2(Arc::new(RefCell::new(Array2D::zeros((1, output_size)))));
+
+        lp.insert_buf(TypeBuffer::Output as i32, output);
+        lp.insert_buf(TypeBuffer::NeuGrad as i32, neu_grad);
+
+        lp
+    }
+
+    /// Params of recurrent layers : input weights (size, input_size),
+    /// recurrent weights (size, hidden_size), bias, output and input gradient.
+    /// For gated layers size is a multiple of hidden_size
+    pub fn new_recurrent(
+        size: usize,
+        input_size: usize,
+        hidden_size: usize,
+        output_size: usize,
+        seq_input_size: usize,
+    ) -> Self {
+        let mut lp = CpuParams::new_with_bias(size, input_size);
+        lp.remove_buf(TypeBuffer::NeuGrad as i32);
+
+        let output =
+            VariantParamArc::Array2(Arc::new(RefCell::new(Array2D::zeros((1, output_size)))));
+        let rec_ws = VariantParamArc::Array2(Arc::new(RefCell::new(WsMat::random(
+            (size, hidden_size),
+            Uniform::new(-0.1, 0.1),
+        ))));
+        let rec_ws_grad =
+            VariantParamArc::Array2(Arc::new(RefCell::new(WsMat::zeros((size, hidden_size)))));
+
+        lp.insert_buf(TypeBuffer::Output as i32, output);
+        lp.insert_buf(TypeBuffer::RecurrentWeights as i32, rec_ws);
+        lp.insert_buf(TypeBuffer::RecurrentWeightsGrad as i32, rec_ws_grad);
+        lp.add_input_grad(seq_input_size);
+
+        lp
+    }
+
+    /// Adds the buffer for gradient with respect to layer input.
+    /// Previous layer uses it instead of this layer weights while backpropagating
+    pub fn add_input_grad(&mut self, input_size: usize) {
+        let input_grad =
+            VariantParamArc::Array2(Arc::new(RefCell::new(Array2D::zeros((1, input_size)))));
+        self.insert_buf(TypeBuffer::InputGrad as i32, input_grad);
+    }
+
     pub fn get_1d_buf(&self, id: i32) -> Arc<RefCell<Array1D>> {
         let res_prm = self.params.get(&id).unwrap();
 
@@ -270,6 +357,11 @@ impl CpuParams {
     }
 
     pub fn fit_to_batch_size(&mut self, new_batch_size: usize) {
+        // buffers of the layer with invalid input shape aren't allocated
+        if !self.contains_buf_t(TypeBuffer::Output) {
+            return;
+        }
+
         let out_m = self.get_2d_buf_t(TypeBuffer::Output);
         let mut out_m = out_m.borrow_mut();
         let size = out_m.ncols();
@@ -282,10 +374,21 @@ impl CpuParams {
                 let mut err_m = err_m.borrow_mut();
                 *err_m = Array2D::zeros((new_batch_size, size));
             }
+
+            if self.contains_buf_t(TypeBuffer::InputGrad) {
+                let inp_grad_m = self.get_2d_buf_t(TypeBuffer::InputGrad);
+                let mut inp_grad_m = inp_grad_m.borrow_mut();
+                let inp_size = inp_grad_m.ncols();
+                *inp_grad_m = Array2D::zeros((new_batch_size, inp_size));
+            }
         }
     }
 
     pub fn prepare_for_tests(&mut self, batch_size: usize) {
+        if !self.contains_buf_t(TypeBuffer::Output) {
+            return;
+        }
+
         let out_size = self.get_2d_buf_t(TypeBuffer::Output).borrow().ncols();
 
         self.params.remove(&(TypeBuffer::NeuGrad as i32));
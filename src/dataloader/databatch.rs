@@ -1,12 +1,24 @@
+use std::collections::HashMap;
+
 use ndarray::{Array, Axis};
 
-use crate::util::{DataVec, Array2D};
+use log::error;
+
+use crate::util::{DataVec, Array1D, Array2D, LossWeights, NamedArrays};
 
 
 #[derive(Clone, Default)]
 pub struct LabeledEntry {
     pub input: DataVec,
     pub expected: DataVec,
+    /// Loss multiplier of the sample, 1.0 if not set
+    pub weight: Option<f32>,
+    /// Loss multiplier for each expected value, zeros exclude outputs (e.g. padding) from the loss
+    pub mask: Option<DataVec>,
+    /// Inputs of models with several input nodes, keyed by node name
+    pub named_inputs: HashMap<String, DataVec>,
+    /// Targets of models with several outputs, keyed by output node name
+    pub named_expected: HashMap<String, DataVec>,
 }
 
 impl LabeledEntry {
@@ -14,14 +26,69 @@ impl LabeledEntry {
         Self {
             input: Array::from_vec(input),
             expected: Array::from_vec(expected),
+            weight: None,
+            mask: None,
+            named_inputs: HashMap::new(),
+            named_expected: HashMap::new(),
+        }
+    }
+
+    pub fn weight(mut self, weight: f32) -> Self {
+        self.weight = Some(weight);
+        self
+    }
+
+    pub fn mask(mut self, mask: Vec<f32>) -> Self {
+        self.mask = Some(Array::from_vec(mask));
+        self
+    }
+
+    pub fn named_input(mut self, name: &str, input: Vec<f32>) -> Self {
+        self.named_inputs.insert(name.to_owned(), Array::from_vec(input));
+        self
+    }
+
+    pub fn named_expected(mut self, name: &str, expected: Vec<f32>) -> Self {
+        self.named_expected.insert(name.to_owned(), Array::from_vec(expected));
+        self
+    }
+}
+
+/// Stacks named vectors of the entries, names are taken from the first entry
+fn stack_named<'a, F>(b: &[&'a LabeledEntry], get: F) -> NamedArrays
+where
+    F: Fn(&'a LabeledEntry) -> &'a HashMap<String, DataVec>,
+{
+    let mut out = NamedArrays::new();
+
+    for (name, first) in get(b[0]).iter() {
+        let mut arr = Array2D::zeros((b.len(), first.len()));
+
+        for (idx, it) in b.iter().enumerate() {
+            match get(it).get(name) {
+                Some(v) if v.len() == first.len() => arr.index_axis_mut(Axis(0), idx).assign(v),
+                _ => error!("Batch entry {} has no valid {} tensor", idx, name),
+            }
         }
+
+        out.insert(name.clone(), arr);
     }
+
+    out
 }
 
 #[derive(Default, Clone)]
 pub struct MiniBatch {
     pub input: Array2D,
     pub output: Array2D,
+    /// Set if any entry of the batch has weight
+    pub sample_weights: Option<Array1D>,
+    /// Set if any entry of the batch has mask
+    pub mask: Option<Array2D>,
+    /// Inputs of models with several input nodes, keyed by node name
+    pub named_inputs: NamedArrays,
+    /// Targets of models with several outputs, keyed by output node name
+    pub named_outputs: NamedArrays,
 }
 
 impl MiniBatch {
@@ -31,28 +98,16 @@ impl MiniBatch {
         let mut inp_arr = Array2D::zeros( (b.len(), b.first().unwrap().input.shape()[0]) );
         let mut out_arr = Array2D::zeros( (b.len(), b.first().unwrap().expected.shape()[0]) );
 
-        // Copies memory into batch
+        let mut sample_weights = None;
+        let mut mask = None;
 
-        for (idx, it) in b.iter().enumerate() {
-            let mut inp_entry = inp_arr.index_axis_mut(Axis(0), idx);
-            inp_entry.assign(&it.input);
-
-            let mut out_entry = out_arr.index_axis_mut(Axis(0), idx);
-            out_entry.assign(&it.expected);
+        if b.iter().any(|it| it.weight.is_some()) {
+            sample_weights = Some(Array1D::ones(b.len()));
         }
 
-        Self {
-            input: inp_arr,
-            output: out_arr,
+        if b.iter().any(|it| it.mask.is_some()) {
+            mask = Some(Array2D::ones(out_arr.dim()));
         }
-    }
-
-    pub fn new_no_ref(b: Vec<LabeledEntry>) -> Self {
-        // TODO : refactor cause dublicating constructors
-        assert!( !b.is_empty() );
-
-        let mut inp_arr = Array2D::zeros( (b.len(), b.first().unwrap().input.shape()[0]) );
-        let mut out_arr = Array2D::zeros( (b.len(), b.first().unwrap().expected.shape()[0]) );
 
         // Copies memory into batch
 
@@ -62,11 +117,42 @@ impl MiniBatch {
 
             let mut out_entry = out_arr.index_axis_mut(Axis(0), idx);
             out_entry.assign(&it.expected);
+
+            if let (Some(sample_weights), Some(weight)) = (sample_weights.as_mut(), it.weight) {
+                sample_weights[idx] = weight;
+            }
+
+            // entry with mismatched mask is left unmasked
+            if let (Some(mask), Some(entry_mask)) = (mask.as_mut(), it.mask.as_ref()) {
+                if entry_mask.len() == out_arr.ncols() {
+                    mask.index_axis_mut(Axis(0), idx).assign(entry_mask);
+                } else {
+                    error!(
+                        "Batch entry {} has mask of size {}, expected {}",
+                        idx,
+                        entry_mask.len(),
+                        out_arr.ncols()
+                    );
+                }
+            }
         }
 
         Self {
             input: inp_arr,
             output: out_arr,
+            sample_weights,
+            mask,
+            named_inputs: stack_named(&b, |it| &it.named_inputs),
+            named_outputs: stack_named(&b, |it| &it.named_expected),
         }
     }
-}
\ No newline at end of file
+
+    pub fn new_no_ref(b: Vec<LabeledEntry>) -> Self {
+        MiniBatch::new(b.iter().collect())
+    }
+
+    /// Sample weights and mask in the form consumed by loss layers
+    pub fn loss_weights(&self) -> LossWeights {
+        LossWeights::new(self.sample_weights.clone(), self.mask.clone())
+    }
+}
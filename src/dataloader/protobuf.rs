@@ -4,7 +4,10 @@ use std::fs::File;
 use std::io::prelude::*;
 use std::cell::RefCell;
 
-use crate::models::pb::{PbDataStorage, PbDataBatch};
+use std::collections::HashMap;
+
+use crate::models::pb::{PbDataStorage, PbDataBatch, PbNamedVec};
+use crate::util::DataVec;
 
 use prost::Message;
 use ndarray::Array;
@@ -38,12 +41,23 @@ impl ProtobufDataLoader {
             let inp_vec = std::mem::replace(&mut i.input, Vec::new());
             let expected_vec = std::mem::replace(&mut i.expected, Vec::new());
 
+            let mask_vec = std::mem::take(&mut i.mask);
+
             let input = Array::from_shape_vec(inp_vec.len(), inp_vec)?;
             let expected = Array::from_shape_vec(expected_vec.len(), expected_vec)?;
 
+            let mut mask = None;
+            if !mask_vec.is_empty() {
+                mask = Some(Array::from_shape_vec(mask_vec.len(), mask_vec)?);
+            }
+
             dl.data.push(LabeledEntry{ 
                 input,
                 expected,
+                weight: i.weight,
+                mask,
+                named_inputs: named_from_pb(&mut i.named_inputs),
+                named_expected: named_from_pb(&mut i.named_expected),
             });
         }
 
@@ -59,9 +73,15 @@ impl ProtobufDataLoader {
             let inp_vec = inp_bor.to_vec();
             let out_vec = i.expected.to_vec();
 
+            let mask_vec = i.mask.as_ref().map(|m| m.to_vec()).unwrap_or_default();
+
             pb_data.data.push(PbDataBatch{
                 input: inp_vec,
-                expected: out_vec
+                expected: out_vec,
+                mask: mask_vec,
+                weight: i.weight,
+                named_inputs: named_to_pb(&i.named_inputs),
+                named_expected: named_to_pb(&i.named_expected),
             });
         }
 
@@ -73,6 +93,23 @@ impl ProtobufDataLoader {
     }
 }
 
+fn named_from_pb(pb_named: &mut Vec<PbNamedVec>) -> HashMap<String, DataVec> {
+    pb_named
+        .drain(..)
+        .map(|n| (n.name, Array::from_vec(n.vals)))
+        .collect()
+}
+
+fn named_to_pb(named: &HashMap<String, DataVec>) -> Vec<PbNamedVec> {
+    named
+        .iter()
+        .map(|(name, vals)| PbNamedVec {
+            name: name.clone(),
+            vals: vals.to_vec(),
+        })
+        .collect()
+}
+
 impl DataLoader for ProtobufDataLoader {
     fn next(&self) -> &LabeledEntry {
         assert!(self.data.len() > 0);
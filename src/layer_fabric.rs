@@ -1,11 +1,28 @@
 use std::collections::HashMap;
 
-use log::warn;
+use log::{error, warn};
 
-use crate::util::*;
 use crate::layers::*;
 use crate::util::*;
 
+/// Activation of the layer config or the default one, when not set.
+/// Unknown activation is logged and results in None
+fn activation_from_cfg(
+    cfg: Option<&HashMap<String, Variant>>,
+    default: Activation,
+) -> Option<Activation> {
+    match cfg {
+        Some(cfg_val) if cfg_val.contains_key("activation") => match Activation::from_cfg(cfg_val) {
+            Ok(activation) => Some(activation),
+            Err(e) => {
+                error!("{}", e);
+                None
+            }
+        },
+        _ => Some(default),
+    }
+}
+
 /// Fabric used to create neural network layers, when deserialing and other cases
 pub fn create_layer(
     layer_type: &str,
@@ -13,56 +30,205 @@ pub fn create_layer(
 ) -> Option<Box<dyn AbstractLayer>> {
     match layer_type {
         "EuclideanLossLayer" => {
+            let activation = activation_from_cfg(cfg, Activation::Raw)?;
+            let mut l = Box::new(EuclideanLossLayer::new(0, activation));
+            if let Some(cfg_val) = cfg {
+                l.set_cfg(cfg_val);
+            }
+            return Some(l);
+        }
+        "HuberLossLayer" => {
+            let activation = activation_from_cfg(cfg, Activation::Raw)?;
+            let mut l = Box::new(HuberLossLayer::new(0, activation));
             if let Some(cfg_val) = cfg {
-                let mut l: Box<dyn AbstractLayer>;
-                let activation = cfg_val.get("activation").unwrap();
-
-                if let Variant::String(activation) = activation {
-                    if activation == "sigmoid" {
-                        l = Box::new(layers_macros::sigmoid_error_layer!());
-                    } else if activation == "tanh" {
-                        l = Box::new(layers_macros::tanh_error_layer!());
-                    } else {
-                        l = Box::new(layers_macros::raw_error_layer!());
-                    }
-                } else {
-                    l = Box::new(layers_macros::raw_error_layer!());
-                }
-
                 l.set_cfg(cfg_val);
-
-                return Some(l);
-            } else {
-                let l = Box::new(layers_macros::raw_error_layer!());
-                return Some(l);
             }
+            return Some(l);
+        }
+        "L1LossLayer" => {
+            let activation = activation_from_cfg(cfg, Activation::Raw)?;
+            let mut l = Box::new(L1LossLayer::new(0, activation));
+            if let Some(cfg_val) = cfg {
+                l.set_cfg(cfg_val);
+            }
+            return Some(l);
         }
         "FcLayer" => {
+            let activation = activation_from_cfg(cfg, Activation::Sigmoid)?;
+            let mut l = Box::new(FcLayer::new(0, activation));
             if let Some(cfg_val) = cfg {
-                let mut l: Box<dyn AbstractLayer>;
-                let activation = cfg_val.get("activation").unwrap(); // TODO : handle unwrap()
-
-                if let Variant::String(activation) = activation {
-                    if activation == "sigmoid" {
-                        l = Box::new(layers_macros::sigmoid_fc_layer!());
-                    } else if activation == "tanh" {
-                        l = Box::new(layers_macros::tanh_fc_layer!());
-                    } else if activation == "relu" {
-                        l = Box::new(layers_macros::relu_fc_layer!());
-                    } else if activation == "leaky_relu" {
-                        l = Box::new(layers_macros::leaky_relu_fc_layer!());
-                    } else {
-                        l = Box::new(layers_macros::raw_rc_layer!());
-                    }
-                } else {
-                    l = Box::new(layers_macros::raw_rc_layer!());
+                l.set_cfg(cfg_val);
+            }
+            return Some(l);
+        }
+        "Conv1DLayer" => {
+            let activation = activation_from_cfg(cfg, Activation::ReLU)?;
+            let mut l = Box::new(Conv1DLayer::new(0, 3, activation));
+            if let Some(cfg_val) = cfg {
+                l.set_cfg(cfg_val);
+            }
+            return Some(l);
+        }
+        "Conv2DLayer" => {
+            let activation = activation_from_cfg(cfg, Activation::ReLU)?;
+            let mut l = Box::new(Conv2DLayer::new(0, 3, activation));
+            if let Some(cfg_val) = cfg {
+                l.set_cfg(cfg_val);
+            }
+            return Some(l);
+        }
+        "ConvTranspose2DLayer" => {
+            let activation = activation_from_cfg(cfg, Activation::ReLU)?;
+            let mut l = Box::new(ConvTranspose2DLayer::new(0, 3, activation));
+            if let Some(cfg_val) = cfg {
+                l.set_cfg(cfg_val);
+            }
+            return Some(l);
+        }
+        "Upsample2DLayer" => {
+            let mut l = Box::new(Upsample2DLayer::new(2));
+            if let Some(cfg_val) = cfg {
+                l.set_cfg(cfg_val);
+            }
+            return Some(l);
+        }
+        "MaxPool2DLayer" => {
+            let mut l = Box::new(MaxPool2DLayer::new(2));
+            if let Some(cfg_val) = cfg {
+                l.set_cfg(cfg_val);
+            }
+            return Some(l);
+        }
+        "AvgPool2DLayer" => {
+            let mut l = Box::new(AvgPool2DLayer::new(2));
+            if let Some(cfg_val) = cfg {
+                l.set_cfg(cfg_val);
+            }
+            return Some(l);
+        }
+        "GlobalAvgPoolLayer" => {
+            let l = Box::new(GlobalAvgPoolLayer::new());
+            return Some(l);
+        }
+        "BatchNormLayer" => {
+            let mut l = Box::new(BatchNormLayer::new());
+            if let Some(cfg_val) = cfg {
+                l.set_cfg(cfg_val);
+            }
+            return Some(l);
+        }
+        "LayerNormLayer" => {
+            let mut l = Box::new(LayerNormLayer::new());
+            if let Some(cfg_val) = cfg {
+                l.set_cfg(cfg_val);
+            }
+            return Some(l);
+        }
+        "ActivationLayer" => {
+            let mut l = Box::new(ActivationLayer::default());
+            if let Some(cfg_val) = cfg {
+                if let Err(e) = LayerActivation::from_cfg(cfg_val) {
+                    error!("{}", e);
+                    return None;
                 }
-
                 l.set_cfg(cfg_val);
-                return Some(l);
             }
-
-            let l = Box::new(layers_macros::sigmoid_fc_layer!());
+            return Some(l);
+        }
+        "PReluLayer" => {
+            let mut l = Box::new(PReluLayer::new());
+            if let Some(cfg_val) = cfg {
+                l.set_cfg(cfg_val);
+            }
+            return Some(l);
+        }
+        "DropoutLayer" => {
+            let mut l = Box::new(DropoutLayer::new(0.5));
+            if let Some(cfg_val) = cfg {
+                l.set_cfg(cfg_val);
+            }
+            return Some(l);
+        }
+        "EmbeddingLayer" => {
+            let mut l = Box::new(EmbeddingLayer::new(0, 0));
+            if let Some(cfg_val) = cfg {
+                l.set_cfg(cfg_val);
+            }
+            return Some(l);
+        }
+        "RnnLayer" => {
+            let mut l = Box::new(RnnLayer::new(0));
+            if let Some(cfg_val) = cfg {
+                l.set_cfg(cfg_val);
+            }
+            return Some(l);
+        }
+        "LstmLayer" => {
+            let mut l = Box::new(LstmLayer::new(0));
+            if let Some(cfg_val) = cfg {
+                l.set_cfg(cfg_val);
+            }
+            return Some(l);
+        }
+        "GruLayer" => {
+            let mut l = Box::new(GruLayer::new(0));
+            if let Some(cfg_val) = cfg {
+                l.set_cfg(cfg_val);
+            }
+            return Some(l);
+        }
+        "Bidirectional" => {
+            let mut l = Box::new(Bidirectional::new(Box::new(LstmLayer::new(0))));
+            if let Some(cfg_val) = cfg {
+                l.set_cfg(cfg_val);
+            }
+            return Some(l);
+        }
+        "MultiHeadAttentionLayer" => {
+            let mut l = Box::new(MultiHeadAttentionLayer::new(1, 0));
+            if let Some(cfg_val) = cfg {
+                l.set_cfg(cfg_val);
+            }
+            return Some(l);
+        }
+        "TransformerEncoderLayer" => {
+            let mut l = Box::new(TransformerEncoderLayer::new(1, 0, 0));
+            if let Some(cfg_val) = cfg {
+                l.set_cfg(cfg_val);
+            }
+            return Some(l);
+        }
+        "AddLayer" => {
+            let l = Box::new(AddLayer::new());
+            return Some(l);
+        }
+        "ConcatLayer" => {
+            let l = Box::new(ConcatLayer::new());
+            return Some(l);
+        }
+        "FlattenLayer" => {
+            let l = Box::new(FlattenLayer::new());
+            return Some(l);
+        }
+        "ReshapeLayer" => {
+            let mut l = Box::new(ReshapeLayer::new(&[-1]));
+            if let Some(cfg_val) = cfg {
+                l.set_cfg(cfg_val);
+            }
+            return Some(l);
+        }
+        "GaussianNoiseLayer" => {
+            let mut l = Box::new(GaussianNoiseLayer::new(0.0));
+            if let Some(cfg_val) = cfg {
+                l.set_cfg(cfg_val);
+            }
+            return Some(l);
+        }
+        "BceLossLayer" => {
+            let mut l = Box::new(BceLossLayer::new(0));
+            if let Some(cfg_val) = cfg {
+                l.set_cfg(cfg_val);
+            }
             return Some(l);
         }
         "InputLayer" => {
@@ -104,14 +270,18 @@ pub fn create_layer_ocl(
             return Some(l);
         },
         "FcLayerOcl" => {
+            let activation = activation_from_cfg(cfg, OclActivationFunc::Sigmoid)?;
             let mut l = Box::new(FcLayerOcl::default());
+            l.set_activation_function(activation);
             if let Some(cfg_val) = cfg {
                 l.set_cfg(cfg_val);
             }
             return Some(l);
         },
         "EuclideanLossLayerOcl" => {
+            let activation = activation_from_cfg(cfg, OclActivationFunc::Raw)?;
             let mut l = Box::new(EuclideanLossLayerOcl::default());
+            l.set_activation_function(activation);
             if let Some(cfg_val) = cfg {
                 l.set_cfg(cfg_val);
             }
@@ -128,62 +298,3 @@ pub fn create_layer_ocl(
         _ => {return None;}
     }
 }
-
-pub mod layers_macros {
-    macro_rules! sigmoid_fc_layer {
-        (  ) => {{
-            FcLayer::new(0, activation_macros::sigmoid_activation!())
-        }};
-    }
-
-    macro_rules! tanh_fc_layer {
-        (  ) => {{
-            FcLayer::new(0, activation_macros::tanh_activation!())
-        }};
-    }
-
-    macro_rules! relu_fc_layer {
-        () => {
-            FcLayer::new(0, activation_macros::relu_activation!())
-        };
-    }
-
-    macro_rules! leaky_relu_fc_layer {
-        () => {
-            FcLayer::new(0, activation_macros::leaky_relu_activation!())
-        };
-    }
-
-    macro_rules! raw_rc_layer {
-        () => {
-            FcLayer::new(0, activation_macros::raw_activation!())
-        };
-    }
-
-    macro_rules! sigmoid_error_layer {
-        () => {
-            EuclideanLossLayer::new(0, activation_macros::sigmoid_activation!())
-        };
-    }
-
-    macro_rules! tanh_error_layer {
-        () => {
-            EuclideanLossLayer::new(0, activation_macros::tanh_activation!())
-        };
-    }
-
-    macro_rules! raw_error_layer {
-        () => {
-            EuclideanLossLayer::new(0, activation_macros::raw_activation!())
-        };
-    }
-
-    pub(crate) use leaky_relu_fc_layer;
-    pub(crate) use raw_error_layer;
-    pub(crate) use raw_rc_layer;
-    pub(crate) use relu_fc_layer;
-    pub(crate) use sigmoid_error_layer;
-    pub(crate) use sigmoid_fc_layer;
-    pub(crate) use tanh_error_layer;
-    pub(crate) use tanh_fc_layer;
-}
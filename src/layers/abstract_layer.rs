@@ -1,7 +1,9 @@
 use std::fmt;
 
-use crate::cpu_params::{CpuParams, ParamsBlob, TypeBuffer};
-use crate::util::{Array2D, Metrics, WithParams};
+use log::error;
+
+use crate::cpu_params::{CpuParams, ParamsBlob, TypeBuffer, VariantParamArc};
+use crate::util::{Array2D, LossWeights, Metrics, WithParams};
 
 #[derive(Debug)]
 pub enum LayerError {
@@ -46,10 +48,12 @@ pub trait AbstractLayer: WithParams {
         Err(LayerError::NotImpl)
     }
 
+    /// Loss layers multiply loss values and gradients by loss_weights factors
     fn backward_output(
         &mut self,
         _prev_input: ParamsBlob,
         _expected: Array2D,
+        _loss_weights: &LossWeights,
     ) -> LayerBackwardResult {
         Err(LayerError::NotImpl)
     }
@@ -58,15 +62,47 @@ pub trait AbstractLayer: WithParams {
 
     fn size(&self) -> usize;
 
+    /// Shape of a single output sample, for example [channels, height, width].
+    /// Output buffer always stores it flattened in a row per batch sample
+    fn output_shape(&self) -> Vec<usize> {
+        vec![self.size()]
+    }
+
     fn set_batch_size(&mut self, batch_size: usize) {
         let mut lr = self.cpu_params().unwrap();
         lr.fit_to_batch_size(batch_size);
     }
 
+    /// Switches between train and eval behaviour for layers like batch normalization.
+    /// Layers are in train mode by default
+    fn set_train_mode(&mut self, _is_train: bool) {}
+
     fn metrics(&self) -> Option<&Metrics> {
         None
     }
 
+    /// Frozen (not trainable) layer is skipped by optimizers and doesn't compute
+    /// gradients of its weights, but still passes gradient to previous layers.
+    /// Layers with weights store it in "trainable" cfg key, layers without weights
+    /// (input, activation, pooling, reshaping, merge and etc.) neither write nor read the key
+    fn is_trainable(&self) -> bool {
+        true
+    }
+
+    fn set_trainable(&mut self, _trainable: bool) {}
+
+    /// Estimated FLOPs of forward pass for a single sample. By default it is a multiply-add
+    /// per trainable parameter and an operation per output value,
+    /// layers which reuse weights (convolutions, recurrent and etc.) override it
+    fn forward_flops(&self) -> usize {
+        let weights = match self.cpu_params() {
+            Some(lp) => params_count(&lp, self.trainable_bufs().0),
+            None => 0,
+        };
+
+        2 * weights + self.size()
+    }
+
     fn serializable_bufs(&self) -> &[i32] {
         return &[TypeBuffer::Weights as i32, TypeBuffer::Bias as i32];
     }
@@ -83,9 +119,57 @@ pub trait AbstractLayer: WithParams {
 
     fn set_input_shape(&mut self, sh: &[usize]);
 
+    /// Shapes of all inputs, layers with several inputs (merge layers of the graph model)
+    /// override it. Such layers return gradient params for each input from backward()
+    fn set_input_shapes(&mut self, shapes: &[Vec<usize>]) {
+        self.set_input_shape(&shapes[0]);
+    }
+
     // Do copy layer memory(ws, output, ...)
     fn copy_layer(&self) -> Box<dyn AbstractLayer>;
 
     // Do copy only Rc
     fn clone_layer(&self) -> Box<dyn AbstractLayer>;
 }
+
+/// Returns gradient with respect to the output of the layer, that precedes next layer.
+/// If next layer provides InputGrad buffer, it is used,
+/// otherwise next layer is considered as fully-connected and gradient is calculated through its weights
+pub fn next_layer_grad(next_params: &CpuParams) -> Array2D {
+    if next_params.contains_buf_t(TypeBuffer::InputGrad) {
+        return next_params
+            .get_2d_buf_t(TypeBuffer::InputGrad)
+            .borrow()
+            .clone();
+    }
+
+    let next_err_vals = next_params.get_2d_buf_t(TypeBuffer::NeuGrad);
+    let next_err_vals = next_err_vals.borrow();
+
+    let next_ws = next_params.get_2d_buf_t(TypeBuffer::Weights);
+    let next_ws = next_ws.borrow();
+
+    next_err_vals.dot(&*next_ws)
+}
+
+/// Returns error if the layer buffers aren't allocated,
+/// which happens when the layer couldn't be applied to the input shape
+pub fn ensure_initialized(params: &CpuParams, layer_type: &str) -> Result<(), LayerError> {
+    if !params.contains_buf_t(TypeBuffer::Output) {
+        error!("{} isn't initialized, check its input shape and config", layer_type);
+        return Err(LayerError::InvalidSize);
+    }
+
+    Ok(())
+}
+
+/// Returns total number of values in the given buffers, missing buffers are skipped
+pub fn params_count(params: &CpuParams, ids: &[i32]) -> usize {
+    ids.iter()
+        .filter(|id| params.contains_buf(**id))
+        .map(|id| match params.get_param(*id) {
+            VariantParamArc::Array1(arr) => arr.borrow().len(),
+            VariantParamArc::Array2(arr) => arr.borrow().len(),
+        })
+        .sum()
+}
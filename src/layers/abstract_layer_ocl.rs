@@ -31,6 +31,7 @@ pub trait AbstractLayerOcl: AbstractLayer {
         &mut self,
         _prev_input: OclParamsBlob,
         _expected: Array2D,
+        _loss_weights: &LossWeights,
     ) -> LayerOclResult {
         Err(LayerError::NotImpl)
     }
@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+
+use ndarray::Zip;
+
+use log::{debug, error};
+
+use super::abstract_layer::{
+    next_layer_grad, AbstractLayer, LayerBackwardResult, LayerError, LayerForwardResult,
+    TrainableBufsIds,
+};
+use crate::cpu_params::*;
+use crate::util::*;
+
+/// Function applied by ActivationLayer.
+/// Softmax and log-softmax are taken over the whole sample row
+#[derive(Clone, Debug, PartialEq)]
+pub enum LayerActivation {
+    Elementwise(Activation),
+    Softmax,
+    LogSoftmax,
+}
+
+impl LayerActivation {
+    pub fn name(&self) -> &str {
+        match self {
+            LayerActivation::Elementwise(act) => act.name(),
+            LayerActivation::Softmax => "softmax",
+            LayerActivation::LogSoftmax => "log_softmax",
+        }
+    }
+
+    /// Same config keys as layers with built-in activation, plus "softmax" and "log_softmax"
+    pub fn from_cfg(cfg: &HashMap<String, Variant>) -> Result<Self, String> {
+        match cfg.get("activation") {
+            Some(Variant::String(name)) if name == "softmax" => Ok(LayerActivation::Softmax),
+            Some(Variant::String(name)) if name == "log_softmax" => Ok(LayerActivation::LogSoftmax),
+            _ => Ok(LayerActivation::Elementwise(Activation::from_cfg(cfg)?)),
+        }
+    }
+
+    pub fn write_cfg(&self, cfg: &mut HashMap<String, Variant>) {
+        match self {
+            LayerActivation::Elementwise(act) => act.write_cfg(cfg),
+            _ => {
+                cfg.insert(
+                    "activation".to_owned(),
+                    Variant::String(self.name().to_owned()),
+                );
+            }
+        }
+    }
+}
+
+impl From<Activation> for LayerActivation {
+    fn from(act: Activation) -> Self {
+        LayerActivation::Elementwise(act)
+    }
+}
+
+/// Applies an activation function to the previous layer output, has no weights
+#[derive(Clone)]
+pub struct ActivationLayer {
+    pub lr_params: CpuParams,
+    shape: Vec<usize>,
+    size: usize,
+    activation: LayerActivation,
+}
+
+impl ActivationLayer {
+    pub fn new(activation: Activation) -> Self {
+        Self::with_activation(LayerActivation::Elementwise(activation))
+    }
+
+    pub fn softmax() -> Self {
+        Self::with_activation(LayerActivation::Softmax)
+    }
+
+    pub fn log_softmax() -> Self {
+        Self::with_activation(LayerActivation::LogSoftmax)
+    }
+
+    pub fn with_activation(activation: LayerActivation) -> Self {
+        Self {
+            lr_params: CpuParams::empty(),
+            shape: vec![0],
+            size: 0,
+            activation,
+        }
+    }
+
+    pub fn new_box(activation: Activation) -> Box<Self> {
+        Box::new(ActivationLayer::new(activation))
+    }
+
+    pub fn activation(&self) -> &LayerActivation {
+        &self.activation
+    }
+}
+
+impl AbstractLayer for ActivationLayer {
+    fn forward(&mut self, input: ParamsBlob) -> LayerForwardResult {
+        let inp_m = input[0].get_2d_buf_t(TypeBuffer::Output);
+        let inp_m = inp_m.borrow();
+        let inp_m = inp_m.deref();
+
+        if inp_m.ncols() != self.size {
+            error!(
+                "Invalid input size for ActivationLayer : {}, expected : {}",
+                inp_m.ncols(),
+                self.size
+            );
+            return Err(LayerError::InvalidSize);
+        }
+
+        let out_m = self.lr_params.get_2d_buf_t(TypeBuffer::Output);
+        let mut out_m = out_m.borrow_mut();
+        let out_m = out_m.deref_mut();
+
+        out_m.assign(inp_m);
+
+        match &self.activation {
+            LayerActivation::Elementwise(act) => {
+                out_m.par_mapv_inplace(|v| act.func(v));
+            }
+            LayerActivation::Softmax => array_helpers::softmax_rows(out_m),
+            LayerActivation::LogSoftmax => {
+                for mut row in out_m.rows_mut() {
+                    let max = row.fold(f32::NEG_INFINITY, |acc, v| acc.max(*v));
+                    let log_sum = row.fold(0.0, |acc, v| acc + (v - max).exp()).ln() + max;
+                    row.mapv_inplace(|v| v - log_sum);
+                }
+            }
+        }
+
+        debug!("[ok] ActivationLayer forward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn backward(&mut self, prev_input: ParamsBlob, next_input: ParamsBlob) -> LayerBackwardResult {
+        let next_grad = next_layer_grad(&next_input[0]);
+
+        let prev_input = prev_input[0].get_2d_buf_t(TypeBuffer::Output);
+        let prev_input = prev_input.borrow();
+        let prev_input = prev_input.deref();
+
+        let out_m = self.lr_params.get_2d_buf_t(TypeBuffer::Output);
+        let out_m = out_m.borrow();
+        let out_m = out_m.deref();
+
+        let inp_grad = self.lr_params.get_2d_buf_t(TypeBuffer::InputGrad);
+        let mut inp_grad = inp_grad.borrow_mut();
+        let inp_grad = inp_grad.deref_mut();
+
+        match &self.activation {
+            LayerActivation::Elementwise(act) => {
+                Zip::from(inp_grad)
+                    .and(&next_grad)
+                    .and(prev_input)
+                    .par_for_each(|inp_grad_el, grad_el, inp_el| {
+                        *inp_grad_el = grad_el * act.func_deriv(*inp_el);
+                    });
+            }
+            LayerActivation::Softmax => {
+                // dx = y * (g - sum(g * y))
+                Zip::from(inp_grad.rows_mut())
+                    .and(next_grad.rows())
+                    .and(out_m.rows())
+                    .par_for_each(|mut inp_grad_r, grad_r, out_r| {
+                        let dot = grad_r.dot(&out_r);
+                        Zip::from(&mut inp_grad_r).and(grad_r).and(out_r).for_each(
+                            |inp_grad_el, grad_el, out_el| {
+                                *inp_grad_el = out_el * (grad_el - dot);
+                            },
+                        );
+                    });
+            }
+            LayerActivation::LogSoftmax => {
+                // dx = g - softmax * sum(g)
+                Zip::from(inp_grad.rows_mut())
+                    .and(next_grad.rows())
+                    .and(out_m.rows())
+                    .par_for_each(|mut inp_grad_r, grad_r, out_r| {
+                        let sum = grad_r.sum();
+                        Zip::from(&mut inp_grad_r).and(grad_r).and(out_r).for_each(
+                            |inp_grad_el, grad_el, out_el| {
+                                *inp_grad_el = grad_el - out_el.exp() * sum;
+                            },
+                        );
+                    });
+            }
+        }
+
+        debug!("[ok] ActivationLayer backward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn cpu_params(&self) -> Option<CpuParams> {
+        Some(self.lr_params.clone())
+    }
+
+    fn set_cpu_params(&mut self, lp: CpuParams) {
+        self.lr_params = lp;
+    }
+
+    fn layer_type(&self) -> &str {
+        "ActivationLayer"
+    }
+
+    fn trainable_bufs(&self) -> TrainableBufsIds {
+        (&[], &[])
+    }
+
+    fn serializable_bufs(&self) -> &[i32] {
+        &[]
+    }
+
+    fn set_input_shape(&mut self, sh: &[usize]) {
+        self.shape = sh.to_vec();
+        self.size = sh.iter().product();
+
+        self.lr_params = CpuParams::new_only_output(self.size);
+        self.lr_params.add_input_grad(self.size);
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        self.shape.clone()
+    }
+
+    fn copy_layer(&self) -> Box<dyn AbstractLayer> {
+        let mut copy_l = self.clone();
+        copy_l.set_cpu_params(self.lr_params.copy());
+        Box::new(copy_l)
+    }
+
+    fn clone_layer(&self) -> Box<dyn AbstractLayer> {
+        Box::new(self.clone())
+    }
+}
+
+impl Default for ActivationLayer {
+    fn default() -> Self {
+        Self::new(Activation::Raw)
+    }
+}
+
+impl WithParams for ActivationLayer {
+    fn cfg(&self) -> HashMap<String, Variant> {
+        let mut cfg: HashMap<String, Variant> = HashMap::new();
+
+        self.activation.write_cfg(&mut cfg);
+
+        cfg
+    }
+
+    fn set_cfg(&mut self, cfg: &HashMap<String, Variant>) {
+        if cfg.contains_key("activation") {
+            match LayerActivation::from_cfg(cfg) {
+                Ok(a) => self.activation = a,
+                Err(e) => error!("[ActivationLayer] {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::grad_check::*;
+
+    #[test]
+    fn softmax_grads_match_numeric() {
+        let mut l = ActivationLayer::softmax();
+        check_layer(&mut l, &[4], test_values(2, 4, 1));
+    }
+}
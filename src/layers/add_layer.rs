@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::ops::DerefMut;
+
+use log::{debug, error};
+
+use super::abstract_layer::{
+    next_layer_grad, AbstractLayer, LayerBackwardResult, LayerError, LayerForwardResult,
+    TrainableBufsIds,
+};
+use crate::cpu_params::*;
+use crate::util::*;
+
+/// Element-wise sum of inputs with the same size, merge node of the graph model.
+/// Each input receives the whole output gradient
+#[derive(Clone)]
+pub struct AddLayer {
+    pub lr_params: CpuParams,
+    shape: Vec<usize>,
+    size: usize,
+}
+
+impl AddLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn new_box() -> Box<Self> {
+        Box::new(AddLayer::new())
+    }
+}
+
+impl AbstractLayer for AddLayer {
+    fn forward(&mut self, input: ParamsBlob) -> LayerForwardResult {
+        let out_m = self.lr_params.get_2d_buf_t(TypeBuffer::Output);
+        let mut out_m = out_m.borrow_mut();
+        let out_m = out_m.deref_mut();
+
+        out_m.fill(0.0);
+
+        for inp in input.iter() {
+            let inp_m = inp.get_2d_buf_t(TypeBuffer::Output);
+            let inp_m = inp_m.borrow();
+
+            if inp_m.dim() != out_m.dim() {
+                error!(
+                    "Invalid input size for AddLayer : {:?}, expected : {:?}",
+                    inp_m.dim(),
+                    out_m.dim()
+                );
+                return Err(LayerError::InvalidSize);
+            }
+
+            *out_m += &*inp_m;
+        }
+
+        debug!("[ok] AddLayer forward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn backward(&mut self, _prev_input: ParamsBlob, next_input: ParamsBlob) -> LayerBackwardResult {
+        let next_grad = next_layer_grad(&next_input[0]);
+
+        let inp_grad = self.lr_params.get_2d_buf_t(TypeBuffer::InputGrad);
+        let mut inp_grad = inp_grad.borrow_mut();
+
+        inp_grad.assign(&next_grad);
+
+        debug!("[ok] AddLayer backward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn cpu_params(&self) -> Option<CpuParams> {
+        Some(self.lr_params.clone())
+    }
+
+    fn set_cpu_params(&mut self, lp: CpuParams) {
+        self.lr_params = lp;
+    }
+
+    fn layer_type(&self) -> &str {
+        "AddLayer"
+    }
+
+    fn trainable_bufs(&self) -> TrainableBufsIds {
+        (&[], &[])
+    }
+
+    fn serializable_bufs(&self) -> &[i32] {
+        &[]
+    }
+
+    fn set_input_shape(&mut self, sh: &[usize]) {
+        self.shape = sh.to_vec();
+        self.size = sh.iter().product();
+
+        self.lr_params = CpuParams::new_only_output(self.size);
+        self.lr_params.add_input_grad(self.size);
+    }
+
+    fn set_input_shapes(&mut self, shapes: &[Vec<usize>]) {
+        for sh in shapes.iter().skip(1) {
+            if sh.iter().product::<usize>() != shapes[0].iter().product::<usize>() {
+                error!(
+                    "AddLayer inputs have different shapes : {:?} and {:?}",
+                    shapes[0], sh
+                );
+            }
+        }
+
+        self.set_input_shape(&shapes[0]);
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        self.shape.clone()
+    }
+
+    fn copy_layer(&self) -> Box<dyn AbstractLayer> {
+        let mut copy_l = self.clone();
+        copy_l.set_cpu_params(self.lr_params.copy());
+        Box::new(copy_l)
+    }
+
+    fn clone_layer(&self) -> Box<dyn AbstractLayer> {
+        Box::new(self.clone())
+    }
+}
+
+impl Default for AddLayer {
+    fn default() -> Self {
+        Self {
+            lr_params: CpuParams::empty(),
+            shape: vec![0],
+            size: 0,
+        }
+    }
+}
+
+impl WithParams for AddLayer {
+    fn cfg(&self) -> HashMap<String, Variant> {
+        HashMap::new()
+    }
+
+    fn set_cfg(&mut self, _cfg: &HashMap<String, Variant>) {}
+}
@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+
+use ndarray::Zip;
+
+use log::{debug, error};
+
+use super::abstract_layer::{
+    ensure_initialized, next_layer_grad, AbstractLayer, LayerBackwardResult, LayerError,
+    LayerForwardResult, TrainableBufsIds,
+};
+use crate::cpu_params::*;
+use crate::util::conv_helpers::ConvGeometry;
+use crate::util::*;
+
+/// 2D average pooling over (channels, height, width) input.
+/// Padded positions aren't counted into average
+#[derive(Clone, Default)]
+pub struct AvgPool2DLayer {
+    pub lr_params: CpuParams,
+    kernel_size: usize,
+    stride: usize,
+    padding: usize,
+    geometry: ConvGeometry,
+}
+
+impl AbstractLayer for AvgPool2DLayer {
+    fn forward(&mut self, input: ParamsBlob) -> LayerForwardResult {
+        ensure_initialized(&self.lr_params, "AvgPool2DLayer")?;
+
+        let inp_m = input[0].get_2d_buf_t(TypeBuffer::Output);
+        let inp_m = inp_m.borrow();
+        let inp_m = inp_m.deref();
+
+        if inp_m.ncols() != self.geometry.input_len() {
+            error!(
+                "Invalid input size for AvgPool2DLayer : {}, expected : {}",
+                inp_m.ncols(),
+                self.geometry.input_len()
+            );
+            return Err(LayerError::InvalidSize);
+        }
+
+        let out_m = self.lr_params.get_2d_buf_t(TypeBuffer::Output);
+        let mut out_m = out_m.borrow_mut();
+        let out_m = out_m.deref_mut();
+
+        let g = &self.geometry;
+        let (out_h, out_w) = (g.out_height(), g.out_width());
+
+        Zip::from(inp_m.rows())
+            .and(out_m.rows_mut())
+            .par_for_each(|inp_r, mut out_r| {
+                // for each batch
+                for c in 0..g.channels {
+                    for oy in 0..out_h {
+                        for ox in 0..out_w {
+                            let (mut sum, mut cnt) = (0.0, 0);
+
+                            for ki in 0..g.kernel_h {
+                                for kj in 0..g.kernel_w {
+                                    if let Some(idx) = g.input_idx(c, ki, kj, oy, ox) {
+                                        sum += inp_r[idx];
+                                        cnt += 1;
+                                    }
+                                }
+                            }
+
+                            out_r[(c * out_h + oy) * out_w + ox] = sum / cnt as f32;
+                        }
+                    }
+                }
+            });
+
+        debug!("[ok] AvgPool2DLayer forward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn backward(&mut self, _prev_input: ParamsBlob, next_input: ParamsBlob) -> LayerBackwardResult {
+        ensure_initialized(&self.lr_params, "AvgPool2DLayer")?;
+
+        let next_grad = next_layer_grad(&next_input[0]);
+
+        let inp_grad = self.lr_params.get_2d_buf_t(TypeBuffer::InputGrad);
+        let mut inp_grad = inp_grad.borrow_mut();
+        let inp_grad = inp_grad.deref_mut();
+
+        let g = &self.geometry;
+        let (out_h, out_w) = (g.out_height(), g.out_width());
+
+        Zip::from(inp_grad.rows_mut())
+            .and(next_grad.rows())
+            .par_for_each(|mut inp_grad_r, next_grad_r| {
+                inp_grad_r.fill(0.0);
+
+                for c in 0..g.channels {
+                    for oy in 0..out_h {
+                        for ox in 0..out_w {
+                            let grad = next_grad_r[(c * out_h + oy) * out_w + ox];
+                            let idxs: Vec<usize> = (0..g.kernel_h * g.kernel_w)
+                                .filter_map(|k| {
+                                    g.input_idx(c, k / g.kernel_w, k % g.kernel_w, oy, ox)
+                                })
+                                .collect();
+
+                            for idx in idxs.iter() {
+                                inp_grad_r[*idx] += grad / idxs.len() as f32;
+                            }
+                        }
+                    }
+                }
+            });
+
+        debug!("[ok] AvgPool2DLayer backward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn cpu_params(&self) -> Option<CpuParams> {
+        Some(self.lr_params.clone())
+    }
+
+    fn set_cpu_params(&mut self, lp: CpuParams) {
+        self.lr_params = lp;
+    }
+
+    fn layer_type(&self) -> &str {
+        "AvgPool2DLayer"
+    }
+
+    fn trainable_bufs(&self) -> TrainableBufsIds {
+        (&[], &[])
+    }
+
+    fn serializable_bufs(&self) -> &[i32] {
+        &[]
+    }
+
+    /// Accepts only [channels, height, width] shape
+    fn set_input_shape(&mut self, sh: &[usize]) {
+        if sh.len() != 3 {
+            error!("AvgPool2DLayer requires [channels, height, width] input shape, got {:?}", sh);
+            return;
+        }
+
+        let geometry = ConvGeometry::new_square(
+            sh[0],
+            sh[1],
+            sh[2],
+            self.kernel_size,
+            self.stride,
+            self.padding,
+        );
+
+        if !geometry.is_valid() {
+            error!(
+                "AvgPool2DLayer couldn't be applied to input shape {:?} with kernel {}",
+                sh, self.kernel_size
+            );
+            return;
+        }
+
+        self.geometry = geometry;
+        self.lr_params = CpuParams::new_only_output(self.size());
+        self.lr_params.add_input_grad(geometry.input_len());
+    }
+
+    fn size(&self) -> usize {
+        if !self.geometry.is_valid() {
+            return 0;
+        }
+
+        self.geometry.channels * self.geometry.cols_cols()
+    }
+
+    fn forward_flops(&self) -> usize {
+        self.size() * self.geometry.kernel_h * self.geometry.kernel_w
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        if !self.geometry.is_valid() {
+            return vec![0];
+        }
+
+        vec![
+            self.geometry.channels,
+            self.geometry.out_height(),
+            self.geometry.out_width(),
+        ]
+    }
+
+    fn copy_layer(&self) -> Box<dyn AbstractLayer> {
+        let mut copy_l = self.clone();
+        copy_l.set_cpu_params(self.lr_params.copy());
+        Box::new(copy_l)
+    }
+
+    fn clone_layer(&self) -> Box<dyn AbstractLayer> {
+        Box::new(self.clone())
+    }
+}
+
+impl AvgPool2DLayer {
+    /// Stride equals to kernel size by default
+    pub fn new(kernel_size: usize) -> Self {
+        Self {
+            lr_params: CpuParams::empty(),
+            kernel_size,
+            stride: kernel_size,
+            padding: 0,
+            geometry: ConvGeometry::default(),
+        }
+    }
+
+    pub fn new_box(kernel_size: usize) -> Box<Self> {
+        Box::new(AvgPool2DLayer::new(kernel_size))
+    }
+
+    pub fn stride(mut self, stride: usize) -> Self {
+        self.stride = stride;
+        self
+    }
+
+    pub fn padding(mut self, padding: usize) -> Self {
+        self.padding = padding;
+        self
+    }
+}
+
+impl WithParams for AvgPool2DLayer {
+    fn cfg(&self) -> HashMap<String, Variant> {
+        let mut cfg: HashMap<String, Variant> = HashMap::new();
+
+        cfg.insert(
+            "kernel_size".to_owned(),
+            Variant::Int(self.kernel_size as i32),
+        );
+        cfg.insert("stride".to_owned(), Variant::Int(self.stride as i32));
+        cfg.insert("padding".to_owned(), Variant::Int(self.padding as i32));
+
+        cfg
+    }
+
+    fn set_cfg(&mut self, cfg: &HashMap<String, Variant>) {
+        if let Some(Variant::Int(kernel_size)) = cfg.get("kernel_size") {
+            self.kernel_size = *kernel_size as usize;
+            self.stride = self.kernel_size;
+        }
+
+        if let Some(Variant::Int(stride)) = cfg.get("stride") {
+            self.stride = *stride as usize;
+        }
+
+        if let Some(Variant::Int(padding)) = cfg.get("padding") {
+            self.padding = *padding as usize;
+        }
+
+        self.lr_params = CpuParams::empty();
+        self.geometry = ConvGeometry::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::grad_check::*;
+
+    #[test]
+    fn overlapping_padded_windows_grads_match_numeric() {
+        let mut l = AvgPool2DLayer::new(3).stride(2).padding(1);
+        check_layer(&mut l, &[2, 5, 5], test_values(2, 50, 1));
+    }
+}
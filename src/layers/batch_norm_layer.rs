@@ -0,0 +1,326 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use ndarray::{s, Zip};
+
+use log::{debug, error};
+
+use super::abstract_layer::{
+    next_layer_grad, AbstractLayer, LayerBackwardResult, LayerError, LayerForwardResult,
+    TrainableBufsIds,
+};
+use crate::cpu_params::*;
+use crate::util::*;
+
+/// Batch normalization layer.
+/// Gamma is stored as Weights and beta as Bias buffer.
+/// For [channels, height, width] input statistics are computed per channel,
+/// otherwise per each input value.
+/// Uses batch statistics in train mode and running statistics in eval mode
+#[derive(Clone)]
+pub struct BatchNormLayer {
+    pub lr_params: CpuParams,
+    shape: Vec<usize>,
+    features: usize,
+    spatial_size: usize, // values per feature in a single sample
+    momentum: f32,
+    eps: f32,
+    is_train: bool,
+    x_hat: Array2D,
+    inv_std: Array1D,
+    trainable: bool,
+}
+
+impl AbstractLayer for BatchNormLayer {
+    fn forward(&mut self, input: ParamsBlob) -> LayerForwardResult {
+        let inp_m = input[0].get_2d_buf_t(TypeBuffer::Output);
+        let inp_m = inp_m.borrow();
+        let inp_m = inp_m.deref();
+
+        if self.features == 0 || inp_m.ncols() != self.size() {
+            error!(
+                "Invalid input size for BatchNormLayer : {}, expected : {}",
+                inp_m.ncols(),
+                self.size()
+            );
+            return Err(LayerError::InvalidSize);
+        }
+
+        let out_m = self.lr_params.get_2d_buf_t(TypeBuffer::Output);
+        let mut out_m = out_m.borrow_mut();
+        let out_m = out_m.deref_mut();
+
+        let gamma = self.lr_params.get_1d_buf_t(TypeBuffer::Weights);
+        let gamma = gamma.borrow();
+
+        let beta = self.lr_params.get_1d_buf_t(TypeBuffer::Bias);
+        let beta = beta.borrow();
+
+        let run_mean = self.lr_params.get_1d_buf_t(TypeBuffer::RunningMean);
+        let mut run_mean = run_mean.borrow_mut();
+
+        let run_var = self.lr_params.get_1d_buf_t(TypeBuffer::RunningVar);
+        let mut run_var = run_var.borrow_mut();
+
+        if self.is_train && self.x_hat.dim() != inp_m.dim() {
+            self.x_hat = Array2D::zeros(inp_m.dim());
+        }
+
+        let sp = self.spatial_size;
+        let count = (inp_m.nrows() * sp) as f32;
+
+        for f in 0..self.features {
+            let inp_f = inp_m.slice(s![.., f * sp..(f + 1) * sp]);
+            let mut out_f = out_m.slice_mut(s![.., f * sp..(f + 1) * sp]);
+
+            let (mean, inv_std) = if self.is_train {
+                let mean = inp_f.sum() / count;
+                let var = inp_f.fold(0.0, |acc, x| acc + (x - mean).powf(2.0)) / count;
+
+                run_mean[f] = self.momentum * run_mean[f] + (1.0 - self.momentum) * mean;
+                run_var[f] = self.momentum * run_var[f] + (1.0 - self.momentum) * var;
+
+                (mean, 1.0 / (var + self.eps).sqrt())
+            } else {
+                (run_mean[f], 1.0 / (run_var[f] + self.eps).sqrt())
+            };
+
+            Zip::from(&mut out_f).and(&inp_f).for_each(|out_el, inp_el| {
+                *out_el = gamma[f] * (inp_el - mean) * inv_std + beta[f];
+            });
+
+            if self.is_train {
+                self.inv_std[f] = inv_std;
+                self.x_hat
+                    .slice_mut(s![.., f * sp..(f + 1) * sp])
+                    .zip_mut_with(&inp_f, |x_hat, inp_el| {
+                        *x_hat = (inp_el - mean) * inv_std;
+                    });
+            }
+        }
+
+        debug!("[ok] BatchNormLayer forward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn backward(&mut self, _prev_input: ParamsBlob, next_input: ParamsBlob) -> LayerBackwardResult {
+        let next_grad = next_layer_grad(&next_input[0]);
+
+        let gamma = self.lr_params.get_1d_buf_t(TypeBuffer::Weights);
+        let gamma = gamma.borrow();
+
+        let gamma_grad = self.lr_params.get_1d_buf_t(TypeBuffer::WeightsGrad);
+        let mut gamma_grad = gamma_grad.borrow_mut();
+
+        let beta_grad = self.lr_params.get_1d_buf_t(TypeBuffer::BiasGrad);
+        let mut beta_grad = beta_grad.borrow_mut();
+
+        let inp_grad = self.lr_params.get_2d_buf_t(TypeBuffer::InputGrad);
+        let mut inp_grad = inp_grad.borrow_mut();
+        let inp_grad = inp_grad.deref_mut();
+
+        let sp = self.spatial_size;
+        let batch_len = next_grad.nrows() as f32;
+        let count = batch_len * sp as f32;
+
+        for f in 0..self.features {
+            let grad_f = next_grad.slice(s![.., f * sp..(f + 1) * sp]);
+            let x_hat_f = self.x_hat.slice(s![.., f * sp..(f + 1) * sp]);
+
+            let grad_sum = grad_f.sum();
+            let grad_x_hat_sum = (&grad_f * &x_hat_f).sum();
+
+            if self.trainable {
+                gamma_grad[f] = grad_x_hat_sum / batch_len;
+                beta_grad[f] = grad_sum / batch_len;
+            }
+
+            let k = gamma[f] * self.inv_std[f] / count;
+
+            Zip::from(inp_grad.slice_mut(s![.., f * sp..(f + 1) * sp]))
+                .and(&grad_f)
+                .and(&x_hat_f)
+                .for_each(|inp_grad_el, grad_el, x_hat_el| {
+                    *inp_grad_el = k * (count * grad_el - grad_sum - x_hat_el * grad_x_hat_sum);
+                });
+        }
+
+        debug!("[ok] BatchNormLayer backward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn cpu_params(&self) -> Option<CpuParams> {
+        Some(self.lr_params.clone())
+    }
+
+    fn set_cpu_params(&mut self, lp: CpuParams) {
+        self.lr_params = lp;
+    }
+
+    fn layer_type(&self) -> &str {
+        "BatchNormLayer"
+    }
+
+    fn is_trainable(&self) -> bool {
+        self.trainable
+    }
+
+    fn set_trainable(&mut self, trainable: bool) {
+        self.trainable = trainable;
+    }
+
+    fn set_train_mode(&mut self, is_train: bool) {
+        self.is_train = is_train;
+    }
+
+    fn trainable_bufs(&self) -> TrainableBufsIds {
+        (
+            &[TypeBuffer::Weights as i32, TypeBuffer::Bias as i32],
+            &[TypeBuffer::WeightsGrad as i32, TypeBuffer::BiasGrad as i32],
+        )
+    }
+
+    fn serializable_bufs(&self) -> &[i32] {
+        &[
+            TypeBuffer::Weights as i32,
+            TypeBuffer::Bias as i32,
+            TypeBuffer::RunningMean as i32,
+            TypeBuffer::RunningVar as i32,
+        ]
+    }
+
+    /// Carefull this method resets gamma, beta and running statistics
+    fn set_input_shape(&mut self, sh: &[usize]) {
+        self.shape = sh.to_vec();
+
+        if sh.len() == 3 {
+            self.features = sh[0];
+            self.spatial_size = sh[1] * sh[2];
+        } else {
+            self.features = sh.iter().product();
+            self.spatial_size = 1;
+        }
+
+        let size = self.size();
+        let f = self.features;
+
+        let new_1d = |arr: Array1D| VariantParamArc::Array1(Arc::new(RefCell::new(arr)));
+
+        self.lr_params = CpuParams::new_only_output(size);
+        self.lr_params
+            .insert_buf(TypeBuffer::Weights as i32, new_1d(Array1D::ones(f)));
+        self.lr_params
+            .insert_buf(TypeBuffer::WeightsGrad as i32, new_1d(Array1D::zeros(f)));
+        self.lr_params
+            .insert_buf(TypeBuffer::Bias as i32, new_1d(Array1D::zeros(f)));
+        self.lr_params
+            .insert_buf(TypeBuffer::BiasGrad as i32, new_1d(Array1D::zeros(f)));
+        self.lr_params
+            .insert_buf(TypeBuffer::RunningMean as i32, new_1d(Array1D::zeros(f)));
+        self.lr_params
+            .insert_buf(TypeBuffer::RunningVar as i32, new_1d(Array1D::ones(f)));
+        self.lr_params.add_input_grad(size);
+
+        self.inv_std = Array1D::zeros(f);
+        self.x_hat = Array2D::zeros((0, 0));
+    }
+
+    fn size(&self) -> usize {
+        self.features * self.spatial_size
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        self.shape.clone()
+    }
+
+    fn copy_layer(&self) -> Box<dyn AbstractLayer> {
+        let mut copy_l = self.clone();
+        copy_l.set_cpu_params(self.lr_params.copy());
+        Box::new(copy_l)
+    }
+
+    fn clone_layer(&self) -> Box<dyn AbstractLayer> {
+        Box::new(self.clone())
+    }
+}
+
+impl Default for BatchNormLayer {
+    fn default() -> Self {
+        Self {
+            lr_params: CpuParams::empty(),
+            shape: vec![0],
+            features: 0,
+            spatial_size: 0,
+            momentum: 0.9,
+            eps: 1e-5,
+            is_train: true,
+            x_hat: Array2D::zeros((0, 0)),
+            inv_std: Array1D::zeros(0),
+            trainable: true,
+        }
+    }
+}
+
+impl BatchNormLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn new_box() -> Box<Self> {
+        Box::new(BatchNormLayer::new())
+    }
+
+    /// Running statistics are updated as
+    /// running = momentum * running + (1 - momentum) * batch
+    pub fn momentum(mut self, momentum: f32) -> Self {
+        self.momentum = momentum;
+        self
+    }
+
+    pub fn eps(mut self, eps: f32) -> Self {
+        self.eps = eps;
+        self
+    }
+}
+
+impl WithParams for BatchNormLayer {
+    fn cfg(&self) -> HashMap<String, Variant> {
+        let mut cfg: HashMap<String, Variant> = HashMap::new();
+
+        cfg.insert("momentum".to_owned(), Variant::Float(self.momentum));
+        cfg.insert("eps".to_owned(), Variant::Float(self.eps));
+        cfg.insert("trainable".to_owned(), Variant::Bool(self.trainable));
+
+        cfg
+    }
+
+    fn set_cfg(&mut self, cfg: &HashMap<String, Variant>) {
+        if let Some(Variant::Float(momentum)) = cfg.get("momentum") {
+            self.momentum = *momentum;
+        }
+
+        if let Some(Variant::Float(eps)) = cfg.get("eps") {
+            self.eps = *eps;
+        }
+
+        if let Some(Variant::Bool(trainable)) = cfg.get("trainable") {
+            self.trainable = *trainable;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::grad_check::*;
+
+    #[test]
+    fn train_mode_grads_include_batch_statistics() {
+        let mut l = BatchNormLayer::new();
+        check_layer(&mut l, &[4], test_values(3, 4, 1));
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+
+use ndarray::{Axis, Zip};
+
+use log::debug;
+
+use crate::cpu_params::*;
+use crate::layers::*;
+use crate::losses::*;
+use crate::util::*;
+
+/// Binary cross-entropy loss layer for multi-label problems.
+/// Each output is an independent sigmoid probability, loss is computed by BceLoss from logits
+/// without precision loss
+#[derive(Clone)]
+pub struct BceLossLayer {
+    pub size: usize,
+    pub lr_params: CpuParams,
+    logits: Array2D,
+    loss: BceLoss,
+    trainable: bool,
+}
+
+impl AbstractLayer for BceLossLayer {
+    fn forward(&mut self, input: ParamsBlob) -> LayerForwardResult {
+        let inp_m = input[0].get_2d_buf_t(TypeBuffer::Output);
+        let inp_m = inp_m.borrow();
+        let inp_m = inp_m.deref();
+
+        let out_m = self.lr_params.get_2d_buf_t(TypeBuffer::Output);
+        let mut out_m = out_m.borrow_mut();
+        let out_m = out_m.deref_mut();
+
+        let ws = self.lr_params.get_2d_buf_t(TypeBuffer::Weights);
+        let ws = ws.borrow();
+        let ws = ws.deref();
+
+        let bias = self.lr_params.get_1d_buf_t(TypeBuffer::Bias);
+        let bias = bias.borrow();
+        let bias = bias.deref();
+
+        self.logits = inp_m.dot(&ws.t()) + bias;
+
+        Zip::from(out_m)
+            .and(&self.logits)
+            .par_for_each(|out_el, logit| {
+                *out_el = stable_sigmoid(*logit);
+            });
+
+        debug!("[ok] BceLossLayer forward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn backward_output(
+        &mut self,
+        prev_input: ParamsBlob,
+        expected_vec: Array2D,
+        loss_weights: &LossWeights,
+    ) -> LayerBackwardResult {
+        let prev_input = prev_input[0].get_2d_buf_t(TypeBuffer::Output);
+        let prev_input = prev_input.borrow();
+        let prev_input = prev_input.deref();
+
+        let self_neu_grad = self.lr_params.get_2d_buf_t(TypeBuffer::NeuGrad);
+        let mut self_neu_grad = self_neu_grad.borrow_mut();
+        let self_neu_grad = self_neu_grad.deref_mut();
+
+        let ws_grad = self.lr_params.get_2d_buf_t(TypeBuffer::WeightsGrad);
+        let mut ws_grad = ws_grad.borrow_mut();
+        let ws_grad = ws_grad.deref_mut();
+
+        let bias_grad = self.lr_params.get_1d_buf_t(TypeBuffer::BiasGrad);
+        let mut bias_grad = bias_grad.borrow_mut();
+        let bias_grad = bias_grad.deref_mut();
+
+        *self_neu_grad = self
+            .loss
+            .backward(&self.logits, &expected_vec, loss_weights)?;
+
+        // frozen layer only passes gradient to previous layer with NeuGrad
+        if !self.trainable {
+            return Ok(vec![self.lr_params.clone()]);
+        }
+
+        let batch_len = prev_input.nrows() as f32;
+
+        *ws_grad = self_neu_grad.t().dot(prev_input) / batch_len;
+        *bias_grad = self_neu_grad.mean_axis(Axis(0)).unwrap();
+
+        debug!("[ok] BceLossLayer backward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn layer_type(&self) -> &str {
+        "BceLossLayer"
+    }
+
+    fn is_trainable(&self) -> bool {
+        self.trainable
+    }
+
+    fn set_trainable(&mut self, trainable: bool) {
+        self.trainable = trainable;
+    }
+
+    fn cpu_params(&self) -> Option<CpuParams> {
+        Some(self.lr_params.clone())
+    }
+
+    fn set_cpu_params(&mut self, lp: CpuParams) {
+        self.lr_params = lp;
+    }
+
+    /// Carefull this method overwrites weights and all other params
+    fn set_input_shape(&mut self, sh: &[usize]) {
+        self.lr_params = CpuParams::new_with_bias(self.size, sh.iter().product());
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Per-label accuracy as "accuracy_{label}", mean of them as "accuracy"
+    /// and the mean loss value as "loss"
+    fn metrics(&self) -> Option<&Metrics> {
+        self.loss.metrics()
+    }
+
+    fn copy_layer(&self) -> Box<dyn AbstractLayer> {
+        let mut copy_l = self.clone();
+        copy_l.set_cpu_params(self.lr_params.copy());
+        Box::new(copy_l)
+    }
+
+    fn clone_layer(&self) -> Box<dyn AbstractLayer> {
+        Box::new(self.clone())
+    }
+}
+
+impl BceLossLayer {
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            lr_params: CpuParams::empty(),
+            logits: Array2D::zeros((0, 0)),
+            loss: BceLoss::new(),
+            trainable: true,
+        }
+    }
+
+    pub fn new_box(size: usize) -> Box<Self> {
+        Box::new(BceLossLayer::new(size))
+    }
+
+    /// Loss multipliers of positive targets for each label
+    pub fn pos_weights(mut self, pos_weights: &[f32]) -> Self {
+        self.loss = self.loss.pos_weights(pos_weights);
+        self
+    }
+}
+
+impl WithParams for BceLossLayer {
+    fn cfg(&self) -> HashMap<String, Variant> {
+        let mut cfg: HashMap<String, Variant> = HashMap::new();
+
+        cfg.insert("size".to_owned(), Variant::Int(self.size as i32));
+
+        cfg.extend(self.loss.cfg());
+        cfg.insert("trainable".to_owned(), Variant::Bool(self.trainable));
+
+        cfg
+    }
+
+    fn set_cfg(&mut self, cfg: &HashMap<String, Variant>) {
+        if let Some(Variant::Int(size)) = cfg.get("size") {
+            if *size > 0 {
+                self.size = *size as usize;
+                self.lr_params = CpuParams::empty();
+            }
+        }
+
+        self.loss.set_cfg(cfg);
+
+        if let Some(Variant::Bool(trainable)) = cfg.get("trainable") {
+            self.trainable = *trainable;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::grad_check::*;
+
+    #[test]
+    fn weighted_positive_targets_grads_match_numeric() {
+        let mut l = BceLossLayer::new(3).pos_weights(&[1.0, 2.0, 0.5]);
+        let expected = ndarray::array![[1.0, 0.0, 1.0], [0.0, 1.0, 1.0]];
+        check_loss_layer(&mut l, test_values(2, 4, 1), expected, &LossWeights::default());
+    }
+}
@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+
+use ndarray::s;
+
+use log::{debug, error};
+
+use super::abstract_layer::{
+    next_layer_grad, AbstractLayer, LayerBackwardResult, LayerError, LayerForwardResult,
+    TrainableBufsIds,
+};
+use super::rnn_layer::seq_input_shape;
+use super::sublayers::*;
+use crate::cpu_params::*;
+use crate::layer_fabric::create_layer;
+use crate::util::*;
+
+/// Reverses order of seq_len blocks of dim values in each row
+fn reverse_seq(m: &Array2D, seq_len: usize, dim: usize) -> Array2D {
+    let mut out = Array2D::zeros(m.dim());
+
+    for t in 0..seq_len {
+        let rev_t = seq_len - 1 - t;
+        out.slice_mut(s![.., rev_t * dim..(rev_t + 1) * dim])
+            .assign(&m.slice(s![.., t * dim..(t + 1) * dim]));
+    }
+
+    out
+}
+
+/// Runs two copies of a recurrent layer over the sequence in forward and reverse order
+/// and concatenates their outputs.
+/// Output shape is [seq_len, 2 * hidden_size] for layers returning sequences,
+/// otherwise [2 * hidden_size]
+pub struct Bidirectional {
+    pub lr_params: CpuParams,
+    fw: Box<dyn AbstractLayer>,
+    bw: Box<dyn AbstractLayer>,
+    seq_len: usize,
+    input_dim: usize,
+    hidden_size: usize,
+    rev_input: CpuParams, // time reversed input of the last forward pass
+    trainable_ids: (Vec<i32>, Vec<i32>),
+    serializable_ids: Vec<i32>,
+}
+
+impl Bidirectional {
+    pub fn new(layer: Box<dyn AbstractLayer>) -> Self {
+        let bw = layer.copy_layer();
+
+        let mut l = Self {
+            lr_params: CpuParams::empty(),
+            fw: layer,
+            bw,
+            seq_len: 0,
+            input_dim: 0,
+            hidden_size: 0,
+            rev_input: CpuParams::empty(),
+            trainable_ids: (Vec::new(), Vec::new()),
+            serializable_ids: Vec::new(),
+        };
+
+        l.update_buf_ids();
+        l
+    }
+
+    pub fn new_box(layer: Box<dyn AbstractLayer>) -> Box<Self> {
+        Box::new(Bidirectional::new(layer))
+    }
+
+    fn is_sequence_output(&self) -> bool {
+        self.fw.output_shape().len() == 2
+    }
+
+    fn update_buf_ids(&mut self) {
+        let (trainable, serializable) = sublayers_buf_ids(&[self.fw.as_ref(), self.bw.as_ref()]);
+
+        self.trainable_ids = trainable;
+        self.serializable_ids = serializable;
+    }
+
+    /// Collects trainable buffers of both directions into own params
+    fn collect_params(&mut self) {
+        let mut lp = CpuParams::new_only_output(self.size());
+        lp.add_input_grad(self.seq_len * self.input_dim);
+
+        collect_sublayers_params(&mut lp, &[self.fw.as_ref(), self.bw.as_ref()]);
+
+        self.lr_params = lp;
+    }
+}
+
+impl AbstractLayer for Bidirectional {
+    fn forward(&mut self, input: ParamsBlob) -> LayerForwardResult {
+        let inp_m = input[0].get_2d_buf_t(TypeBuffer::Output);
+        let inp_m = inp_m.borrow();
+        let inp_m = inp_m.deref();
+
+        if self.seq_len == 0 || inp_m.ncols() != self.seq_len * self.input_dim {
+            error!(
+                "Invalid input size for Bidirectional : {}, expected : {}",
+                inp_m.ncols(),
+                self.seq_len * self.input_dim
+            );
+            return Err(LayerError::InvalidSize);
+        }
+
+        let batch_len = inp_m.nrows();
+
+        self.fw.set_batch_size(batch_len);
+        self.bw.set_batch_size(batch_len);
+
+        self.rev_input = output_params(reverse_seq(inp_m, self.seq_len, self.input_dim));
+
+        let fw_out = self.fw.forward(input.clone())?;
+        let bw_out = self.bw.forward(vec![self.rev_input.clone()])?;
+
+        let fw_out = fw_out[0].get_2d_buf_t(TypeBuffer::Output);
+        let fw_out = fw_out.borrow();
+
+        let bw_out = bw_out[0].get_2d_buf_t(TypeBuffer::Output);
+        let bw_out = bw_out.borrow();
+
+        let out_m = self.lr_params.get_2d_buf_t(TypeBuffer::Output);
+        let mut out_m = out_m.borrow_mut();
+        let out_m = out_m.deref_mut();
+
+        let hid = self.hidden_size;
+
+        if self.is_sequence_output() {
+            let bw_out = reverse_seq(&bw_out, self.seq_len, hid);
+
+            for t in 0..self.seq_len {
+                out_m
+                    .slice_mut(s![.., 2 * t * hid..(2 * t + 1) * hid])
+                    .assign(&fw_out.slice(s![.., t * hid..(t + 1) * hid]));
+                out_m
+                    .slice_mut(s![.., (2 * t + 1) * hid..(2 * t + 2) * hid])
+                    .assign(&bw_out.slice(s![.., t * hid..(t + 1) * hid]));
+            }
+        } else {
+            out_m.slice_mut(s![.., 0..hid]).assign(&fw_out);
+            out_m.slice_mut(s![.., hid..]).assign(&bw_out);
+        }
+
+        debug!("[ok] Bidirectional forward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn backward(&mut self, prev_input: ParamsBlob, next_input: ParamsBlob) -> LayerBackwardResult {
+        let next_grad = next_layer_grad(&next_input[0]);
+
+        let hid = self.hidden_size;
+
+        let (fw_grad, bw_grad) = if self.is_sequence_output() {
+            let mut fw_grad = Array2D::zeros((next_grad.nrows(), self.seq_len * hid));
+            let mut bw_grad = Array2D::zeros((next_grad.nrows(), self.seq_len * hid));
+
+            for t in 0..self.seq_len {
+                fw_grad
+                    .slice_mut(s![.., t * hid..(t + 1) * hid])
+                    .assign(&next_grad.slice(s![.., 2 * t * hid..(2 * t + 1) * hid]));
+                bw_grad
+                    .slice_mut(s![.., t * hid..(t + 1) * hid])
+                    .assign(&next_grad.slice(s![.., (2 * t + 1) * hid..(2 * t + 2) * hid]));
+            }
+
+            (fw_grad, reverse_seq(&bw_grad, self.seq_len, hid))
+        } else {
+            (
+                next_grad.slice(s![.., 0..hid]).to_owned(),
+                next_grad.slice(s![.., hid..]).to_owned(),
+            )
+        };
+
+        let fw_res = self.fw.backward(prev_input, vec![grad_params(fw_grad)])?;
+        let bw_res = self
+            .bw
+            .backward(vec![self.rev_input.clone()], vec![grad_params(bw_grad)])?;
+
+        let fw_inp_grad = fw_res[0].get_2d_buf_t(TypeBuffer::InputGrad);
+        let fw_inp_grad = fw_inp_grad.borrow();
+
+        let bw_inp_grad = bw_res[0].get_2d_buf_t(TypeBuffer::InputGrad);
+        let bw_inp_grad = bw_inp_grad.borrow();
+
+        let inp_grad = self.lr_params.get_2d_buf_t(TypeBuffer::InputGrad);
+        let mut inp_grad = inp_grad.borrow_mut();
+
+        *inp_grad = reverse_seq(&bw_inp_grad, self.seq_len, self.input_dim) + &*fw_inp_grad;
+
+        debug!("[ok] Bidirectional backward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn cpu_params(&self) -> Option<CpuParams> {
+        Some(self.lr_params.clone())
+    }
+
+    fn set_cpu_params(&mut self, lp: CpuParams) {
+        self.lr_params = lp;
+        distribute_sublayers_params(&self.lr_params, &mut [self.fw.as_mut(), self.bw.as_mut()]);
+    }
+
+    fn layer_type(&self) -> &str {
+        "Bidirectional"
+    }
+
+    /// Wrapped layers store the flag, it is a part of the wrapped layer cfg
+    fn is_trainable(&self) -> bool {
+        self.fw.is_trainable()
+    }
+
+    fn set_trainable(&mut self, trainable: bool) {
+        self.fw.set_trainable(trainable);
+        self.bw.set_trainable(trainable);
+    }
+
+    fn trainable_bufs(&self) -> TrainableBufsIds {
+        (&self.trainable_ids.0, &self.trainable_ids.1)
+    }
+
+    fn serializable_bufs(&self) -> &[i32] {
+        &self.serializable_ids
+    }
+
+    /// Accepts [seq_len, input_dim] shape or flat shape,
+    /// which is split by input_dim of wrapped layer.
+    /// Carefull this method overwrites weights and all other params
+    fn set_input_shape(&mut self, sh: &[usize]) {
+        let input_dim = match self.fw.cfg().get("input_dim") {
+            Some(Variant::Int(input_dim)) => *input_dim as usize,
+            _ => 1,
+        };
+
+        if let Some((seq_len, input_dim)) = seq_input_shape(sh, input_dim) {
+            self.seq_len = seq_len;
+            self.input_dim = input_dim;
+        } else {
+            error!(
+                "Bidirectional couldn't split input shape {:?} by input_dim {}",
+                sh, input_dim
+            );
+            return;
+        }
+
+        let seq_sh = [self.seq_len, self.input_dim];
+
+        self.fw.set_input_shape(&seq_sh);
+        self.bw.set_input_shape(&seq_sh);
+
+        self.hidden_size = *self.fw.output_shape().last().unwrap();
+
+        self.update_buf_ids();
+        self.collect_params();
+    }
+
+    fn size(&self) -> usize {
+        2 * self.fw.size()
+    }
+
+    fn forward_flops(&self) -> usize {
+        self.fw.forward_flops() + self.bw.forward_flops() + self.size()
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        let mut sh = self.fw.output_shape();
+        *sh.last_mut().unwrap() *= 2;
+        sh
+    }
+
+    fn copy_layer(&self) -> Box<dyn AbstractLayer> {
+        let mut copy_l = Bidirectional {
+            lr_params: CpuParams::empty(),
+            fw: self.fw.copy_layer(),
+            bw: self.bw.copy_layer(),
+            seq_len: self.seq_len,
+            input_dim: self.input_dim,
+            hidden_size: self.hidden_size,
+            rev_input: CpuParams::empty(),
+            trainable_ids: self.trainable_ids.clone(),
+            serializable_ids: self.serializable_ids.clone(),
+        };
+
+        if self.seq_len > 0 {
+            copy_l.collect_params();
+        }
+        Box::new(copy_l)
+    }
+
+    fn clone_layer(&self) -> Box<dyn AbstractLayer> {
+        Box::new(Bidirectional {
+            lr_params: self.lr_params.clone(),
+            fw: self.fw.clone_layer(),
+            bw: self.bw.clone_layer(),
+            seq_len: self.seq_len,
+            input_dim: self.input_dim,
+            hidden_size: self.hidden_size,
+            rev_input: CpuParams::empty(),
+            trainable_ids: self.trainable_ids.clone(),
+            serializable_ids: self.serializable_ids.clone(),
+        })
+    }
+}
+
+impl WithParams for Bidirectional {
+    /// Config of wrapped layer with its type stored under "layer" key
+    fn cfg(&self) -> HashMap<String, Variant> {
+        let mut cfg = self.fw.cfg();
+
+        cfg.insert(
+            "layer".to_owned(),
+            Variant::String(self.fw.layer_type().to_owned()),
+        );
+
+        cfg
+    }
+
+    fn set_cfg(&mut self, cfg: &HashMap<String, Variant>) {
+        let layer_type = match cfg.get("layer") {
+            Some(Variant::String(layer_type)) => layer_type.clone(),
+            _ => self.fw.layer_type().to_owned(),
+        };
+
+        if let Some(l) = create_layer(&layer_type, Some(cfg)) {
+            self.bw = l.copy_layer();
+            self.fw = l;
+            self.lr_params = CpuParams::empty();
+            self.seq_len = 0;
+            self.update_buf_ids();
+        } else {
+            error!("Bidirectional couldn't create wrapped layer {}", layer_type);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::grad_check::*;
+    use crate::layers::GruLayer;
+
+    #[test]
+    fn both_directions_grads_match_numeric() {
+        let mut l = Bidirectional::new(Box::new(GruLayer::new(2).return_sequences(true)));
+        check_layer(&mut l, &[3, 2], test_values(2, 6, 1));
+    }
+}
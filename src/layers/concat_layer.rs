@@ -0,0 +1,187 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::DerefMut;
+use std::sync::Arc;
+
+use ndarray::s;
+
+use log::{debug, error};
+
+use super::abstract_layer::{
+    next_layer_grad, AbstractLayer, LayerBackwardResult, LayerError, LayerForwardResult,
+    TrainableBufsIds,
+};
+use crate::cpu_params::*;
+use crate::util::*;
+
+/// Concatenates features of inputs, merge node of the graph model.
+/// Images of equal height and width are concatenated by channels.
+/// Backward returns params with InputGrad slice for each input
+#[derive(Clone)]
+pub struct ConcatLayer {
+    pub lr_params: CpuParams,
+    shape: Vec<usize>,
+    input_sizes: Vec<usize>,
+}
+
+impl ConcatLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn new_box() -> Box<Self> {
+        Box::new(ConcatLayer::new())
+    }
+}
+
+impl AbstractLayer for ConcatLayer {
+    fn forward(&mut self, input: ParamsBlob) -> LayerForwardResult {
+        if input.len() != self.input_sizes.len() {
+            error!(
+                "Invalid inputs count for ConcatLayer : {}, expected : {}",
+                input.len(),
+                self.input_sizes.len()
+            );
+            return Err(LayerError::InvalidSize);
+        }
+
+        let out_m = self.lr_params.get_2d_buf_t(TypeBuffer::Output);
+        let mut out_m = out_m.borrow_mut();
+        let out_m = out_m.deref_mut();
+
+        let mut offset = 0;
+
+        for (inp, inp_size) in input.iter().zip(self.input_sizes.iter()) {
+            let inp_m = inp.get_2d_buf_t(TypeBuffer::Output);
+            let inp_m = inp_m.borrow();
+
+            if inp_m.ncols() != *inp_size || inp_m.nrows() != out_m.nrows() {
+                error!(
+                    "Invalid input size for ConcatLayer : {:?}, expected : {:?}",
+                    inp_m.dim(),
+                    (out_m.nrows(), inp_size)
+                );
+                return Err(LayerError::InvalidSize);
+            }
+
+            out_m
+                .slice_mut(s![.., offset..offset + inp_size])
+                .assign(&inp_m);
+            offset += inp_size;
+        }
+
+        debug!("[ok] ConcatLayer forward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn backward(&mut self, _prev_input: ParamsBlob, next_input: ParamsBlob) -> LayerBackwardResult {
+        let next_grad = next_layer_grad(&next_input[0]);
+
+        // whole gradient is kept for the single input case
+        self.lr_params
+            .get_2d_buf_t(TypeBuffer::InputGrad)
+            .borrow_mut()
+            .assign(&next_grad);
+
+        let mut out = Vec::with_capacity(self.input_sizes.len());
+        let mut offset = 0;
+
+        for inp_size in self.input_sizes.iter() {
+            let inp_grad = next_grad
+                .slice(s![.., offset..offset + inp_size])
+                .to_owned();
+            offset += inp_size;
+
+            let mut inp_params = CpuParams::empty();
+            inp_params.insert_buf(
+                TypeBuffer::InputGrad as i32,
+                VariantParamArc::Array2(Arc::new(RefCell::new(inp_grad))),
+            );
+            out.push(inp_params);
+        }
+
+        debug!("[ok] ConcatLayer backward()");
+
+        Ok(out)
+    }
+
+    fn cpu_params(&self) -> Option<CpuParams> {
+        Some(self.lr_params.clone())
+    }
+
+    fn set_cpu_params(&mut self, lp: CpuParams) {
+        self.lr_params = lp;
+    }
+
+    fn layer_type(&self) -> &str {
+        "ConcatLayer"
+    }
+
+    fn trainable_bufs(&self) -> TrainableBufsIds {
+        (&[], &[])
+    }
+
+    fn serializable_bufs(&self) -> &[i32] {
+        &[]
+    }
+
+    fn set_input_shape(&mut self, sh: &[usize]) {
+        self.set_input_shapes(&[sh.to_vec()]);
+    }
+
+    fn set_input_shapes(&mut self, shapes: &[Vec<usize>]) {
+        self.input_sizes = shapes.iter().map(|sh| sh.iter().product()).collect();
+
+        let size = self.size();
+        let same_image_size = shapes
+            .iter()
+            .all(|sh| sh.len() == 3 && sh[1..] == shapes[0][1..]);
+
+        if same_image_size {
+            let channels = shapes.iter().map(|sh| sh[0]).sum();
+            self.shape = vec![channels, shapes[0][1], shapes[0][2]];
+        } else {
+            self.shape = vec![size];
+        }
+
+        self.lr_params = CpuParams::new_only_output(size);
+        self.lr_params.add_input_grad(size);
+    }
+
+    fn size(&self) -> usize {
+        self.input_sizes.iter().sum()
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        self.shape.clone()
+    }
+
+    fn copy_layer(&self) -> Box<dyn AbstractLayer> {
+        let mut copy_l = self.clone();
+        copy_l.set_cpu_params(self.lr_params.copy());
+        Box::new(copy_l)
+    }
+
+    fn clone_layer(&self) -> Box<dyn AbstractLayer> {
+        Box::new(self.clone())
+    }
+}
+
+impl Default for ConcatLayer {
+    fn default() -> Self {
+        Self {
+            lr_params: CpuParams::empty(),
+            shape: vec![0],
+            input_sizes: Vec::new(),
+        }
+    }
+}
+
+impl WithParams for ConcatLayer {
+    fn cfg(&self) -> HashMap<String, Variant> {
+        HashMap::new()
+    }
+
+    fn set_cfg(&mut self, _cfg: &HashMap<String, Variant>) {}
+}
@@ -0,0 +1,430 @@
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+
+use ndarray::{Axis, Zip};
+
+use log::{debug, error};
+
+use super::abstract_layer::{
+    ensure_initialized, next_layer_grad, AbstractLayer, LayerBackwardResult, LayerError,
+    LayerForwardResult,
+};
+use crate::cpu_params::*;
+use crate::util::conv_helpers::{col2im, im2col, ConvGeometry};
+use crate::util::*;
+
+/// Padding mode of 1D convolution
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Padding {
+    Valid, // no padding, output is shorter than input
+    Same,  // output length is ceil(length / stride), zeros are added evenly to both ends
+}
+
+impl Padding {
+    pub fn name(&self) -> &str {
+        match self {
+            Padding::Valid => "valid",
+            Padding::Same => "same",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "valid" => Some(Padding::Valid),
+            "same" => Some(Padding::Same),
+            _ => None,
+        }
+    }
+}
+
+/// 1D convolution layer for sequences and signals.
+/// Input and output rows are (channels, length) samples flattened in channels-first order.
+/// Weights have shape (out_channels, in_channels * kernel_size)
+#[derive(Clone)]
+pub struct Conv1DLayer {
+    pub lr_params: CpuParams,
+    in_channels: usize,
+    out_channels: usize,
+    kernel_size: usize,
+    stride: usize,
+    dilation: usize,
+    padding: Padding,
+    geometry: ConvGeometry,
+    pub activation: Activation,
+    pre_activation: Array2D, // activation input, derivative is taken from it
+    trainable: bool,
+}
+
+impl AbstractLayer for Conv1DLayer {
+    fn forward(&mut self, input: ParamsBlob) -> LayerForwardResult {
+        ensure_initialized(&self.lr_params, "Conv1DLayer")?;
+
+        let inp_m = input[0].get_2d_buf_t(TypeBuffer::Output);
+        let inp_m = inp_m.borrow();
+        let inp_m = inp_m.deref();
+
+        if inp_m.ncols() != self.geometry.input_len() {
+            error!(
+                "Invalid input size for Conv1DLayer : {}, expected : {}",
+                inp_m.ncols(),
+                self.geometry.input_len()
+            );
+            return Err(LayerError::InvalidSize);
+        }
+
+        let out_m = self.lr_params.get_2d_buf_t(TypeBuffer::Output);
+        let mut out_m = out_m.borrow_mut();
+        let out_m = out_m.deref_mut();
+
+        let ws = self.lr_params.get_2d_buf_t(TypeBuffer::Weights);
+        let ws = ws.borrow();
+        let ws = ws.deref();
+
+        let bias_out = self.lr_params.get_1d_buf_t(TypeBuffer::Bias);
+        let bias_out = bias_out.borrow();
+        let bias_out = bias_out.deref();
+
+        let out_len = self.geometry.cols_cols();
+
+        Zip::from(inp_m.rows())
+            .and(out_m.rows_mut())
+            .par_for_each(|inp_r, out_r| {
+                // for each batch
+                let cols = im2col(inp_r, &self.geometry);
+                let mul_res = ws.dot(&cols);
+
+                let mut out_r = out_r
+                    .into_shape((self.out_channels, out_len))
+                    .expect("Conv1DLayer output reshape");
+
+                Zip::from(out_r.rows_mut())
+                    .and(mul_res.rows())
+                    .and(bias_out)
+                    .for_each(|out_ch, mul_ch, bias_el| {
+                        Zip::from(out_ch).and(mul_ch).for_each(|out_el, mul_el| {
+                            *out_el = mul_el + bias_el;
+                        });
+                    });
+            });
+
+        self.activation.apply(out_m, &mut self.pre_activation);
+
+        debug!("[ok] Conv1DLayer forward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn backward(&mut self, prev_input: ParamsBlob, next_input: ParamsBlob) -> LayerBackwardResult {
+        ensure_initialized(&self.lr_params, "Conv1DLayer")?;
+
+        let next_grad = next_layer_grad(&next_input[0]);
+
+        let self_err_vals = self.lr_params.get_2d_buf_t(TypeBuffer::NeuGrad);
+        let mut self_err_vals = self_err_vals.borrow_mut();
+        let self_err_vals = self_err_vals.deref_mut();
+
+        Zip::from(self_err_vals.view_mut())
+            .and(&next_grad)
+            .and(&self.pre_activation)
+            .par_for_each(|err_val, next_grad_val, pre_act| {
+                *err_val = self.activation.func_deriv(*pre_act) * next_grad_val;
+            });
+
+        let prev_input = prev_input[0].get_2d_buf_t(TypeBuffer::Output);
+        let prev_input = prev_input.borrow();
+        let prev_input = prev_input.deref();
+
+        let ws = self.lr_params.get_2d_buf_t(TypeBuffer::Weights);
+        let ws = ws.borrow();
+        let ws = ws.deref();
+
+        let ws_grad = self.lr_params.get_2d_buf_t(TypeBuffer::WeightsGrad);
+        let mut ws_grad = ws_grad.borrow_mut();
+        let ws_grad = ws_grad.deref_mut();
+
+        let bias_grad = self.lr_params.get_1d_buf_t(TypeBuffer::BiasGrad);
+        let mut bias_grad = bias_grad.borrow_mut();
+        let bias_grad = bias_grad.deref_mut();
+
+        let inp_grad = self.lr_params.get_2d_buf_t(TypeBuffer::InputGrad);
+        let mut inp_grad = inp_grad.borrow_mut();
+        let inp_grad = inp_grad.deref_mut();
+
+        let out_len = self.geometry.cols_cols();
+        let batch_len = prev_input.nrows() as f32;
+
+        // calc grad for weights and bias, frozen layer only passes gradient to the previous layer
+        if self.trainable {
+            ws_grad.fill(0.0);
+            bias_grad.fill(0.0);
+
+            for (prev_r, err_r) in prev_input.rows().into_iter().zip(self_err_vals.rows()) {
+                let cols = im2col(prev_r, &self.geometry);
+                let err_r = err_r
+                    .into_shape((self.out_channels, out_len))
+                    .expect("Conv1DLayer gradient reshape");
+
+                *ws_grad += &err_r.dot(&cols.t());
+                *bias_grad += &err_r.sum_axis(Axis(1));
+            }
+
+            *ws_grad /= batch_len;
+            *bias_grad /= batch_len;
+        }
+
+        // calc grad for the previous layer
+        Zip::from(inp_grad.rows_mut())
+            .and(self_err_vals.rows())
+            .par_for_each(|inp_grad_r, err_r| {
+                let err_r = err_r
+                    .into_shape((self.out_channels, out_len))
+                    .expect("Conv1DLayer gradient reshape");
+                let cols_grad = ws.t().dot(&err_r);
+
+                col2im(&cols_grad, &self.geometry, inp_grad_r);
+            });
+
+        debug!("[ok] Conv1DLayer backward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn cpu_params(&self) -> Option<CpuParams> {
+        Some(self.lr_params.clone())
+    }
+
+    fn set_cpu_params(&mut self, lp: CpuParams) {
+        self.lr_params = lp;
+    }
+
+    fn layer_type(&self) -> &str {
+        "Conv1DLayer"
+    }
+
+    fn is_trainable(&self) -> bool {
+        self.trainable
+    }
+
+    fn set_trainable(&mut self, trainable: bool) {
+        self.trainable = trainable;
+    }
+
+    /// Accepts [channels, length] shape.
+    /// Plain [size] shape is split into in_channels channels.
+    /// Carefull this method overwrites weights and all other params
+    fn set_input_shape(&mut self, sh: &[usize]) {
+        let (c, len) = if sh.len() == 2 {
+            (sh[0], sh[1])
+        } else {
+            (self.in_channels, sh[0] / self.in_channels.max(1))
+        };
+
+        let (pad_begin, pad_end) = match self.padding {
+            Padding::Valid => (0, 0),
+            Padding::Same => {
+                let stride = self.stride.max(1);
+                let out_len = len.div_ceil(stride);
+                let span = self.dilation * self.kernel_size.saturating_sub(1) + 1;
+                let total = ((out_len.max(1) - 1) * stride + span).saturating_sub(len);
+
+                (total / 2, total - total / 2)
+            }
+        };
+
+        let geometry = ConvGeometry::new_1d(
+            c,
+            len,
+            self.kernel_size,
+            self.stride,
+            self.dilation,
+            pad_begin,
+            pad_end,
+        );
+
+        if geometry.input_len() != sh.iter().product::<usize>() || !geometry.is_valid() {
+            error!(
+                "Conv1DLayer couldn't be applied to input shape {:?} with kernel {}",
+                sh, self.kernel_size
+            );
+            return;
+        }
+
+        self.in_channels = c;
+        self.geometry = geometry;
+
+        self.lr_params = CpuParams::new_with_bias_and_output(
+            self.out_channels,
+            geometry.cols_rows(),
+            self.size(),
+        );
+        self.lr_params.add_input_grad(geometry.input_len());
+    }
+
+    fn size(&self) -> usize {
+        if !self.geometry.is_valid() {
+            return 0;
+        }
+
+        self.out_channels * self.geometry.cols_cols()
+    }
+
+    fn forward_flops(&self) -> usize {
+        if !self.geometry.is_valid() {
+            return 0;
+        }
+
+        2 * self.out_channels * self.geometry.cols_rows() * self.geometry.cols_cols() + self.size()
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        if !self.geometry.is_valid() {
+            return vec![0];
+        }
+
+        vec![self.out_channels, self.geometry.out_width()]
+    }
+
+    fn copy_layer(&self) -> Box<dyn AbstractLayer> {
+        let mut copy_l = self.clone();
+        copy_l.set_cpu_params(self.lr_params.copy());
+        Box::new(copy_l)
+    }
+
+    fn clone_layer(&self) -> Box<dyn AbstractLayer> {
+        Box::new(self.clone())
+    }
+}
+
+impl Conv1DLayer {
+    pub fn new(out_channels: usize, kernel_size: usize, activation: Activation) -> Self {
+        Self {
+            lr_params: CpuParams::empty(),
+            in_channels: 1,
+            out_channels,
+            kernel_size,
+            stride: 1,
+            dilation: 1,
+            padding: Padding::Valid,
+            geometry: ConvGeometry::default(),
+            activation,
+            pre_activation: Array2D::zeros((0, 0)),
+            trainable: true,
+        }
+    }
+
+    pub fn new_box(
+        out_channels: usize,
+        kernel_size: usize,
+        activation: Activation,
+    ) -> Box<Self> {
+        Box::new(Conv1DLayer::new(out_channels, kernel_size, activation))
+    }
+
+    pub fn stride(mut self, stride: usize) -> Self {
+        self.stride = stride;
+        self
+    }
+
+    pub fn dilation(mut self, dilation: usize) -> Self {
+        self.dilation = dilation;
+        self
+    }
+
+    pub fn padding(mut self, padding: Padding) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// Used only when previous layer provides flat [size] output shape
+    pub fn in_channels(mut self, in_channels: usize) -> Self {
+        self.in_channels = in_channels;
+        self
+    }
+}
+
+impl WithParams for Conv1DLayer {
+    fn cfg(&self) -> HashMap<String, Variant> {
+        let mut cfg: HashMap<String, Variant> = HashMap::new();
+
+        cfg.insert(
+            "in_channels".to_owned(),
+            Variant::Int(self.in_channels as i32),
+        );
+        cfg.insert(
+            "out_channels".to_owned(),
+            Variant::Int(self.out_channels as i32),
+        );
+        cfg.insert(
+            "kernel_size".to_owned(),
+            Variant::Int(self.kernel_size as i32),
+        );
+        cfg.insert("stride".to_owned(), Variant::Int(self.stride as i32));
+        cfg.insert("dilation".to_owned(), Variant::Int(self.dilation as i32));
+        cfg.insert(
+            "padding".to_owned(),
+            Variant::String(self.padding.name().to_owned()),
+        );
+        self.activation.write_cfg(&mut cfg);
+        cfg.insert("trainable".to_owned(), Variant::Bool(self.trainable));
+
+        cfg
+    }
+
+    fn set_cfg(&mut self, cfg: &HashMap<String, Variant>) {
+        if cfg.contains_key("activation") {
+            match Activation::from_cfg(cfg) {
+                Ok(activation) => self.activation = activation,
+                Err(e) => error!("[conv1d] {}", e),
+            }
+        }
+
+        if let Some(Variant::Int(in_channels)) = cfg.get("in_channels") {
+            self.in_channels = *in_channels as usize;
+        }
+
+        if let Some(Variant::Int(out_channels)) = cfg.get("out_channels") {
+            self.out_channels = *out_channels as usize;
+        }
+
+        if let Some(Variant::Int(kernel_size)) = cfg.get("kernel_size") {
+            self.kernel_size = *kernel_size as usize;
+        }
+
+        if let Some(Variant::Int(stride)) = cfg.get("stride") {
+            self.stride = *stride as usize;
+        }
+
+        if let Some(Variant::Int(dilation)) = cfg.get("dilation") {
+            self.dilation = *dilation as usize;
+        }
+
+        if let Some(Variant::String(padding)) = cfg.get("padding") {
+            if let Some(padding) = Padding::from_name(padding) {
+                self.padding = padding;
+            } else {
+                error!("Unknown Conv1DLayer padding : {}", padding);
+            }
+        }
+
+        self.lr_params = CpuParams::empty();
+        self.geometry = ConvGeometry::default();
+
+        if let Some(Variant::Bool(trainable)) = cfg.get("trainable") {
+            self.trainable = *trainable;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::grad_check::*;
+
+    #[test]
+    fn dilated_same_padding_grads_match_numeric() {
+        let mut l = Conv1DLayer::new(2, 3, Activation::Tanh)
+            .dilation(2)
+            .padding(Padding::Same);
+        check_layer(&mut l, &[2, 7], test_values(2, 14, 1));
+    }
+}
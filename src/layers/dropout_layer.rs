@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+
+use ndarray::Zip;
+
+use ndarray_rand::rand_distr::Uniform;
+use ndarray_rand::RandomExt;
+
+use log::{debug, error};
+
+use super::abstract_layer::{
+    next_layer_grad, AbstractLayer, LayerBackwardResult, LayerError, LayerForwardResult,
+    TrainableBufsIds,
+};
+use crate::cpu_params::*;
+use crate::util::*;
+
+/// Inverted dropout mask, shared by DropoutLayer and FcLayer.
+/// Each value is kept with probability (1 - rate) and scaled by 1 / (1 - rate),
+/// so nothing has to be rescaled at evaluation time
+#[derive(Clone)]
+pub struct DropoutMask {
+    pub rate: f32,
+    mask: Array2D,
+}
+
+impl DropoutMask {
+    pub fn new(rate: f32) -> Self {
+        Self {
+            rate,
+            mask: Array2D::zeros((0, 0)),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.rate > 0.0
+    }
+
+    /// Generates new mask and multiplies values by it
+    pub fn apply(&mut self, vals: &mut Array2D) {
+        let keep = 1.0 - self.rate;
+
+        self.mask = Array2D::random(vals.dim(), Uniform::new(0.0, 1.0))
+            .mapv(|v| if v < keep { 1.0 / keep } else { 0.0 });
+
+        *vals *= &self.mask;
+    }
+
+    /// Mask generated on the last apply(...) call
+    pub fn mask(&self) -> &Array2D {
+        &self.mask
+    }
+}
+
+impl Default for DropoutMask {
+    fn default() -> Self {
+        DropoutMask::new(0.0)
+    }
+}
+
+/// Drops input values with probability rate while training, identity in eval mode
+#[derive(Clone)]
+pub struct DropoutLayer {
+    pub lr_params: CpuParams,
+    shape: Vec<usize>,
+    size: usize,
+    dropout: DropoutMask,
+    is_train: bool,
+}
+
+impl AbstractLayer for DropoutLayer {
+    fn forward(&mut self, input: ParamsBlob) -> LayerForwardResult {
+        let inp_m = input[0].get_2d_buf_t(TypeBuffer::Output);
+        let inp_m = inp_m.borrow();
+        let inp_m = inp_m.deref();
+
+        if inp_m.ncols() != self.size {
+            error!(
+                "Invalid input size for DropoutLayer : {}, expected : {}",
+                inp_m.ncols(),
+                self.size
+            );
+            return Err(LayerError::InvalidSize);
+        }
+
+        let out_m = self.lr_params.get_2d_buf_t(TypeBuffer::Output);
+        let mut out_m = out_m.borrow_mut();
+        let out_m = out_m.deref_mut();
+
+        out_m.assign(inp_m);
+
+        if self.is_train && self.dropout.is_enabled() {
+            self.dropout.apply(out_m);
+        }
+
+        debug!("[ok] DropoutLayer forward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn backward(&mut self, _prev_input: ParamsBlob, next_input: ParamsBlob) -> LayerBackwardResult {
+        let next_grad = next_layer_grad(&next_input[0]);
+
+        let inp_grad = self.lr_params.get_2d_buf_t(TypeBuffer::InputGrad);
+        let mut inp_grad = inp_grad.borrow_mut();
+        let inp_grad = inp_grad.deref_mut();
+
+        if self.is_train && self.dropout.is_enabled() {
+            Zip::from(inp_grad)
+                .and(&next_grad)
+                .and(self.dropout.mask())
+                .par_for_each(|inp_grad_el, grad_el, mask_el| {
+                    *inp_grad_el = grad_el * mask_el;
+                });
+        } else {
+            inp_grad.assign(&next_grad);
+        }
+
+        debug!("[ok] DropoutLayer backward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn cpu_params(&self) -> Option<CpuParams> {
+        Some(self.lr_params.clone())
+    }
+
+    fn set_cpu_params(&mut self, lp: CpuParams) {
+        self.lr_params = lp;
+    }
+
+    fn layer_type(&self) -> &str {
+        "DropoutLayer"
+    }
+
+    fn set_train_mode(&mut self, is_train: bool) {
+        self.is_train = is_train;
+    }
+
+    fn trainable_bufs(&self) -> TrainableBufsIds {
+        (&[], &[])
+    }
+
+    fn serializable_bufs(&self) -> &[i32] {
+        &[]
+    }
+
+    fn set_input_shape(&mut self, sh: &[usize]) {
+        self.shape = sh.to_vec();
+        self.size = sh.iter().product();
+
+        self.lr_params = CpuParams::new_only_output(self.size);
+        self.lr_params.add_input_grad(self.size);
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        self.shape.clone()
+    }
+
+    fn copy_layer(&self) -> Box<dyn AbstractLayer> {
+        let mut copy_l = self.clone();
+        copy_l.set_cpu_params(self.lr_params.copy());
+        Box::new(copy_l)
+    }
+
+    fn clone_layer(&self) -> Box<dyn AbstractLayer> {
+        Box::new(self.clone())
+    }
+}
+
+impl DropoutLayer {
+    pub fn new(rate: f32) -> Self {
+        Self {
+            lr_params: CpuParams::empty(),
+            shape: vec![0],
+            size: 0,
+            dropout: DropoutMask::new(rate),
+            is_train: true,
+        }
+    }
+
+    pub fn new_box(rate: f32) -> Box<Self> {
+        Box::new(DropoutLayer::new(rate))
+    }
+}
+
+impl WithParams for DropoutLayer {
+    fn cfg(&self) -> HashMap<String, Variant> {
+        let mut cfg: HashMap<String, Variant> = HashMap::new();
+
+        cfg.insert("rate".to_owned(), Variant::Float(self.dropout.rate));
+
+        cfg
+    }
+
+    fn set_cfg(&mut self, cfg: &HashMap<String, Variant>) {
+        if let Some(Variant::Float(rate)) = cfg.get("rate") {
+            self.dropout.rate = *rate;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::grad_check::*;
+
+    #[test]
+    fn eval_mode_passes_grad_through() {
+        let mut l = DropoutLayer::new(0.5);
+        l.set_train_mode(false);
+        check_layer(&mut l, &[6], test_values(2, 6, 1));
+    }
+}
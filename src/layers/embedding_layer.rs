@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+
+use ndarray::{s, Zip};
+
+use log::{debug, error};
+
+use super::abstract_layer::{
+    next_layer_grad, AbstractLayer, LayerBackwardResult, LayerError, LayerForwardResult,
+    TrainableBufsIds,
+};
+use crate::cpu_params::*;
+use crate::util::*;
+
+/// Embedding layer, each input value is an index into (vocab_size, dim) weights table.
+/// Output shape is [input_len, dim].
+/// Weights gradient is non-zero only for rows met in the batch, so optimizers,
+/// which skip zero gradients, update only these rows
+#[derive(Clone)]
+pub struct EmbeddingLayer {
+    pub lr_params: CpuParams,
+    vocab_size: usize,
+    dim: usize,
+    input_len: usize,
+    trainable: bool,
+}
+
+impl AbstractLayer for EmbeddingLayer {
+    fn forward(&mut self, input: ParamsBlob) -> LayerForwardResult {
+        let inp_m = input[0].get_2d_buf_t(TypeBuffer::Output);
+        let inp_m = inp_m.borrow();
+        let inp_m = inp_m.deref();
+
+        if inp_m.ncols() != self.input_len {
+            error!(
+                "Invalid input size for EmbeddingLayer : {}, expected : {}",
+                inp_m.ncols(),
+                self.input_len
+            );
+            return Err(LayerError::InvalidSize);
+        }
+
+        if let Some(idx) = inp_m
+            .iter()
+            .find(|idx| **idx < 0.0 || **idx as usize >= self.vocab_size)
+        {
+            error!(
+                "EmbeddingLayer index {} is out of vocabulary size {}",
+                idx, self.vocab_size
+            );
+            return Err(LayerError::InvalidSize);
+        }
+
+        let out_m = self.lr_params.get_2d_buf_t(TypeBuffer::Output);
+        let mut out_m = out_m.borrow_mut();
+        let out_m = out_m.deref_mut();
+
+        let ws = self.lr_params.get_2d_buf_t(TypeBuffer::Weights);
+        let ws = ws.borrow();
+        let ws = ws.deref();
+
+        let dim = self.dim;
+
+        Zip::from(inp_m.rows())
+            .and(out_m.rows_mut())
+            .par_for_each(|inp_r, mut out_r| {
+                // for each batch
+                for (i, idx) in inp_r.iter().enumerate() {
+                    out_r
+                        .slice_mut(s![i * dim..(i + 1) * dim])
+                        .assign(&ws.row(*idx as usize));
+                }
+            });
+
+        debug!("[ok] EmbeddingLayer forward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn backward(&mut self, prev_input: ParamsBlob, next_input: ParamsBlob) -> LayerBackwardResult {
+        // indices have no gradient, so frozen embedding has nothing to calc
+        if !self.trainable {
+            return Ok(vec![self.lr_params.clone()]);
+        }
+
+        let next_grad = next_layer_grad(&next_input[0]);
+
+        let prev_input = prev_input[0].get_2d_buf_t(TypeBuffer::Output);
+        let prev_input = prev_input.borrow();
+        let prev_input = prev_input.deref();
+
+        let ws_grad = self.lr_params.get_2d_buf_t(TypeBuffer::WeightsGrad);
+        let mut ws_grad = ws_grad.borrow_mut();
+        let ws_grad = ws_grad.deref_mut();
+
+        let dim = self.dim;
+        let batch_len = prev_input.nrows() as f32;
+
+        ws_grad.fill(0.0);
+
+        for (inp_r, grad_r) in prev_input.rows().into_iter().zip(next_grad.rows()) {
+            for (i, idx) in inp_r.iter().enumerate() {
+                let mut ws_grad_r = ws_grad.row_mut(*idx as usize);
+                ws_grad_r.scaled_add(
+                    1.0 / batch_len,
+                    &grad_r.slice(s![i * dim..(i + 1) * dim]),
+                );
+            }
+        }
+
+        debug!("[ok] EmbeddingLayer backward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn cpu_params(&self) -> Option<CpuParams> {
+        Some(self.lr_params.clone())
+    }
+
+    fn set_cpu_params(&mut self, lp: CpuParams) {
+        self.lr_params = lp;
+    }
+
+    fn layer_type(&self) -> &str {
+        "EmbeddingLayer"
+    }
+
+    fn is_trainable(&self) -> bool {
+        self.trainable
+    }
+
+    fn set_trainable(&mut self, trainable: bool) {
+        self.trainable = trainable;
+    }
+
+    fn trainable_bufs(&self) -> TrainableBufsIds {
+        (
+            &[TypeBuffer::Weights as i32],
+            &[TypeBuffer::WeightsGrad as i32],
+        )
+    }
+
+    fn serializable_bufs(&self) -> &[i32] {
+        &[TypeBuffer::Weights as i32]
+    }
+
+    /// Carefull this method overwrites weights and all other params
+    fn set_input_shape(&mut self, sh: &[usize]) {
+        self.input_len = sh.iter().product();
+
+        let mut lr_params = CpuParams::new(self.vocab_size, self.dim);
+        lr_params.remove_buf(TypeBuffer::NeuGrad as i32);
+        lr_params.remove_buf(TypeBuffer::Output as i32);
+
+        let out_params = CpuParams::new_only_output(self.size());
+        lr_params.insert_buf(
+            TypeBuffer::Output as i32,
+            out_params.get_param_t(TypeBuffer::Output),
+        );
+        // indices aren't differentiable, previous layer always gets zero gradient
+        lr_params.add_input_grad(self.input_len);
+
+        self.lr_params = lr_params;
+    }
+
+    fn size(&self) -> usize {
+        self.input_len * self.dim
+    }
+
+    /// Embedding is a lookup, without multiplications
+    fn forward_flops(&self) -> usize {
+        self.size()
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        vec![self.input_len, self.dim]
+    }
+
+    fn copy_layer(&self) -> Box<dyn AbstractLayer> {
+        let mut copy_l = self.clone();
+        copy_l.set_cpu_params(self.lr_params.copy());
+        Box::new(copy_l)
+    }
+
+    fn clone_layer(&self) -> Box<dyn AbstractLayer> {
+        Box::new(self.clone())
+    }
+}
+
+impl EmbeddingLayer {
+    pub fn new(vocab_size: usize, dim: usize) -> Self {
+        Self {
+            lr_params: CpuParams::empty(),
+            vocab_size,
+            dim,
+            input_len: 0,
+            trainable: true,
+        }
+    }
+
+    pub fn new_box(vocab_size: usize, dim: usize) -> Box<Self> {
+        Box::new(EmbeddingLayer::new(vocab_size, dim))
+    }
+}
+
+impl WithParams for EmbeddingLayer {
+    fn cfg(&self) -> HashMap<String, Variant> {
+        let mut cfg: HashMap<String, Variant> = HashMap::new();
+
+        cfg.insert(
+            "vocab_size".to_owned(),
+            Variant::Int(self.vocab_size as i32),
+        );
+        cfg.insert("dim".to_owned(), Variant::Int(self.dim as i32));
+        cfg.insert("trainable".to_owned(), Variant::Bool(self.trainable));
+
+        cfg
+    }
+
+    fn set_cfg(&mut self, cfg: &HashMap<String, Variant>) {
+        if let Some(Variant::Int(vocab_size)) = cfg.get("vocab_size") {
+            self.vocab_size = *vocab_size as usize;
+        }
+
+        if let Some(Variant::Int(dim)) = cfg.get("dim") {
+            self.dim = *dim as usize;
+        }
+
+        self.lr_params = CpuParams::empty();
+
+        if let Some(Variant::Bool(trainable)) = cfg.get("trainable") {
+            self.trainable = *trainable;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::grad_check::*;
+
+    #[test]
+    fn grads_of_repeated_tokens_are_summed() {
+        let mut l = EmbeddingLayer::new(6, 3);
+        let tokens = ndarray::array![[0.0, 2.0, 5.0, 2.0], [1.0, 1.0, 3.0, 4.0]];
+        check_layer_weights(&mut l, &[4], tokens);
+    }
+}
@@ -3,26 +3,26 @@ use std::ops::{Deref, DerefMut};
 
 use ndarray::{Zip, indices};
 
-use log::{debug, info};
+use log::{debug, error, info};
 
 use crate::layers::*;
+use crate::losses::*;
 use crate::cpu_params::*;
 use crate::util::*;
 
 #[derive(Clone)]
-pub struct EuclideanLossLayer<T: Fn(f32) -> f32 + Clone, TD: Fn(f32) -> f32 + Clone> {
+pub struct EuclideanLossLayer {
     pub size: usize,
     pub lr_params: CpuParams,
     pub l2_regul: f32,
     pub l1_regul: f32,
-    pub activation: Activation<T, TD>,
+    pub activation: Activation,
+    pre_activation: Array2D, // activation input, derivative is taken from it
+    loss: EuclideanLoss,
+    trainable: bool,
 }
 
-impl<T, TD> AbstractLayer for EuclideanLossLayer<T, TD>
-where
-    T: Fn(f32) -> f32 + Sync + Clone + 'static,
-    TD: Fn(f32) -> f32 + Sync + Clone + 'static,
-{
+impl AbstractLayer for EuclideanLossLayer {
     fn forward(&mut self, input: ParamsBlob) -> LayerForwardResult {
         let inp_m = input[0].get_2d_buf_t(TypeBuffer::Output);
         let inp_m = inp_m.borrow();
@@ -52,10 +52,12 @@ where
                     .and(bias_out)
                     .for_each(|out_el, in_row, bias_el| {
                         // for each "neuron"
-                        *out_el = (self.activation.func)(*in_row + bias_el);
+                        *out_el = *in_row + bias_el;
                     });
             });
 
+        self.activation.apply(out_m, &mut self.pre_activation);
+
         debug!("[ok] ErrorLayer forward()");
 
         Ok(vec![self.lr_params.clone()])
@@ -65,6 +67,7 @@ where
         &mut self,
         prev_input: ParamsBlob,
         expected_vec: Array2D,
+        loss_weights: &LossWeights,
     ) -> LayerBackwardResult {
         let prev_input = prev_input[0].get_2d_buf_t(TypeBuffer::Output);
         let prev_input = prev_input.borrow();
@@ -84,18 +87,23 @@ where
         let mut self_bias_grad = self_bias_grad.borrow_mut();
         let self_bias_grad = self_bias_grad.deref_mut();
 
-        // for each batch
-        Zip::from(self_neu_grad.rows_mut())
-            .and(expected_vec.rows())
-            .and(self_output.rows()) 
-            .par_for_each(|err_val_r, expected_r, output_r| {
-                Zip::from(err_val_r).and(expected_r).and(output_r).for_each(
-                    |err_val, expected, output| {
-                        *err_val = (expected - output) * (self.activation.func_deriv)(*output);
-                    },
-                );
+        let mut grad = self
+            .loss
+            .backward(self_output, &expected_vec, loss_weights)?;
+
+        Zip::from(&mut grad)
+            .and(&self.pre_activation)
+            .par_for_each(|grad_el, pre_act| {
+                *grad_el *= self.activation.func_deriv(*pre_act);
             });
 
+        *self_neu_grad = grad;
+
+        // frozen layer only passes gradient to previous layer with NeuGrad
+        if !self.trainable {
+            return Ok(vec![self.lr_params.clone()]);
+        }
+
         let ws_grad = self
             .lr_params
             .get_2d_buf_t(TypeBuffer::WeightsGrad);
@@ -173,6 +181,19 @@ where
         "EuclideanLossLayer"
     }
 
+    fn is_trainable(&self) -> bool {
+        self.trainable
+    }
+
+    fn set_trainable(&mut self, trainable: bool) {
+        self.trainable = trainable;
+    }
+
+    /// "mae", "rmse" and mean loss value as "loss"
+    fn metrics(&self) -> Option<&Metrics> {
+        self.loss.metrics()
+    }
+
     fn cpu_params(&self) -> Option<CpuParams> {
         Some(self.lr_params.clone())
     }
@@ -183,7 +204,7 @@ where
 
     /// Carefull this method overwrites weights and all other params
     fn set_input_shape(&mut self, sh: &[usize]) {
-        self.lr_params = CpuParams::new_with_bias(self.size, sh[0]);
+        self.lr_params = CpuParams::new_with_bias(self.size, sh.iter().product());
     }
 
     fn size(&self) -> usize {
@@ -191,7 +212,7 @@ where
     }
 
     fn copy_layer(&self) -> Box<dyn AbstractLayer> {
-        let mut copy_l = EuclideanLossLayer::new(self.size, self.activation.clone());
+        let mut copy_l = self.clone();
         copy_l.set_cpu_params(self.lr_params.copy());
         Box::new(copy_l)
     }
@@ -201,22 +222,21 @@ where
     }
 }
 
-impl<T, TD> EuclideanLossLayer<T, TD>
-where
-    T: Fn(f32) -> f32 + Clone,
-    TD: Fn(f32) -> f32 + Clone,
-{
-    pub fn new(size: usize, activation: Activation<T, TD>) -> Self {
+impl EuclideanLossLayer {
+    pub fn new(size: usize, activation: Activation) -> Self {
         Self {
             size,
             lr_params: CpuParams::empty(),
             activation,
+            pre_activation: Array2D::zeros((0, 0)),
+            loss: EuclideanLoss::new(),
             l1_regul: 0.0,
             l2_regul: 0.0,
+            trainable: true,
         }
     }
 
-    pub fn new_box(size: usize, activation: Activation<T, TD>) -> Box<Self> {
+    pub fn new_box(size: usize, activation: Activation) -> Box<Self> {
         Box::new(EuclideanLossLayer::new(size, activation))
     }
 
@@ -231,29 +251,30 @@ where
     }
 }
 
-impl<T, TD> WithParams for EuclideanLossLayer<T, TD>
-where
-    T: Fn(f32) -> f32 + Sync + Clone + 'static,
-    TD: Fn(f32) -> f32 + Sync + Clone + 'static,
-{
+impl WithParams for EuclideanLossLayer {
     fn cfg(&self) -> HashMap<String, Variant> {
         let mut cfg: HashMap<String, Variant> = HashMap::new();
 
         cfg.insert("size".to_owned(), Variant::Int(self.size as i32));
         // cfg.insert("prev_size".to_owned(), Variant::Int(self.prev_size as i32));
 
-        cfg.insert(
-            "activation".to_owned(),
-            Variant::String(self.activation.name.clone()),
-        );
+        self.activation.write_cfg(&mut cfg);
 
         cfg.insert("l2_regul".to_owned(), Variant::Float(self.l2_regul));
         cfg.insert("l1_regul".to_owned(), Variant::Float(self.l1_regul));
+        cfg.insert("trainable".to_owned(), Variant::Bool(self.trainable));
 
         cfg
     }
 
     fn set_cfg(&mut self, cfg: &HashMap<String, Variant>) {
+        if cfg.contains_key("activation") {
+            match Activation::from_cfg(cfg) {
+                Ok(activation) => self.activation = activation,
+                Err(e) => error!("[euclidean_loss] {}", e),
+            }
+        }
+
         let mut size: usize = 0;
 
         if let Some(var_size) = cfg.get("size") {
@@ -278,5 +299,9 @@ where
                 self.l2_regul = *l2_regul;
             }
         }
+
+        if let Some(Variant::Bool(trainable)) = cfg.get("trainable") {
+            self.trainable = *trainable;
+        }
     }
 }
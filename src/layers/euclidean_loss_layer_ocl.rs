@@ -3,7 +3,7 @@ use crate::cpu_params::*;
 use crate::ocl::*;
 use crate::util::*;
 
-use log::{debug, warn};
+use log::{debug, error, warn};
 
 use ocl::MemFlags;
 use ocl::{Buffer, Context, Device, Kernel, Program, Queue};
@@ -42,9 +42,13 @@ static EUCLIDEAN_LOSS_KERNEL_BWD: &'static str = r#"
                 __private int const batch_size,
                 __private int const prev_shape,
                 __private int const self_shape,
+                __private int const trainable,
                 __global const float *self_out,
                 __global const float *prev_out,
+                __global const float *ws,
+                __global const float *bias,
                 __global const float *labels,
+                __global const float *factors,
                 __global float *neu_grad, // counter
                 __global float *ws_grad)
     {
@@ -52,19 +56,30 @@ static EUCLIDEAN_LOSS_KERNEL_BWD: &'static str = r#"
 
         for (int i = 0; i < batch_size; ++i) {
             __private int inner_idx = i * self_shape + idx;
-            neu_grad[inner_idx] = (labels[inner_idx] - self_out[inner_idx]) * deriv(self_out[inner_idx]);
+
+            // activation input is recomputed for the derivative
+            __private float pre_act = bias[idx];
+
+            for (int j = 0; j < prev_shape; ++j) {
+                pre_act += ws[idx * prev_shape + j] * prev_out[i * prev_shape + j];
+            }
+
+            neu_grad[inner_idx] = factors[inner_idx] * (labels[inner_idx] - self_out[inner_idx]) * deriv(pre_act);
         }
             
-        for (int i = 0; i < prev_shape; ++i) {
-            __private float avg_grad = 0.0;
+        // frozen layer only computes neurons gradient for the previous layer
+        if (trainable) {
+            for (int i = 0; i < prev_shape; ++i) {
+                __private float avg_grad = 0.0;
 
-            for (int j = 0; j < batch_size; ++j) {
-                avg_grad += neu_grad[j * self_shape + idx] * prev_out[j * prev_shape + i];
-            }
+                for (int j = 0; j < batch_size; ++j) {
+                    avg_grad += neu_grad[j * self_shape + idx] * prev_out[j * prev_shape + i];
+                }
 
-            avg_grad = avg_grad / batch_size;
+                avg_grad = avg_grad / batch_size;
 
-            ws_grad[idx * prev_shape + i] = avg_grad;
+                ws_grad[idx * prev_shape + i] = avg_grad;
+            }
         }
     }
 "#;
@@ -78,6 +93,7 @@ pub struct EuclideanLossLayerOcl {
     ocl_kernel: Option<Kernel>,
     ocl_kernel_grad: Option<Kernel>,
     ocl_act_func: OclActivationFunc,
+    trainable: bool,
 }
 
 impl EuclideanLossLayerOcl {
@@ -91,6 +107,7 @@ impl EuclideanLossLayerOcl {
             ocl_kernel: None,
             ocl_kernel_grad: None,
             ocl_act_func: OclActivationFunc::Raw,
+            trainable: true,
         }
     }
 
@@ -115,6 +132,14 @@ impl AbstractLayer for EuclideanLossLayerOcl {
         "EuclideanLossLayerOcl"
     }
 
+    fn is_trainable(&self) -> bool {
+        self.trainable
+    }
+
+    fn set_trainable(&mut self, trainable: bool) {
+        self.trainable = trainable;
+    }
+
     fn size(&self) -> usize {
         self.size
     }
@@ -200,26 +225,11 @@ impl AbstractLayerOcl for EuclideanLossLayerOcl {
         device: Device,
         queue: Queue,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let fwd_act = match self.ocl_act_func {
-            OclActivationFunc::Sigmoid => OCL_ACTIVATION_SIGMOID,
-            OclActivationFunc::Tanh => OCL_ACTIVATION_TANH,
-            OclActivationFunc::ReLU => OCL_ACTIVATION_RELU,
-            OclActivationFunc::Raw => OCL_ACTIVATION_RAW,
-            OclActivationFunc::LeakyReLU => OCL_ACTIVATION_LEAKY_RELU,
-            _ => todo!(),
-        };
-
-        let bwd_act = match self.ocl_act_func {
-            OclActivationFunc::Sigmoid => OCL_ACTIVATION_SIGMOID_DERIV,
-            OclActivationFunc::Tanh => OCL_ACTIVATION_TANH_DERIV,
-            OclActivationFunc::ReLU => OCL_ACTIVATION_RELU_DERIV,
-            OclActivationFunc::LeakyReLU => OCL_ACTIVATION_LEAKY_RELU_DERIV,
-            OclActivationFunc::Raw => OCL_ACTIVATION_RAW_DERIV,
-            _ => todo!(),
-        };
+        let fwd_act = self.ocl_act_func.ocl_source();
+        let bwd_act = self.ocl_act_func.ocl_deriv_source();
 
-        let program_fwd = [fwd_act, EUCLIDEAN_LOSS_KERNEL_FWD].join("\n");
-        let program_bwd = [bwd_act, EUCLIDEAN_LOSS_KERNEL_BWD].join("\n");
+        let program_fwd = [fwd_act.as_str(), EUCLIDEAN_LOSS_KERNEL_FWD].join("\n");
+        let program_bwd = [bwd_act.as_str(), EUCLIDEAN_LOSS_KERNEL_BWD].join("\n");
 
         let program = Program::builder()
             .devices(device)
@@ -252,9 +262,13 @@ impl AbstractLayerOcl for EuclideanLossLayerOcl {
             .arg_named("batch_size", self.batch_size as i32)
             .arg_named("prev_shape", 0 as i32)
             .arg_named("self_shape", self.size as i32)
+            .arg_named("trainable", self.trainable as i32)
             .arg_named("self_out", None::<&Buffer<f32>>)
             .arg_named("prev_out", None::<&Buffer<f32>>)
+            .arg_named("ws", None::<&Buffer<f32>>)
+            .arg_named("bias", None::<&Buffer<f32>>)
             .arg_named("labels", None::<&Buffer<f32>>)
+            .arg_named("factors", None::<&Buffer<f32>>)
             .arg_named("neu_grad", None::<&Buffer<f32>>)
             .arg_named("ws_grad", None::<&Buffer<f32>>)
             .build()?;
@@ -310,6 +324,7 @@ impl AbstractLayerOcl for EuclideanLossLayerOcl {
         &mut self,
         prev_input: OclParamsBlob,
         expected: Array2D,
+        loss_weights: &LossWeights,
     ) -> LayerOclResult {
         let ocl_queue = self.ocl_queue.as_ref().unwrap();
 
@@ -321,6 +336,22 @@ impl AbstractLayerOcl for EuclideanLossLayerOcl {
             .build()
             .expect("[euc_ocl] Couldn't create label buffer");
 
+        let factors = match loss_weights.factors(expected.dim()) {
+            Ok(factors) => factors,
+            Err(e) => {
+                error!("{}", e);
+                return Err(LayerError::InvalidSize);
+            }
+        };
+
+        let factors_buf = Buffer::builder()
+            .queue(ocl_queue.clone())
+            .flags(MemFlags::new().read_only())
+            .len(factors.len())
+            .copy_host_slice(factors.as_slice().unwrap())
+            .build()
+            .expect("[euc_ocl] Couldn't create loss factors buffer");
+
         let self_out = self.ocl_params.get_buf_t(TypeBuffer::Output);
         let self_out = self_out.0.borrow();
 
@@ -330,6 +361,12 @@ impl AbstractLayerOcl for EuclideanLossLayerOcl {
         let self_ws_grad = self.ocl_params.get_buf_t(TypeBuffer::WeightsGrad);
         let self_ws_grad = self_ws_grad.0.borrow_mut();
 
+        let self_ws = self.ocl_params.get_buf_t(TypeBuffer::Weights);
+        let self_ws = self_ws.0.borrow();
+
+        let self_bias = self.ocl_params.get_buf_t(TypeBuffer::Bias);
+        let self_bias = self_bias.0.borrow();
+
         let prev_out = prev_input.first().unwrap().get_buf_t(TypeBuffer::Output);
         let prev_out = prev_out.0.borrow();
 
@@ -341,15 +378,27 @@ impl AbstractLayerOcl for EuclideanLossLayerOcl {
         self_kern
             .set_arg("prev_out", prev_out.deref())
             .expect("[euc_ocl] Setting param PREV_OUT failure");
+        self_kern
+            .set_arg("ws", self_ws.deref())
+            .expect("[euc_ocl] Setting param WS failure");
+        self_kern
+            .set_arg("bias", self_bias.deref())
+            .expect("[euc_ocl] Failed to set BIAS param");
         self_kern
             .set_arg("labels", &lbl_buf)
             .expect("[euc_ocl] Setting param LABELS failure");
+        self_kern
+            .set_arg("factors", &factors_buf)
+            .expect("[euc_ocl] Setting param FACTORS failure");
         self_kern
             .set_arg("neu_grad", self_neu_grad.deref())
             .expect("[euc_ocl] Setting param NEU_GRAD failure");
         self_kern
             .set_arg("ws_grad", self_ws_grad.deref())
             .expect("[euc_ocl] Setting param WS_GRAD failure");
+        self_kern
+            .set_arg("trainable", self.trainable as i32)
+            .expect("[euc_ocl] Setting param TRAINABLE failure");
 
         unsafe {
             self_kern
@@ -388,6 +437,7 @@ impl Default for EuclideanLossLayerOcl {
             ocl_kernel: None,
             ocl_kernel_grad: None,
             ocl_act_func: OclActivationFunc::Raw,
+            trainable: true,
         }
     }
 }
@@ -405,6 +455,7 @@ impl Clone for EuclideanLossLayerOcl {
             ocl_kernel_grad: None,
             ocl_act_func: self.ocl_act_func.clone(),
             ocl_queue: Some(queue.clone()),
+            trainable: self.trainable,
         }
     }
 
@@ -418,10 +469,8 @@ impl WithParams for EuclideanLossLayerOcl {
         let mut out = HashMap::new();
 
         out.insert("size".to_string(), Variant::Int(self.size as i32));
-        out.insert(
-            "activation".to_string(),
-            Variant::String(self.ocl_act_func.to_string()),
-        );
+        self.ocl_act_func.write_cfg(&mut out);
+        out.insert("trainable".to_owned(), Variant::Bool(self.trainable));
 
         out
     }
@@ -433,13 +482,15 @@ impl WithParams for EuclideanLossLayerOcl {
             }
         }
 
-        if let Some(act_f) = args.get("activation") {
-            if let Variant::String(act_f) = act_f {
-                let cvt_res = OclActivationFunc::try_from(act_f.as_str());
-                if let Ok(cvt) = cvt_res {
-                    self.ocl_act_func = cvt;
-                }
+        if args.contains_key("activation") {
+            match OclActivationFunc::from_cfg(args) {
+                Ok(act) => self.ocl_act_func = act,
+                Err(e) => error!("[euc_ocl] {}", e),
             }
         }
+
+        if let Some(Variant::Bool(trainable)) = args.get("trainable") {
+            self.trainable = *trainable;
+        }
     }
 }
@@ -2,13 +2,12 @@ use std::collections::HashMap;
 
 use ndarray::{indices, Zip};
 
-use log::debug;
-
-use rand::{thread_rng, Rng, ThreadRng};
+use log::{debug, error};
 
 use std::ops::{Deref, DerefMut};
 
-use super::abstract_layer::{AbstractLayer, LayerBackwardResult, LayerForwardResult};
+use super::abstract_layer::{next_layer_grad, AbstractLayer, LayerBackwardResult, LayerForwardResult};
+use super::dropout_layer::DropoutMask;
 use crate::cpu_params::*;
 use crate::util::*;
 
@@ -16,21 +15,19 @@ use crate::util::{Variant, WithParams};
 
 // Fully-connected layer
 #[derive(Clone)]
-pub struct FcLayer<T: Fn(f32) -> f32 + Clone, TD: Fn(f32) -> f32 + Clone> {
+pub struct FcLayer {
     pub lr_params: CpuParams,
     size: usize,
-    dropout: f32,
+    dropout: DropoutMask,
+    is_train: bool,
     l2_regul: f32,
     l1_regul: f32,
-    pub activation: Activation<T, TD>,
-    rng: ThreadRng,
+    pub activation: Activation,
+    pre_activation: Array2D, // activation input, derivative is taken from it
+    trainable: bool,
 }
 
-impl<T, TD> AbstractLayer for FcLayer<T, TD>
-where
-    T: Fn(f32) -> f32 + Sync + Clone + 'static,
-    TD: Fn(f32) -> f32 + Sync + Clone + 'static,
-{
+impl AbstractLayer for FcLayer {
     fn forward(&mut self, input: ParamsBlob) -> LayerForwardResult {
         let inp_m = input[0].get_2d_buf_t(TypeBuffer::Output);
         let inp_m = inp_m.borrow();
@@ -48,10 +45,6 @@ where
         let bias_out = bias_out.borrow();
         let bias_out = bias_out.deref();
 
-        let dropout_len = (self.size as f32 * self.dropout) as usize;
-        let dropout_n = self.rng.gen_range(0, self.size - dropout_len as usize);
-        let dropout_y = dropout_n + dropout_len;
-
         // for each input batch
         Zip::from(inp_m.rows())
             .and(out_m.rows_mut())
@@ -59,47 +52,33 @@ where
                 // for each batch
                 let mul_res = ws.dot(&inp_b);
 
-                let mut counter_neu = 0;
-
                 // for each neuron
                 Zip::from(out_b)
                     .and(&mul_res)
                     .and(bias_out)
                     .for_each(|out_el, in_row, bias_el| {
-                        // for each "neuron"
-                        if counter_neu >= dropout_n && counter_neu < dropout_y {
-                            // zero neuron
-                            *out_el = 0.0;
-                            counter_neu += 1;
-                        } else {
-                            *out_el = (self.activation.func)(in_row + bias_el);
-                            counter_neu += 1;
-                        }
+                        *out_el = in_row + bias_el;
                     });
             });
 
+        self.activation.apply(out_m, &mut self.pre_activation);
+
+        if self.is_train && self.dropout.is_enabled() {
+            self.dropout.apply(out_m);
+        }
+
         debug!("[ok] HiddenLayer forward()");
 
         Ok(vec![self.lr_params.clone()])
     }
 
     fn backward(&mut self, prev_input: ParamsBlob, next_input: ParamsBlob) -> LayerBackwardResult {
-        let next_err_vals = next_input[0].get_2d_buf_t(TypeBuffer::NeuGrad);
-        let next_err_vals = next_err_vals.borrow();
-        let next_err_vals = next_err_vals.deref();
-
-        let next_ws = next_input[0].get_2d_buf_t(TypeBuffer::Weights);
-        let next_ws = next_ws.borrow();
-        let next_ws = next_ws.deref();
+        let next_grad = next_layer_grad(&next_input[0]);
 
         let self_err_vals = self.lr_params.get_2d_buf_t(TypeBuffer::NeuGrad);
         let mut self_err_vals = self_err_vals.borrow_mut();
         let self_err_vals = self_err_vals.deref_mut();
 
-        let self_output = self.lr_params.get_2d_buf_t(TypeBuffer::Output);
-        let self_output = self_output.borrow();
-        let self_output = self_output.deref();
-
         let self_bias_grad = self.lr_params.get_1d_buf_t(TypeBuffer::BiasGrad);
         let mut self_bias_grad = self_bias_grad.borrow_mut();
         let self_bias_grad = self_bias_grad.deref_mut();
@@ -108,21 +87,35 @@ where
         let mut self_bias = self_bias.borrow_mut();
         let self_bias = self_bias.deref_mut();
 
-        Zip::from(self_err_vals.rows_mut())
-            .and(next_err_vals.rows())
-            .and(self_output.rows())
-            .par_for_each(|err_val_r, next_err_val_r, output_r| {
-                let mul_res = next_ws.t().dot(&next_err_val_r);
-
-                Zip::from(err_val_r).and(output_r).and(&mul_res).for_each(
-                    |err_val, output, col| {
-                        *err_val = (self.activation.func_deriv)(*output) * col;
-                    },
-                );
-            });
+        if self.is_train && self.dropout.is_enabled() {
+            // output was scaled by dropout mask
+            Zip::from(self_err_vals.view_mut())
+                .and(&next_grad)
+                .and(&self.pre_activation)
+                .and(self.dropout.mask())
+                .par_for_each(|err_val, col, pre_act, mask_el| {
+                    *err_val = self.activation.func_deriv(*pre_act) * col * mask_el;
+                });
+        } else {
+            Zip::from(self_err_vals.rows_mut())
+                .and(next_grad.rows())
+                .and(self.pre_activation.rows())
+                .par_for_each(|err_val_r, next_grad_r, pre_act_r| {
+                    Zip::from(err_val_r).and(pre_act_r).and(next_grad_r).for_each(
+                        |err_val, pre_act, col| {
+                            *err_val = self.activation.func_deriv(*pre_act) * col;
+                        },
+                    );
+                });
+        }
 
         debug!("[hidden layer] i am here 2");
 
+        // frozen layer only passes gradient to previous layer with NeuGrad
+        if !self.trainable {
+            return Ok(vec![self.lr_params.clone()]);
+        }
+
         // calc per-weight gradient
         // for prev_layer :
         let prev_input = prev_input[0].get_2d_buf_t(TypeBuffer::Output);
@@ -206,9 +199,21 @@ where
         "FcLayer"
     }
 
+    fn is_trainable(&self) -> bool {
+        self.trainable
+    }
+
+    fn set_trainable(&mut self, trainable: bool) {
+        self.trainable = trainable;
+    }
+
+    fn set_train_mode(&mut self, is_train: bool) {
+        self.is_train = is_train;
+    }
+
     /// Carefull this method overwrites weights and all other params
     fn set_input_shape(&mut self, sh: &[usize]) {
-        self.lr_params = CpuParams::new_with_bias(self.size, sh[0]);
+        self.lr_params = CpuParams::new_with_bias(self.size, sh.iter().product());
     }
 
     fn size(&self) -> usize {
@@ -216,9 +221,9 @@ where
     }
 
     fn copy_layer(&self) -> Box<dyn AbstractLayer> {
-        let mut copy_l = Box::new(FcLayer::new(self.size, self.activation.clone()));
+        let mut copy_l = self.clone();
         copy_l.set_cpu_params(self.lr_params.copy());
-        copy_l
+        Box::new(copy_l)
     }
 
     fn clone_layer(&self) -> Box<dyn AbstractLayer> {
@@ -226,34 +231,32 @@ where
     }
 }
 
-impl<T, TD> FcLayer<T, TD>
-where
-    T: Fn(f32) -> f32 + Clone,
-    TD: Fn(f32) -> f32 + Clone,
-{
-    pub fn new(size: usize, activation: Activation<T, TD>) -> Self {
+impl FcLayer {
+    pub fn new(size: usize, activation: Activation) -> Self {
         Self {
             size,
-            dropout: 0.0,
+            dropout: DropoutMask::new(0.0),
+            is_train: true,
             lr_params: CpuParams::empty(),
             activation,
+            pre_activation: Array2D::zeros((0, 0)),
             l2_regul: 0.0,
             l1_regul: 0.0,
-            rng: thread_rng(),
+            trainable: true,
         }
     }
 
-    pub fn new_box(size: usize, activation: Activation<T, TD>) -> Box<Self> {
+    pub fn new_box(size: usize, activation: Activation) -> Box<Self> {
         Box::new(FcLayer::new(size, activation))
     }
 
     pub fn dropout(mut self, val: f32) -> Self {
-        self.dropout = val;
+        self.dropout.rate = val;
         self
     }
 
     pub fn set_dropout(&mut self, val: f32) {
-        self.dropout = val;
+        self.dropout.rate = val;
     }
 
     pub fn l2_regularization(mut self, coef: f32) -> Self {
@@ -275,23 +278,17 @@ where
     }
 }
 
-impl<T, TD> WithParams for FcLayer<T, TD>
-where
-    T: Fn(f32) -> f32 + Sync + Clone + 'static,
-    TD: Fn(f32) -> f32 + Sync + Clone + 'static,
-{
+impl WithParams for FcLayer {
     fn cfg(&self) -> HashMap<String, Variant> {
         let mut cfg: HashMap<String, Variant> = HashMap::new();
 
         cfg.insert("size".to_owned(), Variant::Int(self.size as i32));
         // cfg.insert("prev_size".to_owned(), Variant::Int(self.prev_size as i32));
-        cfg.insert(
-            "activation".to_owned(),
-            Variant::String(self.activation.name.clone()),
-        );
+        self.activation.write_cfg(&mut cfg);
         cfg.insert("l2_regul".to_owned(), Variant::Float(self.l2_regul));
         cfg.insert("l1_regul".to_owned(), Variant::Float(self.l1_regul));
-        cfg.insert("dropout".to_owned(), Variant::Float(self.dropout));
+        cfg.insert("dropout".to_owned(), Variant::Float(self.dropout.rate));
+        cfg.insert("trainable".to_owned(), Variant::Bool(self.trainable));
 
         cfg
     }
@@ -310,9 +307,16 @@ where
             self.lr_params = CpuParams::empty();
         }
 
+        if cfg.contains_key("activation") {
+            match Activation::from_cfg(cfg) {
+                Ok(activation) => self.activation = activation,
+                Err(e) => error!("[fc_layer] {}", e),
+            }
+        }
+
         if let Some(dropout) = cfg.get("dropout") {
             if let Variant::Float(dropout) = dropout {
-                self.dropout = *dropout;
+                self.dropout.rate = *dropout;
             }
         }
 
@@ -327,5 +331,9 @@ where
                 self.l2_regul = *l2_regul;
             }
         }
+
+        if let Some(Variant::Bool(trainable)) = cfg.get("trainable") {
+            self.trainable = *trainable;
+        }
     }
 }
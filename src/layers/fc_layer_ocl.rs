@@ -3,7 +3,7 @@ use crate::cpu_params::*;
 use crate::ocl::*;
 use crate::util::*;
 
-use log::{debug, warn};
+use log::{debug, error, warn};
 
 use rand::{thread_rng, Rng, ThreadRng};
 
@@ -46,10 +46,13 @@ static FC_LAYER_KERNEL_BWD: &'static str = r#"
                 __private int const prev_shape,
                 __private int const next_shape,
                 __private int const self_shape,
+                __private int const trainable,
                 __global const float *self_out,
                 __global const float *next_grad,
                 __global const float *next_ws,
                 __global const float *prev_out,
+                __global const float *ws,
+                __global const float *bias,
                 __global float *neu_grad,
                 __global float *ws_grad)
     {
@@ -62,19 +65,29 @@ static FC_LAYER_KERNEL_BWD: &'static str = r#"
                 sum_err += next_grad[i * next_shape + j] * next_ws[self_shape * j + idx];
             }
 
-            neu_grad[i * self_shape + idx] = sum_err * deriv(self_out[i * self_shape + idx]);
+            // activation input is recomputed for the derivative
+            __private float pre_act = bias[idx];
+
+            for (int j = 0; j < prev_shape; ++j) {
+                pre_act += ws[idx * prev_shape + j] * prev_out[i * prev_shape + j];
+            }
+
+            neu_grad[i * self_shape + idx] = sum_err * deriv(pre_act);
         }
 
-        for (int i = 0; i < prev_shape; ++i) {
-            __private float avg_grad = 0.0;
+        // frozen layer only computes neurons gradient for the previous layer
+        if (trainable) {
+            for (int i = 0; i < prev_shape; ++i) {
+                __private float avg_grad = 0.0;
+
+                for (int j = 0; j < batch_size; ++j) {
+                    avg_grad += neu_grad[j * self_shape + idx] * prev_out[j * prev_shape + i];
+                }
+
+                avg_grad = avg_grad / batch_size;
 
-            for (int j = 0; j < batch_size; ++j) {
-                avg_grad += neu_grad[j * self_shape + idx] * prev_out[j * prev_shape + i];
+                ws_grad[idx * prev_shape + i] = avg_grad;
             }
-    
-            avg_grad = avg_grad / batch_size;
-    
-            ws_grad[idx * prev_shape + i] = avg_grad;
         }
     }
 "#;
@@ -92,6 +105,7 @@ pub struct FcLayerOcl {
     ocl_kernel_grad: Option<Kernel>,
     ocl_queue: Option<Queue>,
     ocl_act_func: OclActivationFunc,
+    trainable: bool,
 }
 
 impl FcLayerOcl {
@@ -107,6 +121,7 @@ impl FcLayerOcl {
             ocl_act_func: act,
             dropout: 0.0,
             rng: thread_rng(),
+            trainable: true,
         }
     }
 
@@ -133,6 +148,14 @@ impl AbstractLayer for FcLayerOcl {
         "FcLayerOcl"
     }
 
+    fn is_trainable(&self) -> bool {
+        self.trainable
+    }
+
+    fn set_trainable(&mut self, trainable: bool) {
+        self.trainable = trainable;
+    }
+
     fn size(&self) -> usize {
         self.size
     }
@@ -213,24 +236,11 @@ impl AbstractLayerOcl for FcLayerOcl {
         device: Device,
         queue: Queue,
     ) -> Result<(), Box<dyn Error>> {
-        let fwd_act = match self.ocl_act_func {
-            OclActivationFunc::Sigmoid => OCL_ACTIVATION_SIGMOID,
-            OclActivationFunc::Tanh => OCL_ACTIVATION_TANH,
-            OclActivationFunc::ReLU => OCL_ACTIVATION_RELU,
-            OclActivationFunc::Raw => OCL_ACTIVATION_RAW,
-            OclActivationFunc::LeakyReLU => OCL_ACTIVATION_LEAKY_RELU,
-        };
-
-        let bwd_act = match self.ocl_act_func {
-            OclActivationFunc::Sigmoid => OCL_ACTIVATION_SIGMOID_DERIV,
-            OclActivationFunc::Tanh => OCL_ACTIVATION_TANH_DERIV,
-            OclActivationFunc::ReLU => OCL_ACTIVATION_RELU_DERIV,
-            OclActivationFunc::LeakyReLU => OCL_ACTIVATION_LEAKY_RELU_DERIV,
-            OclActivationFunc::Raw => OCL_ACTIVATION_RAW_DERIV,
-        };
-
-        let program_fwd = [fwd_act, FC_LAYER_KERNEL_FWD].join("\n");
-        let program_bwd = [bwd_act, FC_LAYER_KERNEL_BWD].join("\n");
+        let fwd_act = self.ocl_act_func.ocl_source();
+        let bwd_act = self.ocl_act_func.ocl_deriv_source();
+
+        let program_fwd = [fwd_act.as_str(), FC_LAYER_KERNEL_FWD].join("\n");
+        let program_bwd = [bwd_act.as_str(), FC_LAYER_KERNEL_BWD].join("\n");
 
         let program = Program::builder()
             .devices(device)
@@ -266,10 +276,13 @@ impl AbstractLayerOcl for FcLayerOcl {
             .arg_named("prev_shape", 0 as i32)
             .arg_named("next_shape", 0 as i32)
             .arg_named("self_shape", self.size as i32)
+            .arg_named("trainable", self.trainable as i32)
             .arg_named("self_out", None::<&Buffer<f32>>)
             .arg_named("next_grad", None::<&Buffer<f32>>)
             .arg_named("next_ws", None::<&Buffer<f32>>)
             .arg_named("prev_out", None::<&Buffer<f32>>)
+            .arg_named("ws", None::<&Buffer<f32>>)
+            .arg_named("bias", None::<&Buffer<f32>>)
             .arg_named("neu_grad", None::<&Buffer<f32>>)
             .arg_named("ws_grad", None::<&Buffer<f32>>)
             .build()?;
@@ -345,6 +358,12 @@ impl AbstractLayerOcl for FcLayerOcl {
         let self_ws_grad = self.ocl_params.get_buf_t(TypeBuffer::WeightsGrad);
         let self_ws_grad = self_ws_grad.0.borrow_mut();
 
+        let self_ws = self.ocl_params.get_buf_t(TypeBuffer::Weights);
+        let self_ws = self_ws.0.borrow();
+
+        let self_bias = self.ocl_params.get_buf_t(TypeBuffer::Bias);
+        let self_bias = self_bias.0.borrow();
+
         let prev_out = prev_input.first().unwrap().get_buf_t(TypeBuffer::Output);
         let prev_out = prev_out.0.borrow();
 
@@ -367,9 +386,18 @@ impl AbstractLayerOcl for FcLayerOcl {
         self_kern
             .set_arg("ws_grad", &*self_ws_grad)
             .expect("[fc_ocl] Setting param WS_GRAD failure");
+        self_kern
+            .set_arg("trainable", self.trainable as i32)
+            .expect("[fc_ocl] Setting param TRAINABLE failure");
         self_kern
             .set_arg("prev_out", &*prev_out)
             .expect("[fc_ocl] Setting param PREV_OUT failure");
+        self_kern
+            .set_arg("ws", &*self_ws)
+            .expect("[fc_ocl] Setting param WS failure");
+        self_kern
+            .set_arg("bias", &*self_bias)
+            .expect("[fc_ocl] Failed to set BIAS param");
         self_kern
             .set_arg("next_ws", &*next_ws)
             .expect("[fc_ocl] Setting param NEXT_WS failure");
@@ -423,6 +451,7 @@ impl Default for FcLayerOcl {
 
             dropout: 0.0,
             rng: thread_rng(),
+            trainable: true,
         }
     }
 }
@@ -443,6 +472,7 @@ impl Clone for FcLayerOcl {
 
             dropout: 0.0,
             rng: thread_rng(),
+            trainable: self.trainable,
         }
     }
 
@@ -456,10 +486,8 @@ impl WithParams for FcLayerOcl {
         let mut out = HashMap::new();
 
         out.insert("size".to_string(), Variant::Int(self.size as i32));
-        out.insert(
-            "activation".to_string(),
-            Variant::String(self.ocl_act_func.to_string()),
-        );
+        self.ocl_act_func.write_cfg(&mut out);
+        out.insert("trainable".to_owned(), Variant::Bool(self.trainable));
 
         out
     }
@@ -471,14 +499,15 @@ impl WithParams for FcLayerOcl {
             }
         }
 
-        if let Some(act_f) = args.get("activation") {
-            if let Variant::String(act_f) = act_f {
-                let cvt_res = OclActivationFunc::try_from(act_f.as_str());
-
-                if let Ok(cvt) = cvt_res {
-                    self.ocl_act_func = cvt;
-                }
+        if args.contains_key("activation") {
+            match OclActivationFunc::from_cfg(args) {
+                Ok(act) => self.ocl_act_func = act,
+                Err(e) => error!("[fc_ocl] {}", e),
             }
         }
+
+        if let Some(Variant::Bool(trainable)) = args.get("trainable") {
+            self.trainable = *trainable;
+        }
     }
 }
@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+
+use log::debug;
+
+use super::abstract_layer::{
+    AbstractLayer, LayerBackwardResult, LayerForwardResult, TrainableBufsIds,
+};
+use super::reshape_layer::{share_input, share_next_grad};
+use crate::cpu_params::*;
+use crate::util::*;
+
+/// Flattens multi-dimensional input shape to [size], values aren't copied
+#[derive(Clone, Default)]
+pub struct FlattenLayer {
+    pub lr_params: CpuParams,
+    size: usize,
+}
+
+impl AbstractLayer for FlattenLayer {
+    fn forward(&mut self, input: ParamsBlob) -> LayerForwardResult {
+        share_input(&mut self.lr_params, &input[0], self.size)?;
+
+        debug!("[ok] FlattenLayer forward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn backward(&mut self, _prev_input: ParamsBlob, next_input: ParamsBlob) -> LayerBackwardResult {
+        share_next_grad(&mut self.lr_params, &next_input[0]);
+
+        debug!("[ok] FlattenLayer backward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn cpu_params(&self) -> Option<CpuParams> {
+        Some(self.lr_params.clone())
+    }
+
+    fn set_cpu_params(&mut self, lp: CpuParams) {
+        self.lr_params = lp;
+    }
+
+    fn layer_type(&self) -> &str {
+        "FlattenLayer"
+    }
+
+    fn trainable_bufs(&self) -> TrainableBufsIds {
+        (&[], &[])
+    }
+
+    fn serializable_bufs(&self) -> &[i32] {
+        &[]
+    }
+
+    fn set_input_shape(&mut self, sh: &[usize]) {
+        self.size = sh.iter().product();
+
+        self.lr_params = CpuParams::new_only_output(self.size);
+        self.lr_params.add_input_grad(self.size);
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Output buffer of the previous layer is shared, no computation is done
+    fn forward_flops(&self) -> usize {
+        0
+    }
+
+    fn copy_layer(&self) -> Box<dyn AbstractLayer> {
+        let mut copy_l = self.clone();
+        copy_l.set_cpu_params(self.lr_params.copy());
+        Box::new(copy_l)
+    }
+
+    fn clone_layer(&self) -> Box<dyn AbstractLayer> {
+        Box::new(self.clone())
+    }
+}
+
+impl FlattenLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn new_box() -> Box<Self> {
+        Box::new(FlattenLayer::new())
+    }
+}
+
+impl WithParams for FlattenLayer {
+    fn cfg(&self) -> HashMap<String, Variant> {
+        HashMap::new()
+    }
+
+    fn set_cfg(&mut self, _cfg: &HashMap<String, Variant>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::grad_check::*;
+
+    #[test]
+    fn shared_buffers_pass_grad_through() {
+        let mut l = FlattenLayer::new();
+        check_layer(&mut l, &[2, 3], test_values(2, 6, 1));
+    }
+}
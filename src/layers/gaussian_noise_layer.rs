@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+
+use ndarray_rand::rand_distr::Normal;
+use ndarray_rand::RandomExt;
+
+use log::{debug, error};
+
+use super::abstract_layer::{
+    next_layer_grad, AbstractLayer, LayerBackwardResult, LayerError, LayerForwardResult,
+    TrainableBufsIds,
+};
+use crate::cpu_params::*;
+use crate::util::*;
+
+/// Adds zero-mean gaussian noise with given stddev while training, identity in eval mode
+#[derive(Clone)]
+pub struct GaussianNoiseLayer {
+    pub lr_params: CpuParams,
+    shape: Vec<usize>,
+    size: usize,
+    stddev: f32,
+    is_train: bool,
+}
+
+impl AbstractLayer for GaussianNoiseLayer {
+    fn forward(&mut self, input: ParamsBlob) -> LayerForwardResult {
+        let inp_m = input[0].get_2d_buf_t(TypeBuffer::Output);
+        let inp_m = inp_m.borrow();
+        let inp_m = inp_m.deref();
+
+        if inp_m.ncols() != self.size {
+            error!(
+                "Invalid input size for GaussianNoiseLayer : {}, expected : {}",
+                inp_m.ncols(),
+                self.size
+            );
+            return Err(LayerError::InvalidSize);
+        }
+
+        let out_m = self.lr_params.get_2d_buf_t(TypeBuffer::Output);
+        let mut out_m = out_m.borrow_mut();
+        let out_m = out_m.deref_mut();
+
+        out_m.assign(inp_m);
+
+        if self.is_train && self.stddev > 0.0 {
+            *out_m += &Array2D::random(inp_m.dim(), Normal::new(0.0, self.stddev).unwrap());
+        }
+
+        debug!("[ok] GaussianNoiseLayer forward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn backward(&mut self, _prev_input: ParamsBlob, next_input: ParamsBlob) -> LayerBackwardResult {
+        let next_grad = next_layer_grad(&next_input[0]);
+
+        let inp_grad = self.lr_params.get_2d_buf_t(TypeBuffer::InputGrad);
+        let mut inp_grad = inp_grad.borrow_mut();
+
+        inp_grad.assign(&next_grad);
+
+        debug!("[ok] GaussianNoiseLayer backward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn cpu_params(&self) -> Option<CpuParams> {
+        Some(self.lr_params.clone())
+    }
+
+    fn set_cpu_params(&mut self, lp: CpuParams) {
+        self.lr_params = lp;
+    }
+
+    fn layer_type(&self) -> &str {
+        "GaussianNoiseLayer"
+    }
+
+    fn set_train_mode(&mut self, is_train: bool) {
+        self.is_train = is_train;
+    }
+
+    fn trainable_bufs(&self) -> TrainableBufsIds {
+        (&[], &[])
+    }
+
+    fn serializable_bufs(&self) -> &[i32] {
+        &[]
+    }
+
+    fn set_input_shape(&mut self, sh: &[usize]) {
+        self.shape = sh.to_vec();
+        self.size = sh.iter().product();
+
+        self.lr_params = CpuParams::new_only_output(self.size);
+        self.lr_params.add_input_grad(self.size);
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        self.shape.clone()
+    }
+
+    fn copy_layer(&self) -> Box<dyn AbstractLayer> {
+        let mut copy_l = self.clone();
+        copy_l.set_cpu_params(self.lr_params.copy());
+        Box::new(copy_l)
+    }
+
+    fn clone_layer(&self) -> Box<dyn AbstractLayer> {
+        Box::new(self.clone())
+    }
+}
+
+impl GaussianNoiseLayer {
+    pub fn new(stddev: f32) -> Self {
+        Self {
+            lr_params: CpuParams::empty(),
+            shape: vec![0],
+            size: 0,
+            stddev,
+            is_train: true,
+        }
+    }
+
+    pub fn new_box(stddev: f32) -> Box<Self> {
+        Box::new(GaussianNoiseLayer::new(stddev))
+    }
+}
+
+impl WithParams for GaussianNoiseLayer {
+    fn cfg(&self) -> HashMap<String, Variant> {
+        let mut cfg: HashMap<String, Variant> = HashMap::new();
+
+        cfg.insert("stddev".to_owned(), Variant::Float(self.stddev));
+
+        cfg
+    }
+
+    fn set_cfg(&mut self, cfg: &HashMap<String, Variant>) {
+        if let Some(Variant::Float(stddev)) = cfg.get("stddev") {
+            self.stddev = *stddev;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::grad_check::*;
+
+    #[test]
+    fn eval_mode_passes_grad_through() {
+        let mut l = GaussianNoiseLayer::new(0.1);
+        l.set_train_mode(false);
+        check_layer(&mut l, &[6], test_values(2, 6, 1));
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+
+use ndarray::Zip;
+
+use log::{debug, error};
+
+use super::abstract_layer::{
+    next_layer_grad, AbstractLayer, LayerBackwardResult, LayerError, LayerForwardResult,
+    TrainableBufsIds,
+};
+use crate::cpu_params::*;
+use crate::util::*;
+
+/// Averages each channel of (channels, height, width) input to a single value,
+/// output shape is [channels]
+#[derive(Clone, Default)]
+pub struct GlobalAvgPoolLayer {
+    pub lr_params: CpuParams,
+    channels: usize,
+    spatial_size: usize, // height * width
+}
+
+impl AbstractLayer for GlobalAvgPoolLayer {
+    fn forward(&mut self, input: ParamsBlob) -> LayerForwardResult {
+        let inp_m = input[0].get_2d_buf_t(TypeBuffer::Output);
+        let inp_m = inp_m.borrow();
+        let inp_m = inp_m.deref();
+
+        if self.spatial_size == 0 || inp_m.ncols() != self.channels * self.spatial_size {
+            error!(
+                "Invalid input size for GlobalAvgPoolLayer : {}, expected : {}",
+                inp_m.ncols(),
+                self.channels * self.spatial_size
+            );
+            return Err(LayerError::InvalidSize);
+        }
+
+        let out_m = self.lr_params.get_2d_buf_t(TypeBuffer::Output);
+        let mut out_m = out_m.borrow_mut();
+        let out_m = out_m.deref_mut();
+
+        let spatial_size = self.spatial_size;
+
+        Zip::from(inp_m.rows())
+            .and(out_m.rows_mut())
+            .par_for_each(|inp_r, out_r| {
+                // for each batch
+                Zip::from(inp_r.exact_chunks(spatial_size))
+                    .and(out_r)
+                    .for_each(|inp_ch, out_el| {
+                        *out_el = inp_ch.sum() / spatial_size as f32;
+                    });
+            });
+
+        debug!("[ok] GlobalAvgPoolLayer forward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn backward(&mut self, _prev_input: ParamsBlob, next_input: ParamsBlob) -> LayerBackwardResult {
+        let next_grad = next_layer_grad(&next_input[0]);
+
+        let inp_grad = self.lr_params.get_2d_buf_t(TypeBuffer::InputGrad);
+        let mut inp_grad = inp_grad.borrow_mut();
+        let inp_grad = inp_grad.deref_mut();
+
+        let spatial_size = self.spatial_size;
+
+        Zip::from(inp_grad.rows_mut())
+            .and(next_grad.rows())
+            .par_for_each(|mut inp_grad_r, next_grad_r| {
+                Zip::from(inp_grad_r.exact_chunks_mut(spatial_size))
+                    .and(next_grad_r)
+                    .for_each(|mut inp_grad_ch, grad| {
+                        inp_grad_ch.fill(grad / spatial_size as f32);
+                    });
+            });
+
+        debug!("[ok] GlobalAvgPoolLayer backward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn cpu_params(&self) -> Option<CpuParams> {
+        Some(self.lr_params.clone())
+    }
+
+    fn set_cpu_params(&mut self, lp: CpuParams) {
+        self.lr_params = lp;
+    }
+
+    fn layer_type(&self) -> &str {
+        "GlobalAvgPoolLayer"
+    }
+
+    fn trainable_bufs(&self) -> TrainableBufsIds {
+        (&[], &[])
+    }
+
+    fn serializable_bufs(&self) -> &[i32] {
+        &[]
+    }
+
+    /// Accepts only [channels, height, width] shape
+    fn set_input_shape(&mut self, sh: &[usize]) {
+        if sh.len() != 3 {
+            error!(
+                "GlobalAvgPoolLayer requires [channels, height, width] input shape, got {:?}",
+                sh
+            );
+            return;
+        }
+
+        self.channels = sh[0];
+        self.spatial_size = sh[1] * sh[2];
+
+        self.lr_params = CpuParams::new_only_output(self.channels);
+        self.lr_params.add_input_grad(self.channels * self.spatial_size);
+    }
+
+    fn size(&self) -> usize {
+        self.channels
+    }
+
+    fn forward_flops(&self) -> usize {
+        self.channels * self.spatial_size
+    }
+
+    fn copy_layer(&self) -> Box<dyn AbstractLayer> {
+        let mut copy_l = self.clone();
+        copy_l.set_cpu_params(self.lr_params.copy());
+        Box::new(copy_l)
+    }
+
+    fn clone_layer(&self) -> Box<dyn AbstractLayer> {
+        Box::new(self.clone())
+    }
+}
+
+impl GlobalAvgPoolLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn new_box() -> Box<Self> {
+        Box::new(GlobalAvgPoolLayer::new())
+    }
+}
+
+impl WithParams for GlobalAvgPoolLayer {
+    fn cfg(&self) -> HashMap<String, Variant> {
+        HashMap::new()
+    }
+
+    fn set_cfg(&mut self, _cfg: &HashMap<String, Variant>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::grad_check::*;
+
+    #[test]
+    fn grads_match_numeric() {
+        let mut l = GlobalAvgPoolLayer::new();
+        check_layer(&mut l, &[3, 2, 2], test_values(2, 12, 1));
+    }
+}
@@ -0,0 +1,250 @@
+//! Finite-difference checks of the backward pass for layers and models tests.
+//! Layers keep negative gradients : InputGrad and NeuGrad are -dJ/dx summed over the batch,
+//! trainable gradients are -dJ/dw averaged over the batch
+
+pub(crate) use super::sublayers::{grad_params, output_params};
+use crate::cpu_params::*;
+use crate::layers::*;
+use crate::models::Model;
+use crate::util::*;
+
+const EPS: f32 = 1e-3;
+// values checked in each buffer
+const CHECKS_PER_BUF: usize = 8;
+
+/// Deterministic test values in [-1, 1], different for each seed
+pub(crate) fn test_values(rows: usize, cols: usize, seed: usize) -> Array2D {
+    Array2D::from_shape_fn((rows, cols), |(r, c)| {
+        ((r * cols + c + seed * 101) as f32 * 1.37 + 0.5).sin()
+    })
+}
+
+fn flat_values(p: &VariantParamArc) -> Vec<f32> {
+    match p {
+        VariantParamArc::Array1(arr) => arr.borrow().iter().copied().collect(),
+        VariantParamArc::Array2(arr) => arr.borrow().iter().copied().collect(),
+    }
+}
+
+fn add_to_value(p: &VariantParamArc, idx: usize, delta: f32) {
+    match p {
+        VariantParamArc::Array1(arr) => *arr.borrow_mut().iter_mut().nth(idx).unwrap() += delta,
+        VariantParamArc::Array2(arr) => *arr.borrow_mut().iter_mut().nth(idx).unwrap() += delta,
+    }
+}
+
+fn assert_close(what: &str, idx: usize, numeric: f32, analytic: f32) {
+    let tol = 1e-2 + 2e-2 * numeric.abs().max(analytic.abs());
+
+    assert!(
+        (numeric - analytic).abs() <= tol,
+        "{} [{}] : numeric gradient {}, analytic {}",
+        what,
+        idx,
+        numeric,
+        analytic
+    );
+}
+
+/// One-sided differences disagree when the step crosses a kink of the objective, e.g. Relu at zero
+fn is_kink(j_minus: f32, j: f32, j_plus: f32) -> bool {
+    let (d_plus, d_minus) = ((j_plus - j) / EPS, (j - j_minus) / EPS);
+
+    (d_plus - d_minus).abs() > 5e-2 + 0.2 * d_plus.abs().max(d_minus.abs())
+}
+
+/// Compares the gradient of the value with central difference of the objective.
+/// grad_scale converts stored gradient to dJ/dx
+fn check_values(
+    what: &str,
+    value: &VariantParamArc,
+    grad: &[f32],
+    grad_scale: f32,
+    objective: &mut dyn FnMut() -> f32,
+) {
+    let step = grad.len() / CHECKS_PER_BUF + 1;
+
+    for idx in (0..grad.len()).step_by(step) {
+        let j = objective();
+        add_to_value(value, idx, EPS);
+        let j_plus = objective();
+        add_to_value(value, idx, -2.0 * EPS);
+        let j_minus = objective();
+        add_to_value(value, idx, EPS);
+
+        // the gradient isn't defined there, randomly initialized weights may hit it
+        if is_kink(j_minus, j, j_plus) {
+            continue;
+        }
+
+        let numeric = (j_plus - j_minus) / (2.0 * EPS);
+        assert_close(what, idx, numeric, grad[idx] * grad_scale);
+    }
+}
+
+/// Checks trainable gradients of the layers, which must be computed by the last backward pass
+fn check_trainable(
+    layers: &[(String, CpuParams, Vec<i32>, Vec<i32>)],
+    batch_size: usize,
+    objective: &mut dyn FnMut() -> f32,
+) {
+    // backward results are saved before the objective reruns forward pass
+    let grads: Vec<Vec<Vec<f32>>> = layers
+        .iter()
+        .map(|(_, lp, _, grad_ids)| {
+            grad_ids
+                .iter()
+                .map(|id| flat_values(&lp.get_param(*id)))
+                .collect()
+        })
+        .collect();
+
+    for ((name, lp, buf_ids, _), grads) in layers.iter().zip(grads.iter()) {
+        for (id, grad) in buf_ids.iter().zip(grads.iter()) {
+            let what = format!("{} buffer {}", name, id);
+            check_values(
+                &what,
+                &lp.get_param(*id),
+                grad,
+                -(batch_size as f32),
+                objective,
+            );
+        }
+    }
+}
+
+fn trainable_of(l: &dyn AbstractLayer) -> (String, CpuParams, Vec<i32>, Vec<i32>) {
+    let (bufs, grads) = l.trainable_bufs();
+    (
+        l.layer_type().to_owned(),
+        l.cpu_params().unwrap(),
+        bufs.to_vec(),
+        grads.to_vec(),
+    )
+}
+
+fn layer_output(out: &ParamsBlob) -> Array2D {
+    out[0].get_2d_buf_t(TypeBuffer::Output).borrow().clone()
+}
+
+/// Checks gradients of J = sum(output * R) with respect to layer params and input
+pub(crate) fn check_layer(layer: &mut dyn AbstractLayer, input_shape: &[usize], input: Array2D) {
+    check_layer_grads(layer, input_shape, input, true);
+}
+
+/// Same as check_layer, but input gradient isn't checked, e.g. for indices input
+pub(crate) fn check_layer_weights(
+    layer: &mut dyn AbstractLayer,
+    input_shape: &[usize],
+    input: Array2D,
+) {
+    check_layer_grads(layer, input_shape, input, false);
+}
+
+fn check_layer_grads(
+    layer: &mut dyn AbstractLayer,
+    input_shape: &[usize],
+    input: Array2D,
+    check_input: bool,
+) {
+    let batch_size = input.nrows();
+
+    layer.set_input_shape(input_shape);
+    layer.set_batch_size(batch_size);
+
+    let inp = output_params(input);
+    let out = layer.forward(vec![inp.clone()]).unwrap();
+    let r = test_values(batch_size, layer_output(&out).ncols(), 7);
+
+    let back = layer
+        .backward(vec![inp.clone()], vec![grad_params(-&r)])
+        .unwrap();
+    let inp_grad: Vec<f32> = next_layer_grad(&back[0]).iter().copied().collect();
+
+    let trainable = vec![trainable_of(layer)];
+    let what = format!("{} input", layer.layer_type());
+
+    let mut objective = || {
+        let out = layer.forward(vec![inp.clone()]).unwrap();
+        (layer_output(&out) * &r).sum()
+    };
+
+    check_trainable(&trainable, batch_size, &mut objective);
+
+    if check_input {
+        let inp_buf = inp.get_param_t(TypeBuffer::Output);
+        check_values(&what, &inp_buf, &inp_grad, -1.0, &mut objective);
+    }
+}
+
+/// Checks gradients of the loss layer with loss value for each output,
+/// J is the loss summed over the batch
+pub(crate) fn check_loss_layer(
+    layer: &mut dyn AbstractLayer,
+    input: Array2D,
+    expected: Array2D,
+    loss_weights: &LossWeights,
+) {
+    check_loss_layer_grads(layer, input, expected, loss_weights, true);
+}
+
+/// Same as check_loss_layer for losses with single value for each sample, e.g. cross-entropy
+pub(crate) fn check_sample_loss_layer(
+    layer: &mut dyn AbstractLayer,
+    input: Array2D,
+    expected: Array2D,
+    loss_weights: &LossWeights,
+) {
+    check_loss_layer_grads(layer, input, expected, loss_weights, false);
+}
+
+fn check_loss_layer_grads(
+    layer: &mut dyn AbstractLayer,
+    input: Array2D,
+    expected: Array2D,
+    loss_weights: &LossWeights,
+    loss_per_output: bool,
+) {
+    let batch_size = input.nrows();
+
+    layer.set_input_shape(&[input.ncols()]);
+    layer.set_batch_size(batch_size);
+
+    let inp = output_params(input);
+    let inp_buf = inp.get_param_t(TypeBuffer::Output);
+    let trainable = vec![trainable_of(layer)];
+    let what = format!("{} input", layer.layer_type());
+
+    let mut loss_sum = || {
+        layer.forward(vec![inp.clone()]).unwrap();
+        let back = layer
+            .backward_output(vec![inp.clone()], expected.clone(), loss_weights)
+            .unwrap();
+        let losses_cnt = match loss_per_output {
+            true => back[0].get_2d_buf_t(TypeBuffer::Output).borrow().len(),
+            false => batch_size,
+        };
+
+        layer.metrics().unwrap()["loss"] as f32 * losses_cnt as f32
+    };
+
+    loss_sum();
+
+    let inp_grad: Vec<f32> = next_layer_grad(&trainable[0].1).iter().copied().collect();
+
+    check_trainable(&trainable, batch_size, &mut loss_sum);
+    check_values(&what, &inp_buf, &inp_grad, -1.0, &mut loss_sum);
+}
+
+/// Checks gradients of all trainable model layers after backpropagate,
+/// objective runs feedforward and returns the loss summed over the batch
+pub(crate) fn check_model<M: Model>(mdl: &mut M, mut objective: impl FnMut(&mut M) -> f32) {
+    let trainable: Vec<_> = (0..mdl.layers_count())
+        .map(|id| mdl.layer(id))
+        .filter(|l| l.is_trainable() && !l.trainable_bufs().0.is_empty())
+        .map(|l| trainable_of(l.as_ref()))
+        .collect();
+
+    let batch_size = mdl.batch_size();
+    check_trainable(&trainable, batch_size, &mut || objective(mdl));
+}
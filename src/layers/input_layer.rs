@@ -9,6 +9,7 @@ use crate::util::*;
 pub struct InputLayer {
     pub input_size: usize,
     pub lr_params: CpuParams,
+    shape: Option<(usize, usize, usize)>, // (channels, height, width) for images
 }
 
 impl AbstractLayer for InputLayer {
@@ -45,8 +46,22 @@ impl AbstractLayer for InputLayer {
         self.input_size
     }
 
+    /// Input data is only copied
+    fn forward_flops(&self) -> usize {
+        0
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        if let Some((c, h, w)) = self.shape {
+            return vec![c, h, w];
+        }
+
+        vec![self.input_size]
+    }
+
     fn copy_layer(&self) -> Box<dyn AbstractLayer> {
         let mut copy_l = InputLayer::new(self.input_size);
+        copy_l.shape = self.shape;
         copy_l.set_cpu_params(self.lr_params.copy());
         Box::new(copy_l)
     }
@@ -69,9 +84,17 @@ impl InputLayer {
         Self {
             input_size,
             lr_params: CpuParams::new_only_output(input_size),
+            shape: None,
         }
     }
 
+    /// Input of (channels, height, width) images, flattened to a row per sample
+    pub fn new_image(channels: usize, height: usize, width: usize) -> Self {
+        let mut l = InputLayer::new(channels * height * width);
+        l.shape = Some((channels, height, width));
+        l
+    }
+
     pub fn new_box(size: usize) -> Box<Self> {
         Box::new(InputLayer::new(size))
     }
@@ -83,6 +106,12 @@ impl WithParams for InputLayer {
 
         cfg.insert("size".to_owned(), Variant::Int(self.input_size as i32));
 
+        if let Some((c, h, w)) = self.shape {
+            cfg.insert("channels".to_owned(), Variant::Int(c as i32));
+            cfg.insert("height".to_owned(), Variant::Int(h as i32));
+            cfg.insert("width".to_owned(), Variant::Int(w as i32));
+        }
+
         cfg
     }
 
@@ -97,5 +126,20 @@ impl WithParams for InputLayer {
             self.input_size = size;
             self.lr_params = CpuParams::new_only_output(self.input_size);
         }
+
+        if let (Some(Variant::Int(c)), Some(Variant::Int(h)), Some(Variant::Int(w))) =
+            (cfg.get("channels"), cfg.get("height"), cfg.get("width"))
+        {
+            let (c, h, w) = (*c as usize, *h as usize, *w as usize);
+
+            if c * h * w == self.input_size {
+                self.shape = Some((c, h, w));
+            } else {
+                error!(
+                    "InputLayer shape {}x{}x{} doesn't match size {}",
+                    c, h, w, self.input_size
+                );
+            }
+        }
     }
 }
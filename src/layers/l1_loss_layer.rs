@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+
+use ndarray::Zip;
+
+use log::{debug, error};
+
+use crate::cpu_params::*;
+use crate::layers::*;
+use crate::losses::*;
+use crate::util::*;
+
+/// Mean absolute error loss layer, less sensitive to outliers than EuclideanLossLayer
+#[derive(Clone)]
+pub struct L1LossLayer {
+    pub size: usize,
+    pub lr_params: CpuParams,
+    pub l2_regul: f32,
+    pub l1_regul: f32,
+    pub activation: Activation,
+    pre_activation: Array2D, // activation input, derivative is taken from it
+    loss: L1Loss,
+    trainable: bool,
+}
+
+impl AbstractLayer for L1LossLayer {
+    fn forward(&mut self, input: ParamsBlob) -> LayerForwardResult {
+        let inp_m = input[0].get_2d_buf_t(TypeBuffer::Output);
+        let inp_m = inp_m.borrow();
+        let inp_m = inp_m.deref();
+
+        let out_m = self.lr_params.get_2d_buf_t(TypeBuffer::Output);
+        let mut out_m = out_m.borrow_mut();
+        let out_m = out_m.deref_mut();
+
+        let ws = self.lr_params.get_2d_buf_t(TypeBuffer::Weights);
+        let ws = ws.borrow();
+        let ws = ws.deref();
+
+        let bias_out = self.lr_params.get_1d_buf_t(TypeBuffer::Bias);
+        let bias_out = bias_out.borrow();
+        let bias_out = bias_out.deref();
+
+        Zip::from(inp_m.rows())
+            .and(out_m.rows_mut())
+            .par_for_each(|inp_r, out_b| {
+                // for each batch
+                let mul_res = ws.dot(&inp_r);
+
+                Zip::from(out_b)
+                    .and(&mul_res)
+                    .and(bias_out)
+                    .for_each(|out_el, in_row, bias_el| {
+                        // for each "neuron"
+                        *out_el = *in_row + bias_el;
+                    });
+            });
+
+        self.activation.apply(out_m, &mut self.pre_activation);
+
+        debug!("[ok] L1LossLayer forward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn backward_output(
+        &mut self,
+        prev_input: ParamsBlob,
+        expected_vec: Array2D,
+        loss_weights: &LossWeights,
+    ) -> LayerBackwardResult {
+        let prev_input = prev_input[0].get_2d_buf_t(TypeBuffer::Output);
+        let prev_input = prev_input.borrow();
+        let prev_input = prev_input.deref();
+
+        let self_output = self.lr_params.get_2d_buf_t(TypeBuffer::Output);
+        let self_output = self_output.borrow();
+        let self_output = self_output.deref();
+
+        let mut grad = self
+            .loss
+            .backward(self_output, &expected_vec, loss_weights)?;
+
+        Zip::from(&mut grad)
+            .and(&self.pre_activation)
+            .par_for_each(|grad_el, pre_act| {
+                *grad_el *= self.activation.func_deriv(*pre_act);
+            });
+
+        *self.lr_params.get_2d_buf_t(TypeBuffer::NeuGrad).borrow_mut() = grad;
+
+        if self.trainable {
+            loss_params_grad(&self.lr_params, prev_input, self.l1_regul, self.l2_regul);
+        }
+
+        debug!("[ok] L1LossLayer backward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn layer_type(&self) -> &str {
+        "L1LossLayer"
+    }
+
+    fn is_trainable(&self) -> bool {
+        self.trainable
+    }
+
+    fn set_trainable(&mut self, trainable: bool) {
+        self.trainable = trainable;
+    }
+
+    fn cpu_params(&self) -> Option<CpuParams> {
+        Some(self.lr_params.clone())
+    }
+
+    fn set_cpu_params(&mut self, lp: CpuParams) {
+        self.lr_params = lp;
+    }
+
+    /// Carefull this method overwrites weights and all other params
+    fn set_input_shape(&mut self, sh: &[usize]) {
+        self.lr_params = CpuParams::new_with_bias(self.size, sh.iter().product());
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    /// "mae", "rmse" and mean loss value as "loss"
+    fn metrics(&self) -> Option<&Metrics> {
+        self.loss.metrics()
+    }
+
+    fn copy_layer(&self) -> Box<dyn AbstractLayer> {
+        let mut copy_l = self.clone();
+        copy_l.set_cpu_params(self.lr_params.copy());
+        Box::new(copy_l)
+    }
+
+    fn clone_layer(&self) -> Box<dyn AbstractLayer> {
+        Box::new(self.clone())
+    }
+}
+
+impl L1LossLayer {
+    pub fn new(size: usize, activation: Activation) -> Self {
+        Self {
+            size,
+            lr_params: CpuParams::empty(),
+            activation,
+            pre_activation: Array2D::zeros((0, 0)),
+            l1_regul: 0.0,
+            l2_regul: 0.0,
+            loss: L1Loss::new(),
+            trainable: true,
+        }
+    }
+
+    pub fn new_box(size: usize, activation: Activation) -> Box<Self> {
+        Box::new(L1LossLayer::new(size, activation))
+    }
+
+    pub fn l2_regularization(mut self, coef: f32) -> Self {
+        self.l2_regul = coef;
+        self
+    }
+
+    pub fn l1_regularization(mut self, coef: f32) -> Self {
+        self.l1_regul = coef;
+        self
+    }
+}
+
+impl WithParams for L1LossLayer {
+    fn cfg(&self) -> HashMap<String, Variant> {
+        let mut cfg: HashMap<String, Variant> = HashMap::new();
+
+        cfg.insert("size".to_owned(), Variant::Int(self.size as i32));
+        self.activation.write_cfg(&mut cfg);
+        cfg.insert("l2_regul".to_owned(), Variant::Float(self.l2_regul));
+        cfg.insert("l1_regul".to_owned(), Variant::Float(self.l1_regul));
+        cfg.insert("trainable".to_owned(), Variant::Bool(self.trainable));
+
+        cfg
+    }
+
+    fn set_cfg(&mut self, cfg: &HashMap<String, Variant>) {
+        if cfg.contains_key("activation") {
+            match Activation::from_cfg(cfg) {
+                Ok(activation) => self.activation = activation,
+                Err(e) => error!("[l1_loss] {}", e),
+            }
+        }
+
+        if let Some(Variant::Int(size)) = cfg.get("size") {
+            if *size > 0 {
+                self.size = *size as usize;
+                self.lr_params = CpuParams::empty();
+            }
+        }
+
+        if let Some(Variant::Float(l1_regul)) = cfg.get("l1_regul") {
+            self.l1_regul = *l1_regul;
+        }
+
+        if let Some(Variant::Float(l2_regul)) = cfg.get("l2_regul") {
+            self.l2_regul = *l2_regul;
+        }
+
+        if let Some(Variant::Bool(trainable)) = cfg.get("trainable") {
+            self.trainable = *trainable;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::grad_check::*;
+
+    #[test]
+    fn grads_match_numeric() {
+        let mut l = L1LossLayer::new(3, Activation::Tanh);
+        let expected = test_values(2, 3, 2);
+        check_loss_layer(&mut l, test_values(2, 4, 1), expected, &LossWeights::default());
+    }
+}
@@ -0,0 +1,266 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use ndarray::{Axis, Zip};
+
+use log::{debug, error};
+
+use super::abstract_layer::{
+    next_layer_grad, AbstractLayer, LayerBackwardResult, LayerError, LayerForwardResult,
+    TrainableBufsIds,
+};
+use crate::cpu_params::*;
+use crate::util::*;
+
+/// Layer normalization, statistics are computed over all values of a single sample,
+/// so it doesn't depend on batch size.
+/// Gain is stored as Weights and bias as Bias buffer
+#[derive(Clone)]
+pub struct LayerNormLayer {
+    pub lr_params: CpuParams,
+    shape: Vec<usize>,
+    size: usize,
+    eps: f32,
+    x_hat: Array2D,
+    inv_std: Array1D,
+    trainable: bool,
+}
+
+impl AbstractLayer for LayerNormLayer {
+    fn forward(&mut self, input: ParamsBlob) -> LayerForwardResult {
+        let inp_m = input[0].get_2d_buf_t(TypeBuffer::Output);
+        let inp_m = inp_m.borrow();
+        let inp_m = inp_m.deref();
+
+        if self.size == 0 || inp_m.ncols() != self.size {
+            error!(
+                "Invalid input size for LayerNormLayer : {}, expected : {}",
+                inp_m.ncols(),
+                self.size
+            );
+            return Err(LayerError::InvalidSize);
+        }
+
+        let out_m = self.lr_params.get_2d_buf_t(TypeBuffer::Output);
+        let mut out_m = out_m.borrow_mut();
+        let out_m = out_m.deref_mut();
+
+        let gain = self.lr_params.get_1d_buf_t(TypeBuffer::Weights);
+        let gain = gain.borrow();
+        let gain = gain.deref();
+
+        let bias = self.lr_params.get_1d_buf_t(TypeBuffer::Bias);
+        let bias = bias.borrow();
+        let bias = bias.deref();
+
+        if self.x_hat.dim() != inp_m.dim() {
+            self.x_hat = Array2D::zeros(inp_m.dim());
+            self.inv_std = Array1D::zeros(inp_m.nrows());
+        }
+
+        let eps = self.eps;
+        let size = self.size as f32;
+
+        Zip::from(inp_m.rows())
+            .and(out_m.rows_mut())
+            .and(self.x_hat.rows_mut())
+            .and(&mut self.inv_std)
+            .par_for_each(|inp_r, out_r, x_hat_r, inv_std| {
+                // for each batch
+                let mean = inp_r.sum() / size;
+                let var = inp_r.fold(0.0, |acc, x| acc + (x - mean).powf(2.0)) / size;
+                *inv_std = 1.0 / (var + eps).sqrt();
+
+                Zip::from(out_r)
+                    .and(x_hat_r)
+                    .and(inp_r)
+                    .and(gain)
+                    .and(bias)
+                    .for_each(|out_el, x_hat_el, inp_el, gain_el, bias_el| {
+                        *x_hat_el = (inp_el - mean) * *inv_std;
+                        *out_el = gain_el * *x_hat_el + bias_el;
+                    });
+            });
+
+        debug!("[ok] LayerNormLayer forward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn backward(&mut self, _prev_input: ParamsBlob, next_input: ParamsBlob) -> LayerBackwardResult {
+        let next_grad = next_layer_grad(&next_input[0]);
+
+        let gain = self.lr_params.get_1d_buf_t(TypeBuffer::Weights);
+        let gain = gain.borrow();
+        let gain = gain.deref();
+
+        let gain_grad = self.lr_params.get_1d_buf_t(TypeBuffer::WeightsGrad);
+        let mut gain_grad = gain_grad.borrow_mut();
+        let gain_grad = gain_grad.deref_mut();
+
+        let bias_grad = self.lr_params.get_1d_buf_t(TypeBuffer::BiasGrad);
+        let mut bias_grad = bias_grad.borrow_mut();
+        let bias_grad = bias_grad.deref_mut();
+
+        let inp_grad = self.lr_params.get_2d_buf_t(TypeBuffer::InputGrad);
+        let mut inp_grad = inp_grad.borrow_mut();
+        let inp_grad = inp_grad.deref_mut();
+
+        if self.trainable {
+            *gain_grad = (&next_grad * &self.x_hat).mean_axis(Axis(0)).unwrap();
+            *bias_grad = next_grad.mean_axis(Axis(0)).unwrap();
+        }
+
+        let size = self.size as f32;
+
+        Zip::from(inp_grad.rows_mut())
+            .and(next_grad.rows())
+            .and(self.x_hat.rows())
+            .and(&self.inv_std)
+            .par_for_each(|inp_grad_r, grad_r, x_hat_r, inv_std| {
+                let grad_gain_r = &grad_r * gain;
+                let grad_sum = grad_gain_r.sum();
+                let grad_x_hat_sum = (&grad_gain_r * &x_hat_r).sum();
+
+                Zip::from(inp_grad_r)
+                    .and(&grad_gain_r)
+                    .and(x_hat_r)
+                    .for_each(|inp_grad_el, grad_el, x_hat_el| {
+                        *inp_grad_el = inv_std / size
+                            * (size * grad_el - grad_sum - x_hat_el * grad_x_hat_sum);
+                    });
+            });
+
+        debug!("[ok] LayerNormLayer backward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn cpu_params(&self) -> Option<CpuParams> {
+        Some(self.lr_params.clone())
+    }
+
+    fn set_cpu_params(&mut self, lp: CpuParams) {
+        self.lr_params = lp;
+    }
+
+    fn layer_type(&self) -> &str {
+        "LayerNormLayer"
+    }
+
+    fn is_trainable(&self) -> bool {
+        self.trainable
+    }
+
+    fn set_trainable(&mut self, trainable: bool) {
+        self.trainable = trainable;
+    }
+
+    fn trainable_bufs(&self) -> TrainableBufsIds {
+        (
+            &[TypeBuffer::Weights as i32, TypeBuffer::Bias as i32],
+            &[TypeBuffer::WeightsGrad as i32, TypeBuffer::BiasGrad as i32],
+        )
+    }
+
+    /// Carefull this method resets gain and bias
+    fn set_input_shape(&mut self, sh: &[usize]) {
+        self.shape = sh.to_vec();
+        self.size = sh.iter().product();
+
+        let new_1d = |arr: Array1D| VariantParamArc::Array1(Arc::new(RefCell::new(arr)));
+
+        self.lr_params = CpuParams::new_only_output(self.size);
+        self.lr_params
+            .insert_buf(TypeBuffer::Weights as i32, new_1d(Array1D::ones(self.size)));
+        self.lr_params
+            .insert_buf(TypeBuffer::WeightsGrad as i32, new_1d(Array1D::zeros(self.size)));
+        self.lr_params
+            .insert_buf(TypeBuffer::Bias as i32, new_1d(Array1D::zeros(self.size)));
+        self.lr_params
+            .insert_buf(TypeBuffer::BiasGrad as i32, new_1d(Array1D::zeros(self.size)));
+        self.lr_params.add_input_grad(self.size);
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        self.shape.clone()
+    }
+
+    fn copy_layer(&self) -> Box<dyn AbstractLayer> {
+        let mut copy_l = self.clone();
+        copy_l.set_cpu_params(self.lr_params.copy());
+        Box::new(copy_l)
+    }
+
+    fn clone_layer(&self) -> Box<dyn AbstractLayer> {
+        Box::new(self.clone())
+    }
+}
+
+impl Default for LayerNormLayer {
+    fn default() -> Self {
+        Self {
+            lr_params: CpuParams::empty(),
+            shape: vec![0],
+            size: 0,
+            eps: 1e-5,
+            x_hat: Array2D::zeros((0, 0)),
+            inv_std: Array1D::zeros(0),
+            trainable: true,
+        }
+    }
+}
+
+impl LayerNormLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn new_box() -> Box<Self> {
+        Box::new(LayerNormLayer::new())
+    }
+
+    pub fn eps(mut self, eps: f32) -> Self {
+        self.eps = eps;
+        self
+    }
+}
+
+impl WithParams for LayerNormLayer {
+    fn cfg(&self) -> HashMap<String, Variant> {
+        let mut cfg: HashMap<String, Variant> = HashMap::new();
+
+        cfg.insert("eps".to_owned(), Variant::Float(self.eps));
+        cfg.insert("trainable".to_owned(), Variant::Bool(self.trainable));
+
+        cfg
+    }
+
+    fn set_cfg(&mut self, cfg: &HashMap<String, Variant>) {
+        if let Some(Variant::Float(eps)) = cfg.get("eps") {
+            self.eps = *eps;
+        }
+
+        if let Some(Variant::Bool(trainable)) = cfg.get("trainable") {
+            self.trainable = *trainable;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::grad_check::*;
+
+    #[test]
+    fn grads_match_numeric() {
+        let mut l = LayerNormLayer::new();
+        check_layer(&mut l, &[5], test_values(2, 5, 1));
+    }
+}
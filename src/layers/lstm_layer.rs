@@ -0,0 +1,426 @@
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+
+use ndarray::{s, Axis, Zip};
+
+use log::{debug, error};
+
+use super::abstract_layer::{
+    next_layer_grad, params_count, AbstractLayer, LayerBackwardResult, LayerError,
+    LayerForwardResult, TrainableBufsIds,
+};
+use super::rnn_layer::{is_bptt_boundary, seq_input_shape};
+use crate::cpu_params::*;
+use crate::util::*;
+
+/// Long short-term memory layer.
+/// Weights, recurrent weights and bias store input, forget, cell and output gates
+/// stacked along the first axis, so their size is 4 * hidden_size.
+/// Input layout, outputs and truncated BPTT are the same as in RnnLayer
+#[derive(Clone)]
+pub struct LstmLayer {
+    pub lr_params: CpuParams,
+    hidden_size: usize,
+    input_dim: usize,
+    seq_len: usize,
+    return_sequences: bool,
+    bptt_steps: usize,
+    // states of the last forward pass, hs[0] and cs[0] are initial states
+    hs: Vec<Array2D>,
+    cs: Vec<Array2D>,
+    gates: Vec<Array2D>, // activated gates (batch, 4 * hidden_size) per timestep
+    trainable: bool,
+}
+
+impl AbstractLayer for LstmLayer {
+    fn forward(&mut self, input: ParamsBlob) -> LayerForwardResult {
+        let inp_m = input[0].get_2d_buf_t(TypeBuffer::Output);
+        let inp_m = inp_m.borrow();
+        let inp_m = inp_m.deref();
+
+        if self.seq_len == 0 || inp_m.ncols() != self.seq_len * self.input_dim {
+            error!(
+                "Invalid input size for LstmLayer : {}, expected : {}",
+                inp_m.ncols(),
+                self.seq_len * self.input_dim
+            );
+            return Err(LayerError::InvalidSize);
+        }
+
+        let out_m = self.lr_params.get_2d_buf_t(TypeBuffer::Output);
+        let mut out_m = out_m.borrow_mut();
+        let out_m = out_m.deref_mut();
+
+        let ws = self.lr_params.get_2d_buf_t(TypeBuffer::Weights);
+        let ws = ws.borrow();
+        let ws = ws.deref();
+
+        let rec_ws = self.lr_params.get_2d_buf_t(TypeBuffer::RecurrentWeights);
+        let rec_ws = rec_ws.borrow();
+        let rec_ws = rec_ws.deref();
+
+        let bias = self.lr_params.get_1d_buf_t(TypeBuffer::Bias);
+        let bias = bias.borrow();
+        let bias = bias.deref();
+
+        let (hid, inp_dim) = (self.hidden_size, self.input_dim);
+        let batch_len = inp_m.nrows();
+
+        self.hs.clear();
+        self.cs.clear();
+        self.gates.clear();
+
+        self.hs.push(Array2D::zeros((batch_len, hid)));
+        self.cs.push(Array2D::zeros((batch_len, hid)));
+
+        for t in 0..self.seq_len {
+            let x_t = inp_m.slice(s![.., t * inp_dim..(t + 1) * inp_dim]);
+
+            let mut z = x_t.dot(&ws.t()) + self.hs[t].dot(&rec_ws.t()) + bias;
+
+            z.slice_mut(s![.., 0..2 * hid]).mapv_inplace(sigmoid);
+            z.slice_mut(s![.., 2 * hid..3 * hid]).mapv_inplace(|v| v.tanh());
+            z.slice_mut(s![.., 3 * hid..]).mapv_inplace(sigmoid);
+
+            let (i_g, f_g) = (z.slice(s![.., 0..hid]), z.slice(s![.., hid..2 * hid]));
+            let (g_g, o_g) = (
+                z.slice(s![.., 2 * hid..3 * hid]),
+                z.slice(s![.., 3 * hid..]),
+            );
+
+            let c_t = &f_g * &self.cs[t] + &i_g * &g_g;
+            let h_t = &o_g * &c_t.mapv(|c| c.tanh());
+
+            if self.return_sequences {
+                out_m.slice_mut(s![.., t * hid..(t + 1) * hid]).assign(&h_t);
+            }
+
+            self.hs.push(h_t);
+            self.cs.push(c_t);
+            self.gates.push(z);
+        }
+
+        if !self.return_sequences {
+            out_m.assign(&self.hs[self.seq_len]);
+        }
+
+        debug!("[ok] LstmLayer forward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn backward(&mut self, prev_input: ParamsBlob, next_input: ParamsBlob) -> LayerBackwardResult {
+        let next_grad = next_layer_grad(&next_input[0]);
+
+        let prev_input = prev_input[0].get_2d_buf_t(TypeBuffer::Output);
+        let prev_input = prev_input.borrow();
+        let prev_input = prev_input.deref();
+
+        let ws = self.lr_params.get_2d_buf_t(TypeBuffer::Weights);
+        let ws = ws.borrow();
+        let ws = ws.deref();
+
+        let rec_ws = self.lr_params.get_2d_buf_t(TypeBuffer::RecurrentWeights);
+        let rec_ws = rec_ws.borrow();
+        let rec_ws = rec_ws.deref();
+
+        let ws_grad = self.lr_params.get_2d_buf_t(TypeBuffer::WeightsGrad);
+        let mut ws_grad = ws_grad.borrow_mut();
+        let ws_grad = ws_grad.deref_mut();
+
+        let rec_ws_grad = self.lr_params.get_2d_buf_t(TypeBuffer::RecurrentWeightsGrad);
+        let mut rec_ws_grad = rec_ws_grad.borrow_mut();
+        let rec_ws_grad = rec_ws_grad.deref_mut();
+
+        let bias_grad = self.lr_params.get_1d_buf_t(TypeBuffer::BiasGrad);
+        let mut bias_grad = bias_grad.borrow_mut();
+        let bias_grad = bias_grad.deref_mut();
+
+        let inp_grad = self.lr_params.get_2d_buf_t(TypeBuffer::InputGrad);
+        let mut inp_grad = inp_grad.borrow_mut();
+        let inp_grad = inp_grad.deref_mut();
+
+        let (hid, inp_dim) = (self.hidden_size, self.input_dim);
+        let batch_len = prev_input.nrows();
+
+        ws_grad.fill(0.0);
+        rec_ws_grad.fill(0.0);
+        bias_grad.fill(0.0);
+
+        let mut dh_next = Array2D::zeros((batch_len, hid));
+        let mut dc_next = Array2D::zeros((batch_len, hid));
+        let mut dz = Array2D::zeros((batch_len, 4 * hid));
+
+        for t in (0..self.seq_len).rev() {
+            let mut dh = dh_next;
+
+            if self.return_sequences {
+                dh += &next_grad.slice(s![.., t * hid..(t + 1) * hid]);
+            } else if t == self.seq_len - 1 {
+                dh += &next_grad;
+            }
+
+            let z = &self.gates[t];
+            let (i_g, f_g) = (z.slice(s![.., 0..hid]), z.slice(s![.., hid..2 * hid]));
+            let (g_g, o_g) = (
+                z.slice(s![.., 2 * hid..3 * hid]),
+                z.slice(s![.., 3 * hid..]),
+            );
+
+            let c_tanh = self.cs[t + 1].mapv(|c| c.tanh());
+            let dc = &dh * &o_g * &c_tanh.mapv(|c| 1.0 - c * c) + &dc_next;
+
+            // gradients before gates activations
+            Zip::from(dz.slice_mut(s![.., 0..hid]))
+                .and(&dc)
+                .and(&g_g)
+                .and(&i_g)
+                .for_each(|d, dc, g, i| *d = dc * g * i * (1.0 - i));
+            Zip::from(dz.slice_mut(s![.., hid..2 * hid]))
+                .and(&dc)
+                .and(&self.cs[t])
+                .and(&f_g)
+                .for_each(|d, dc, c_prev, f| *d = dc * c_prev * f * (1.0 - f));
+            Zip::from(dz.slice_mut(s![.., 2 * hid..3 * hid]))
+                .and(&dc)
+                .and(&i_g)
+                .and(&g_g)
+                .for_each(|d, dc, i, g| *d = dc * i * (1.0 - g * g));
+            Zip::from(dz.slice_mut(s![.., 3 * hid..]))
+                .and(&dh)
+                .and(&c_tanh)
+                .and(&o_g)
+                .for_each(|d, dh, c_tanh, o| *d = dh * c_tanh * o * (1.0 - o));
+
+            let x_t = prev_input.slice(s![.., t * inp_dim..(t + 1) * inp_dim]);
+
+            if self.trainable {
+                *ws_grad += &dz.t().dot(&x_t);
+                *rec_ws_grad += &dz.t().dot(&self.hs[t]);
+                *bias_grad += &dz.sum_axis(Axis(0));
+            }
+
+            inp_grad
+                .slice_mut(s![.., t * inp_dim..(t + 1) * inp_dim])
+                .assign(&dz.dot(ws));
+
+            if is_bptt_boundary(t, self.bptt_steps) {
+                dh_next = Array2D::zeros((batch_len, hid));
+                dc_next = Array2D::zeros((batch_len, hid));
+            } else {
+                dh_next = dz.dot(rec_ws);
+                dc_next = dc * f_g;
+            }
+        }
+
+        if self.trainable {
+            *ws_grad /= batch_len as f32;
+            *rec_ws_grad /= batch_len as f32;
+            *bias_grad /= batch_len as f32;
+        }
+
+        debug!("[ok] LstmLayer backward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn cpu_params(&self) -> Option<CpuParams> {
+        Some(self.lr_params.clone())
+    }
+
+    fn set_cpu_params(&mut self, lp: CpuParams) {
+        self.lr_params = lp;
+    }
+
+    fn layer_type(&self) -> &str {
+        "LstmLayer"
+    }
+
+    fn is_trainable(&self) -> bool {
+        self.trainable
+    }
+
+    fn set_trainable(&mut self, trainable: bool) {
+        self.trainable = trainable;
+    }
+
+    fn trainable_bufs(&self) -> TrainableBufsIds {
+        (
+            &[
+                TypeBuffer::Weights as i32,
+                TypeBuffer::RecurrentWeights as i32,
+                TypeBuffer::Bias as i32,
+            ],
+            &[
+                TypeBuffer::WeightsGrad as i32,
+                TypeBuffer::RecurrentWeightsGrad as i32,
+                TypeBuffer::BiasGrad as i32,
+            ],
+        )
+    }
+
+    fn serializable_bufs(&self) -> &[i32] {
+        &[
+            TypeBuffer::Weights as i32,
+            TypeBuffer::RecurrentWeights as i32,
+            TypeBuffer::Bias as i32,
+        ]
+    }
+
+    /// Accepts [seq_len, input_dim] shape or flat shape, which is split by input_dim.
+    /// Carefull this method overwrites weights and all other params
+    fn set_input_shape(&mut self, sh: &[usize]) {
+        if let Some((seq_len, input_dim)) = seq_input_shape(sh, self.input_dim) {
+            self.seq_len = seq_len;
+            self.input_dim = input_dim;
+        } else {
+            error!(
+                "LstmLayer couldn't split input shape {:?} by input_dim {}",
+                sh, self.input_dim
+            );
+            return;
+        }
+
+        let hid = self.hidden_size;
+
+        self.lr_params = CpuParams::new_recurrent(
+            4 * hid,
+            self.input_dim,
+            hid,
+            self.size(),
+            self.seq_len * self.input_dim,
+        );
+
+        // forget gate bias starts from 1.0 to remember by default
+        self.lr_params
+            .get_1d_buf_t(TypeBuffer::Bias)
+            .borrow_mut()
+            .slice_mut(s![hid..2 * hid])
+            .fill(1.0);
+    }
+
+    fn size(&self) -> usize {
+        if self.return_sequences {
+            self.seq_len * self.hidden_size
+        } else {
+            self.hidden_size
+        }
+    }
+
+    /// Weights are applied at each timestep
+    fn forward_flops(&self) -> usize {
+        let weights = params_count(&self.lr_params, self.trainable_bufs().0);
+        self.seq_len * (2 * weights + 4 * self.hidden_size)
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        if self.return_sequences {
+            vec![self.seq_len, self.hidden_size]
+        } else {
+            vec![self.hidden_size]
+        }
+    }
+
+    fn copy_layer(&self) -> Box<dyn AbstractLayer> {
+        let mut copy_l = self.clone();
+        copy_l.set_cpu_params(self.lr_params.copy());
+        Box::new(copy_l)
+    }
+
+    fn clone_layer(&self) -> Box<dyn AbstractLayer> {
+        Box::new(self.clone())
+    }
+}
+
+impl LstmLayer {
+    pub fn new(hidden_size: usize) -> Self {
+        Self {
+            lr_params: CpuParams::empty(),
+            hidden_size,
+            input_dim: 1,
+            seq_len: 0,
+            return_sequences: false,
+            bptt_steps: 0,
+            hs: Vec::new(),
+            cs: Vec::new(),
+            gates: Vec::new(),
+            trainable: true,
+        }
+    }
+
+    pub fn new_box(hidden_size: usize) -> Box<Self> {
+        Box::new(LstmLayer::new(hidden_size))
+    }
+
+    /// Used only when previous layer provides flat [size] output shape
+    pub fn input_dim(mut self, input_dim: usize) -> Self {
+        self.input_dim = input_dim;
+        self
+    }
+
+    pub fn return_sequences(mut self, state: bool) -> Self {
+        self.return_sequences = state;
+        self
+    }
+
+    pub fn bptt_steps(mut self, steps: usize) -> Self {
+        self.bptt_steps = steps;
+        self
+    }
+}
+
+impl WithParams for LstmLayer {
+    fn cfg(&self) -> HashMap<String, Variant> {
+        let mut cfg: HashMap<String, Variant> = HashMap::new();
+
+        cfg.insert(
+            "hidden_size".to_owned(),
+            Variant::Int(self.hidden_size as i32),
+        );
+        cfg.insert("input_dim".to_owned(), Variant::Int(self.input_dim as i32));
+        cfg.insert(
+            "return_sequences".to_owned(),
+            Variant::Bool(self.return_sequences),
+        );
+        cfg.insert("bptt_steps".to_owned(), Variant::Int(self.bptt_steps as i32));
+        cfg.insert("trainable".to_owned(), Variant::Bool(self.trainable));
+
+        cfg
+    }
+
+    fn set_cfg(&mut self, cfg: &HashMap<String, Variant>) {
+        if let Some(Variant::Int(hidden_size)) = cfg.get("hidden_size") {
+            self.hidden_size = *hidden_size as usize;
+        }
+
+        if let Some(Variant::Int(input_dim)) = cfg.get("input_dim") {
+            self.input_dim = *input_dim as usize;
+        }
+
+        if let Some(Variant::Bool(return_sequences)) = cfg.get("return_sequences") {
+            self.return_sequences = *return_sequences;
+        }
+
+        if let Some(Variant::Int(bptt_steps)) = cfg.get("bptt_steps") {
+            self.bptt_steps = *bptt_steps as usize;
+        }
+
+        self.lr_params = CpuParams::empty();
+
+        if let Some(Variant::Bool(trainable)) = cfg.get("trainable") {
+            self.trainable = *trainable;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::grad_check::*;
+
+    #[test]
+    fn last_step_grads_match_numeric() {
+        let mut l = LstmLayer::new(3);
+        check_layer(&mut l, &[4, 2], test_values(2, 8, 1));
+    }
+}
@@ -1,9 +1,40 @@
 mod abstract_layer;
+mod activation_layer;
+mod add_layer;
+mod avg_pool2d_layer;
+mod batch_norm_layer;
+mod bce_loss_layer;
+mod bidirectional_layer;
+mod concat_layer;
+mod conv1d_layer;
+mod conv2d_layer;
+mod conv_transpose2d_layer;
+mod dropout_layer;
 mod dummy_layer;
+mod embedding_layer;
 mod euclidean_loss_layer;
 mod softmax_loss_layer;
 mod fc_layer;
+mod flatten_layer;
+mod gaussian_noise_layer;
+mod global_avg_pool_layer;
+mod gru_layer;
+mod huber_loss_layer;
 mod input_layer;
+mod l1_loss_layer;
+mod layer_norm_layer;
+mod lstm_layer;
+mod max_pool2d_layer;
+mod multi_head_attention_layer;
+mod prelu_layer;
+mod reshape_layer;
+mod rnn_layer;
+mod sublayers;
+mod transformer_encoder_layer;
+mod upsample2d_layer;
+
+#[cfg(test)]
+pub(crate) mod grad_check;
 
 #[cfg(feature = "opencl")]
 mod abstract_layer_ocl;
@@ -17,11 +48,38 @@ mod euclidean_loss_layer_ocl;
 mod softmax_loss_layer_ocl;
 
 pub use abstract_layer::*;
+pub use activation_layer::*;
+pub use add_layer::*;
+pub use avg_pool2d_layer::*;
+pub use batch_norm_layer::*;
+pub use bce_loss_layer::*;
+pub use bidirectional_layer::*;
+pub use concat_layer::*;
+pub use conv1d_layer::*;
+pub use conv2d_layer::*;
+pub use conv_transpose2d_layer::*;
+pub use dropout_layer::*;
 pub use dummy_layer::*;
+pub use embedding_layer::*;
 pub use euclidean_loss_layer::*;
 pub use fc_layer::*;
+pub use flatten_layer::*;
+pub use gaussian_noise_layer::*;
+pub use global_avg_pool_layer::*;
+pub use gru_layer::*;
+pub use huber_loss_layer::*;
 pub use input_layer::*;
+pub use l1_loss_layer::*;
+pub use layer_norm_layer::*;
+pub use lstm_layer::*;
+pub use max_pool2d_layer::*;
+pub use multi_head_attention_layer::*;
+pub use prelu_layer::*;
+pub use reshape_layer::*;
+pub use rnn_layer::*;
 pub use softmax_loss_layer::*;
+pub use transformer_encoder_layer::*;
+pub use upsample2d_layer::*;
 #[cfg(feature = "opencl")]
 pub use abstract_layer_ocl::*;
 #[cfg(feature = "opencl")]
@@ -0,0 +1,548 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use ndarray::{s, Axis};
+use ndarray_rand::rand_distr::Uniform;
+use ndarray_rand::RandomExt;
+
+use log::{debug, error};
+
+use super::abstract_layer::{
+    ensure_initialized, next_layer_grad, params_count, AbstractLayer, LayerBackwardResult,
+    LayerError, LayerForwardResult, TrainableBufsIds,
+};
+use super::rnn_layer::seq_input_shape;
+use super::sublayers::new_2d_buf;
+use crate::cpu_params::*;
+use crate::util::*;
+
+/// Positional encoding added to the attention input
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PositionalEncoding {
+    None,
+    Sinusoidal, // fixed, isn't trained or serialized
+    Learned,
+}
+
+impl PositionalEncoding {
+    pub fn name(&self) -> &str {
+        match self {
+            PositionalEncoding::None => "none",
+            PositionalEncoding::Sinusoidal => "sinusoidal",
+            PositionalEncoding::Learned => "learned",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(PositionalEncoding::None),
+            "sinusoidal" => Some(PositionalEncoding::Sinusoidal),
+            "learned" => Some(PositionalEncoding::Learned),
+            _ => None,
+        }
+    }
+}
+
+const TRAINABLE_BUFS: [i32; 4] = [
+    TypeBuffer::Weights as i32,
+    TypeBuffer::Bias as i32,
+    TypeBuffer::ProjWeights as i32,
+    TypeBuffer::ProjBias as i32,
+];
+
+const TRAINABLE_GRADS: [i32; 4] = [
+    TypeBuffer::WeightsGrad as i32,
+    TypeBuffer::BiasGrad as i32,
+    TypeBuffer::ProjWeightsGrad as i32,
+    TypeBuffer::ProjBiasGrad as i32,
+];
+
+const TRAINABLE_POS_BUFS: [i32; 5] = [
+    TypeBuffer::Weights as i32,
+    TypeBuffer::Bias as i32,
+    TypeBuffer::ProjWeights as i32,
+    TypeBuffer::ProjBias as i32,
+    TypeBuffer::PosEncoding as i32,
+];
+
+const TRAINABLE_POS_GRADS: [i32; 5] = [
+    TypeBuffer::WeightsGrad as i32,
+    TypeBuffer::BiasGrad as i32,
+    TypeBuffer::ProjWeightsGrad as i32,
+    TypeBuffer::ProjBiasGrad as i32,
+    TypeBuffer::PosEncodingGrad as i32,
+];
+
+/// Sinusoidal encoding from "Attention is all you need" paper
+fn sinusoidal_encoding(seq_len: usize, dim: usize) -> Array2D {
+    let mut enc = Array2D::zeros((seq_len, dim));
+
+    for ((t, i), val) in enc.indexed_iter_mut() {
+        let freq = 1.0 / 10000_f32.powf((i - i % 2) as f32 / dim as f32);
+        let angle = t as f32 * freq;
+
+        *val = if i % 2 == 0 { angle.sin() } else { angle.cos() };
+    }
+
+    enc
+}
+
+/// Multi-head scaled dot-product self-attention.
+/// Input row is a sequence of seq_len vectors of model_dim values, output has the same shape.
+/// Q, K, V projections are stacked in Weights (3 * model_dim, model_dim) and Bias,
+/// output projection is stored in ProjWeights and ProjBias
+#[derive(Clone)]
+pub struct MultiHeadAttentionLayer {
+    pub lr_params: CpuParams,
+    num_heads: usize,
+    model_dim: usize,
+    seq_len: usize,
+    causal: bool,
+    pos_encoding: PositionalEncoding,
+    // per sample values of the last forward pass
+    xp: Vec<Array2D>,       // input with positional encoding
+    qkv: Vec<Array2D>,      // stacked projections
+    attn: Vec<Vec<Array2D>>, // attention weights of each head
+    ctx: Vec<Array2D>,      // concatenated heads outputs
+    trainable: bool,
+}
+
+impl MultiHeadAttentionLayer {
+    pub fn new(num_heads: usize, model_dim: usize) -> Self {
+        Self {
+            lr_params: CpuParams::empty(),
+            num_heads,
+            model_dim,
+            seq_len: 0,
+            causal: false,
+            pos_encoding: PositionalEncoding::None,
+            xp: Vec::new(),
+            qkv: Vec::new(),
+            attn: Vec::new(),
+            ctx: Vec::new(),
+            trainable: true,
+        }
+    }
+
+    pub fn new_box(num_heads: usize, model_dim: usize) -> Box<Self> {
+        Box::new(MultiHeadAttentionLayer::new(num_heads, model_dim))
+    }
+
+    /// Forbids attending to the next positions of the sequence
+    pub fn causal(mut self, state: bool) -> Self {
+        self.causal = state;
+        self
+    }
+
+    pub fn pos_encoding(mut self, pos_encoding: PositionalEncoding) -> Self {
+        self.pos_encoding = pos_encoding;
+        self
+    }
+
+    fn head_dim(&self) -> usize {
+        self.model_dim / self.num_heads
+    }
+}
+
+impl AbstractLayer for MultiHeadAttentionLayer {
+    fn forward(&mut self, input: ParamsBlob) -> LayerForwardResult {
+        ensure_initialized(&self.lr_params, "MultiHeadAttentionLayer")?;
+
+        let inp_m = input[0].get_2d_buf_t(TypeBuffer::Output);
+        let inp_m = inp_m.borrow();
+        let inp_m = inp_m.deref();
+
+        if self.seq_len == 0 || inp_m.ncols() != self.seq_len * self.model_dim {
+            error!(
+                "Invalid input size for MultiHeadAttentionLayer : {}, expected : {}",
+                inp_m.ncols(),
+                self.seq_len * self.model_dim
+            );
+            return Err(LayerError::InvalidSize);
+        }
+
+        let out_m = self.lr_params.get_2d_buf_t(TypeBuffer::Output);
+        let mut out_m = out_m.borrow_mut();
+        let out_m = out_m.deref_mut();
+
+        let ws = self.lr_params.get_2d_buf_t(TypeBuffer::Weights);
+        let ws = ws.borrow();
+        let ws = ws.deref();
+
+        let bias = self.lr_params.get_1d_buf_t(TypeBuffer::Bias);
+        let bias = bias.borrow();
+        let bias = bias.deref();
+
+        let proj_ws = self.lr_params.get_2d_buf_t(TypeBuffer::ProjWeights);
+        let proj_ws = proj_ws.borrow();
+        let proj_ws = proj_ws.deref();
+
+        let proj_bias = self.lr_params.get_1d_buf_t(TypeBuffer::ProjBias);
+        let proj_bias = proj_bias.borrow();
+        let proj_bias = proj_bias.deref();
+
+        let pos_enc = if self.pos_encoding != PositionalEncoding::None {
+            Some(self.lr_params.get_2d_buf_t(TypeBuffer::PosEncoding))
+        } else {
+            None
+        };
+
+        let (seq_len, dim, head_dim) = (self.seq_len, self.model_dim, self.head_dim());
+        let scale = 1.0 / (head_dim as f32).sqrt();
+
+        self.xp.clear();
+        self.qkv.clear();
+        self.attn.clear();
+        self.ctx.clear();
+
+        for (inp_r, mut out_r) in inp_m.rows().into_iter().zip(out_m.rows_mut()) {
+            // for each batch
+            let mut xp = inp_r.to_owned().into_shape((seq_len, dim)).unwrap();
+
+            if let Some(pos_enc) = &pos_enc {
+                xp += &*pos_enc.borrow();
+            }
+
+            let qkv = xp.dot(&ws.t()) + bias;
+            let mut ctx = Array2D::zeros((seq_len, dim));
+            let mut heads_attn = Vec::with_capacity(self.num_heads);
+
+            for h in 0..self.num_heads {
+                let q = qkv.slice(s![.., h * head_dim..(h + 1) * head_dim]);
+                let k = qkv.slice(s![.., dim + h * head_dim..dim + (h + 1) * head_dim]);
+                let v = qkv.slice(s![.., 2 * dim + h * head_dim..2 * dim + (h + 1) * head_dim]);
+
+                let mut a = q.dot(&k.t()) * scale;
+
+                if self.causal {
+                    for ((i, j), val) in a.indexed_iter_mut() {
+                        if j > i {
+                            *val = f32::NEG_INFINITY;
+                        }
+                    }
+                }
+
+                array_helpers::softmax_rows(&mut a);
+
+                ctx.slice_mut(s![.., h * head_dim..(h + 1) * head_dim])
+                    .assign(&a.dot(&v));
+                heads_attn.push(a);
+            }
+
+            let y = ctx.dot(&proj_ws.t()) + proj_bias;
+            out_r.assign(&y.into_shape(seq_len * dim).unwrap());
+
+            self.xp.push(xp);
+            self.qkv.push(qkv);
+            self.attn.push(heads_attn);
+            self.ctx.push(ctx);
+        }
+
+        debug!("[ok] MultiHeadAttentionLayer forward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn backward(&mut self, _prev_input: ParamsBlob, next_input: ParamsBlob) -> LayerBackwardResult {
+        ensure_initialized(&self.lr_params, "MultiHeadAttentionLayer")?;
+
+        let next_grad = next_layer_grad(&next_input[0]);
+
+        let ws = self.lr_params.get_2d_buf_t(TypeBuffer::Weights);
+        let ws = ws.borrow();
+        let ws = ws.deref();
+
+        let proj_ws = self.lr_params.get_2d_buf_t(TypeBuffer::ProjWeights);
+        let proj_ws = proj_ws.borrow();
+        let proj_ws = proj_ws.deref();
+
+        let ws_grad = self.lr_params.get_2d_buf_t(TypeBuffer::WeightsGrad);
+        let mut ws_grad = ws_grad.borrow_mut();
+        let ws_grad = ws_grad.deref_mut();
+
+        let bias_grad = self.lr_params.get_1d_buf_t(TypeBuffer::BiasGrad);
+        let mut bias_grad = bias_grad.borrow_mut();
+        let bias_grad = bias_grad.deref_mut();
+
+        let proj_ws_grad = self.lr_params.get_2d_buf_t(TypeBuffer::ProjWeightsGrad);
+        let mut proj_ws_grad = proj_ws_grad.borrow_mut();
+        let proj_ws_grad = proj_ws_grad.deref_mut();
+
+        let proj_bias_grad = self.lr_params.get_1d_buf_t(TypeBuffer::ProjBiasGrad);
+        let mut proj_bias_grad = proj_bias_grad.borrow_mut();
+        let proj_bias_grad = proj_bias_grad.deref_mut();
+
+        let inp_grad = self.lr_params.get_2d_buf_t(TypeBuffer::InputGrad);
+        let mut inp_grad = inp_grad.borrow_mut();
+        let inp_grad = inp_grad.deref_mut();
+
+        let (seq_len, dim, head_dim) = (self.seq_len, self.model_dim, self.head_dim());
+        let scale = 1.0 / (head_dim as f32).sqrt();
+        let batch_len = next_grad.nrows() as f32;
+
+        ws_grad.fill(0.0);
+        bias_grad.fill(0.0);
+        proj_ws_grad.fill(0.0);
+        proj_bias_grad.fill(0.0);
+
+        let mut pos_grad = Array2D::zeros((seq_len, dim));
+
+        for (b, (grad_r, mut inp_grad_r)) in next_grad
+            .rows()
+            .into_iter()
+            .zip(inp_grad.rows_mut())
+            .enumerate()
+        {
+            // for each batch
+            let dy = grad_r.to_owned().into_shape((seq_len, dim)).unwrap();
+            let qkv = &self.qkv[b];
+
+            if self.trainable {
+                *proj_ws_grad += &dy.t().dot(&self.ctx[b]);
+                *proj_bias_grad += &dy.sum_axis(Axis(0));
+            }
+
+            let d_ctx = dy.dot(proj_ws);
+            let mut d_qkv = Array2D::zeros((seq_len, 3 * dim));
+
+            for h in 0..self.num_heads {
+                let (q_cols, k_cols, v_cols) = (
+                    h * head_dim..(h + 1) * head_dim,
+                    dim + h * head_dim..dim + (h + 1) * head_dim,
+                    2 * dim + h * head_dim..2 * dim + (h + 1) * head_dim,
+                );
+
+                let a = &self.attn[b][h];
+                let d_c = d_ctx.slice(s![.., h * head_dim..(h + 1) * head_dim]);
+
+                let d_a = d_c.dot(&qkv.slice(s![.., v_cols.clone()]).t());
+                let d_v = a.t().dot(&d_c);
+
+                // softmax derivative, masked positions have zero attention weight
+                let row_sums = (&d_a * a).sum_axis(Axis(1)).insert_axis(Axis(1));
+                let d_s = a * &(&d_a - &row_sums) * scale;
+
+                let d_q = d_s.dot(&qkv.slice(s![.., k_cols.clone()]));
+                let d_k = d_s.t().dot(&qkv.slice(s![.., q_cols.clone()]));
+
+                d_qkv.slice_mut(s![.., q_cols]).assign(&d_q);
+                d_qkv.slice_mut(s![.., k_cols]).assign(&d_k);
+                d_qkv.slice_mut(s![.., v_cols]).assign(&d_v);
+            }
+
+            if self.trainable {
+                *ws_grad += &d_qkv.t().dot(&self.xp[b]);
+                *bias_grad += &d_qkv.sum_axis(Axis(0));
+            }
+
+            let d_xp = d_qkv.dot(ws);
+            pos_grad += &d_xp;
+
+            inp_grad_r.assign(&d_xp.into_shape(seq_len * dim).unwrap());
+        }
+
+        if self.trainable {
+            *ws_grad /= batch_len;
+            *bias_grad /= batch_len;
+            *proj_ws_grad /= batch_len;
+            *proj_bias_grad /= batch_len;
+        }
+
+        if self.trainable && self.pos_encoding == PositionalEncoding::Learned {
+            let pos_enc_grad = self.lr_params.get_2d_buf_t(TypeBuffer::PosEncodingGrad);
+            *pos_enc_grad.borrow_mut() = pos_grad / batch_len;
+        }
+
+        debug!("[ok] MultiHeadAttentionLayer backward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn cpu_params(&self) -> Option<CpuParams> {
+        Some(self.lr_params.clone())
+    }
+
+    fn set_cpu_params(&mut self, lp: CpuParams) {
+        self.lr_params = lp;
+    }
+
+    fn layer_type(&self) -> &str {
+        "MultiHeadAttentionLayer"
+    }
+
+    fn is_trainable(&self) -> bool {
+        self.trainable
+    }
+
+    fn set_trainable(&mut self, trainable: bool) {
+        self.trainable = trainable;
+    }
+
+    fn trainable_bufs(&self) -> TrainableBufsIds {
+        if self.pos_encoding == PositionalEncoding::Learned {
+            (&TRAINABLE_POS_BUFS, &TRAINABLE_POS_GRADS)
+        } else {
+            (&TRAINABLE_BUFS, &TRAINABLE_GRADS)
+        }
+    }
+
+    fn serializable_bufs(&self) -> &[i32] {
+        if self.pos_encoding == PositionalEncoding::Learned {
+            &TRAINABLE_POS_BUFS
+        } else {
+            &TRAINABLE_BUFS
+        }
+    }
+
+    /// Accepts [seq_len, model_dim] shape or flat shape, which is split by model_dim.
+    /// Carefull this method overwrites weights and all other params
+    fn set_input_shape(&mut self, sh: &[usize]) {
+        let (seq_len, model_dim) = match seq_input_shape(sh, self.model_dim) {
+            Some(split) => split,
+            None => {
+                error!(
+                    "MultiHeadAttentionLayer couldn't split input shape {:?} by model_dim {}",
+                    sh, self.model_dim
+                );
+                return;
+            }
+        };
+
+        // fields are kept untouched on error, so forward() sees the layer as uninitialized
+        if self.num_heads == 0 || model_dim % self.num_heads != 0 {
+            error!(
+                "MultiHeadAttentionLayer model_dim {} isn't divisible by num_heads {}",
+                model_dim, self.num_heads
+            );
+            return;
+        }
+
+        self.seq_len = seq_len;
+        self.model_dim = model_dim;
+
+        let (seq_len, dim) = (self.seq_len, self.model_dim);
+        let new_1d = |arr: Array1D| VariantParamArc::Array1(Arc::new(RefCell::new(arr)));
+
+        let mut lp = CpuParams::new_with_bias_and_output(3 * dim, dim, seq_len * dim);
+        lp.remove_buf(TypeBuffer::NeuGrad as i32);
+        lp.add_input_grad(seq_len * dim);
+
+        lp.insert_buf(
+            TypeBuffer::ProjWeights as i32,
+            new_2d_buf(Array2D::random((dim, dim), Uniform::new(-0.1, 0.1))),
+        );
+        lp.insert_buf(
+            TypeBuffer::ProjWeightsGrad as i32,
+            new_2d_buf(Array2D::zeros((dim, dim))),
+        );
+        lp.insert_buf(TypeBuffer::ProjBias as i32, new_1d(Array1D::zeros(dim)));
+        lp.insert_buf(TypeBuffer::ProjBiasGrad as i32, new_1d(Array1D::zeros(dim)));
+
+        match self.pos_encoding {
+            PositionalEncoding::None => {}
+            PositionalEncoding::Sinusoidal => {
+                lp.insert_buf(
+                    TypeBuffer::PosEncoding as i32,
+                    new_2d_buf(sinusoidal_encoding(seq_len, dim)),
+                );
+            }
+            PositionalEncoding::Learned => {
+                lp.insert_buf(
+                    TypeBuffer::PosEncoding as i32,
+                    new_2d_buf(Array2D::random((seq_len, dim), Uniform::new(-0.1, 0.1))),
+                );
+                lp.insert_buf(
+                    TypeBuffer::PosEncodingGrad as i32,
+                    new_2d_buf(Array2D::zeros((seq_len, dim))),
+                );
+            }
+        }
+
+        self.lr_params = lp;
+    }
+
+    fn size(&self) -> usize {
+        self.seq_len * self.model_dim
+    }
+
+    /// Projections of each token, attention scores, their softmax and weighted sum of values
+    fn forward_flops(&self) -> usize {
+        let weights = params_count(&self.lr_params, self.trainable_bufs().0);
+        let scores = self.seq_len * self.seq_len;
+
+        2 * weights * self.seq_len + 4 * scores * self.model_dim + self.num_heads * scores
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        vec![self.seq_len, self.model_dim]
+    }
+
+    fn copy_layer(&self) -> Box<dyn AbstractLayer> {
+        let mut copy_l = self.clone();
+        copy_l.set_cpu_params(self.lr_params.copy());
+        Box::new(copy_l)
+    }
+
+    fn clone_layer(&self) -> Box<dyn AbstractLayer> {
+        Box::new(self.clone())
+    }
+}
+
+impl WithParams for MultiHeadAttentionLayer {
+    fn cfg(&self) -> HashMap<String, Variant> {
+        let mut cfg: HashMap<String, Variant> = HashMap::new();
+
+        cfg.insert("num_heads".to_owned(), Variant::Int(self.num_heads as i32));
+        cfg.insert("model_dim".to_owned(), Variant::Int(self.model_dim as i32));
+        cfg.insert("causal".to_owned(), Variant::Bool(self.causal));
+        cfg.insert(
+            "pos_encoding".to_owned(),
+            Variant::String(self.pos_encoding.name().to_owned()),
+        );
+        cfg.insert("trainable".to_owned(), Variant::Bool(self.trainable));
+
+        cfg
+    }
+
+    fn set_cfg(&mut self, cfg: &HashMap<String, Variant>) {
+        if let Some(Variant::Int(num_heads)) = cfg.get("num_heads") {
+            self.num_heads = *num_heads as usize;
+        }
+
+        if let Some(Variant::Int(model_dim)) = cfg.get("model_dim") {
+            self.model_dim = *model_dim as usize;
+        }
+
+        if let Some(Variant::Bool(causal)) = cfg.get("causal") {
+            self.causal = *causal;
+        }
+
+        if let Some(Variant::String(pos_encoding)) = cfg.get("pos_encoding") {
+            if let Some(pos_encoding) = PositionalEncoding::from_name(pos_encoding) {
+                self.pos_encoding = pos_encoding;
+            } else {
+                error!("Unknown positional encoding : {}", pos_encoding);
+            }
+        }
+
+        self.lr_params = CpuParams::empty();
+
+        if let Some(Variant::Bool(trainable)) = cfg.get("trainable") {
+            self.trainable = *trainable;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::grad_check::*;
+
+    #[test]
+    fn causal_attention_grads_match_numeric() {
+        let mut l = MultiHeadAttentionLayer::new(2, 4).causal(true);
+        check_layer(&mut l, &[3, 4], test_values(2, 12, 1));
+    }
+}
@@ -0,0 +1,300 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use ndarray::{Axis, Zip};
+
+use log::{debug, error};
+
+use super::abstract_layer::{
+    next_layer_grad, AbstractLayer, LayerBackwardResult, LayerError, LayerForwardResult,
+    TrainableBufsIds,
+};
+use crate::cpu_params::*;
+use crate::util::*;
+
+/// Parametric ReLU : x for positive values and slope * x otherwise.
+/// Slopes are learned per channel (or per feature for flat input) or a single one is shared,
+/// they are stored as Weights buffer
+#[derive(Clone)]
+pub struct PReluLayer {
+    pub lr_params: CpuParams,
+    shape: Vec<usize>,
+    channels: usize,
+    spatial_size: usize, // values per channel in a single sample
+    shared: bool,
+    init_slope: f32,
+    trainable: bool,
+}
+
+impl PReluLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn new_box() -> Box<Self> {
+        Box::new(PReluLayer::new())
+    }
+
+    /// Single slope for all values
+    pub fn shared(mut self, shared: bool) -> Self {
+        self.shared = shared;
+        self
+    }
+
+    pub fn init_slope(mut self, slope: f32) -> Self {
+        self.init_slope = slope;
+        self
+    }
+
+    fn slopes_count(&self) -> usize {
+        if self.shared {
+            1
+        } else {
+            self.channels
+        }
+    }
+}
+
+/// Slope index for a value of the sample
+fn slope_idx(col: usize, shared: bool, spatial_size: usize) -> usize {
+    if shared {
+        0
+    } else {
+        col / spatial_size
+    }
+}
+
+impl AbstractLayer for PReluLayer {
+    fn forward(&mut self, input: ParamsBlob) -> LayerForwardResult {
+        let inp_m = input[0].get_2d_buf_t(TypeBuffer::Output);
+        let inp_m = inp_m.borrow();
+        let inp_m = inp_m.deref();
+
+        if inp_m.ncols() != self.size() {
+            error!(
+                "Invalid input size for PReluLayer : {}, expected : {}",
+                inp_m.ncols(),
+                self.size()
+            );
+            return Err(LayerError::InvalidSize);
+        }
+
+        let out_m = self.lr_params.get_2d_buf_t(TypeBuffer::Output);
+        let mut out_m = out_m.borrow_mut();
+        let out_m = out_m.deref_mut();
+
+        let slopes = self.lr_params.get_1d_buf_t(TypeBuffer::Weights);
+        let slopes = slopes.borrow();
+        let slopes = slopes.deref();
+
+        let (shared, spatial_size) = (self.shared, self.spatial_size);
+
+        Zip::from(inp_m.rows())
+            .and(out_m.rows_mut())
+            .par_for_each(|inp_r, out_r| {
+                // for each batch
+                Zip::indexed(out_r)
+                    .and(inp_r)
+                    .for_each(|col, out_el, inp_el| {
+                        if *inp_el > 0.0 {
+                            *out_el = *inp_el;
+                        } else {
+                            *out_el = slopes[slope_idx(col, shared, spatial_size)] * inp_el;
+                        }
+                    });
+            });
+
+        debug!("[ok] PReluLayer forward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn backward(&mut self, prev_input: ParamsBlob, next_input: ParamsBlob) -> LayerBackwardResult {
+        let next_grad = next_layer_grad(&next_input[0]);
+
+        let prev_input = prev_input[0].get_2d_buf_t(TypeBuffer::Output);
+        let prev_input = prev_input.borrow();
+        let prev_input = prev_input.deref();
+
+        let slopes = self.lr_params.get_1d_buf_t(TypeBuffer::Weights);
+        let slopes = slopes.borrow();
+        let slopes = slopes.deref();
+
+        let slopes_grad = self.lr_params.get_1d_buf_t(TypeBuffer::WeightsGrad);
+        let mut slopes_grad = slopes_grad.borrow_mut();
+        let slopes_grad = slopes_grad.deref_mut();
+
+        let inp_grad = self.lr_params.get_2d_buf_t(TypeBuffer::InputGrad);
+        let mut inp_grad = inp_grad.borrow_mut();
+        let inp_grad = inp_grad.deref_mut();
+
+        let (shared, spatial_size) = (self.shared, self.spatial_size);
+
+        Zip::from(inp_grad.rows_mut())
+            .and(next_grad.rows())
+            .and(prev_input.rows())
+            .par_for_each(|inp_grad_r, grad_r, inp_r| {
+                // for each batch
+                Zip::indexed(inp_grad_r).and(grad_r).and(inp_r).for_each(
+                    |col, inp_grad_el, grad_el, inp_el| {
+                        if *inp_el > 0.0 {
+                            *inp_grad_el = *grad_el;
+                        } else {
+                            *inp_grad_el = slopes[slope_idx(col, shared, spatial_size)] * grad_el;
+                        }
+                    },
+                );
+            });
+
+        // d(out) / d(slope) is the input for non-positive values
+        if self.trainable {
+            let slope_terms = Zip::from(&next_grad)
+                .and(prev_input)
+                .par_map_collect(|grad_el, inp_el| grad_el * inp_el.min(0.0))
+                .mean_axis(Axis(0))
+                .unwrap();
+
+            slopes_grad.fill(0.0);
+
+            for (col, term) in slope_terms.iter().enumerate() {
+                slopes_grad[slope_idx(col, shared, spatial_size)] += term;
+            }
+        }
+
+        debug!("[ok] PReluLayer backward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn cpu_params(&self) -> Option<CpuParams> {
+        Some(self.lr_params.clone())
+    }
+
+    fn set_cpu_params(&mut self, lp: CpuParams) {
+        self.lr_params = lp;
+    }
+
+    fn layer_type(&self) -> &str {
+        "PReluLayer"
+    }
+
+    fn is_trainable(&self) -> bool {
+        self.trainable
+    }
+
+    fn set_trainable(&mut self, trainable: bool) {
+        self.trainable = trainable;
+    }
+
+    fn serializable_bufs(&self) -> &[i32] {
+        &[TypeBuffer::Weights as i32]
+    }
+
+    fn trainable_bufs(&self) -> TrainableBufsIds {
+        (
+            &[TypeBuffer::Weights as i32],
+            &[TypeBuffer::WeightsGrad as i32],
+        )
+    }
+
+    /// Carefull this method resets slopes
+    fn set_input_shape(&mut self, sh: &[usize]) {
+        self.shape = sh.to_vec();
+
+        if sh.len() == 3 {
+            self.channels = sh[0];
+            self.spatial_size = sh[1] * sh[2];
+        } else {
+            self.channels = sh.iter().product();
+            self.spatial_size = 1;
+        }
+
+        let size = self.size();
+        let slopes_cnt = self.slopes_count();
+
+        let new_1d = |arr: Array1D| VariantParamArc::Array1(Arc::new(RefCell::new(arr)));
+
+        self.lr_params = CpuParams::new_only_output(size);
+        self.lr_params.insert_buf(
+            TypeBuffer::Weights as i32,
+            new_1d(Array1D::from_elem(slopes_cnt, self.init_slope)),
+        );
+        self.lr_params.insert_buf(
+            TypeBuffer::WeightsGrad as i32,
+            new_1d(Array1D::zeros(slopes_cnt)),
+        );
+        self.lr_params.add_input_grad(size);
+    }
+
+    fn size(&self) -> usize {
+        self.channels * self.spatial_size
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        self.shape.clone()
+    }
+
+    fn copy_layer(&self) -> Box<dyn AbstractLayer> {
+        let mut copy_l = self.clone();
+        copy_l.set_cpu_params(self.lr_params.copy());
+        Box::new(copy_l)
+    }
+
+    fn clone_layer(&self) -> Box<dyn AbstractLayer> {
+        Box::new(self.clone())
+    }
+}
+
+impl Default for PReluLayer {
+    fn default() -> Self {
+        Self {
+            lr_params: CpuParams::empty(),
+            shape: vec![0],
+            channels: 0,
+            spatial_size: 1,
+            shared: false,
+            init_slope: 0.25,
+            trainable: true,
+        }
+    }
+}
+
+impl WithParams for PReluLayer {
+    fn cfg(&self) -> HashMap<String, Variant> {
+        let mut cfg: HashMap<String, Variant> = HashMap::new();
+
+        cfg.insert("shared".to_owned(), Variant::Bool(self.shared));
+        cfg.insert("init_slope".to_owned(), Variant::Float(self.init_slope));
+        cfg.insert("trainable".to_owned(), Variant::Bool(self.trainable));
+
+        cfg
+    }
+
+    fn set_cfg(&mut self, cfg: &HashMap<String, Variant>) {
+        if let Some(Variant::Bool(shared)) = cfg.get("shared") {
+            self.shared = *shared;
+        }
+
+        if let Some(Variant::Float(init_slope)) = cfg.get("init_slope") {
+            self.init_slope = *init_slope;
+        }
+
+        if let Some(Variant::Bool(trainable)) = cfg.get("trainable") {
+            self.trainable = *trainable;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::grad_check::*;
+
+    #[test]
+    fn per_channel_slopes_grads_match_numeric() {
+        let mut l = PReluLayer::new().init_slope(0.2);
+        check_layer(&mut l, &[2, 3], test_values(2, 6, 1));
+    }
+}
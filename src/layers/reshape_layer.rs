@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+
+use log::{debug, error};
+
+use super::abstract_layer::{
+    next_layer_grad, AbstractLayer, LayerBackwardResult, LayerError, LayerForwardResult,
+    TrainableBufsIds,
+};
+use super::sublayers::new_2d_buf;
+use crate::cpu_params::*;
+use crate::util::*;
+
+/// Batch rows are stored flat, so layers changing only the shape share
+/// the output buffer of the previous layer instead of copying it
+pub(crate) fn share_input(
+    lr_params: &mut CpuParams,
+    input: &CpuParams,
+    size: usize,
+) -> Result<(), LayerError> {
+    let inp_size = input.get_2d_buf_t(TypeBuffer::Output).borrow().ncols();
+
+    if inp_size != size {
+        error!(
+            "Invalid input size for shape layer : {}, expected : {}",
+            inp_size, size
+        );
+        return Err(LayerError::InvalidSize);
+    }
+
+    lr_params.insert_buf(
+        TypeBuffer::Output as i32,
+        input.get_param_t(TypeBuffer::Output),
+    );
+
+    Ok(())
+}
+
+/// Shares input gradient of the next layer if it has one, otherwise computes it
+pub(crate) fn share_next_grad(lr_params: &mut CpuParams, next_input: &CpuParams) {
+    let inp_grad = if next_input.contains_buf_t(TypeBuffer::InputGrad) {
+        next_input.get_param_t(TypeBuffer::InputGrad)
+    } else {
+        new_2d_buf(next_layer_grad(next_input))
+    };
+
+    lr_params.insert_buf(TypeBuffer::InputGrad as i32, inp_grad);
+}
+
+/// Changes output shape to the given one, values aren't copied.
+/// Single -1 dimension is inferred from the input size
+#[derive(Clone)]
+pub struct ReshapeLayer {
+    pub lr_params: CpuParams,
+    target_shape: Vec<i32>,
+    shape: Vec<usize>,
+    size: usize,
+}
+
+impl AbstractLayer for ReshapeLayer {
+    fn forward(&mut self, input: ParamsBlob) -> LayerForwardResult {
+        share_input(&mut self.lr_params, &input[0], self.size)?;
+
+        debug!("[ok] ReshapeLayer forward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn backward(&mut self, _prev_input: ParamsBlob, next_input: ParamsBlob) -> LayerBackwardResult {
+        share_next_grad(&mut self.lr_params, &next_input[0]);
+
+        debug!("[ok] ReshapeLayer backward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn cpu_params(&self) -> Option<CpuParams> {
+        Some(self.lr_params.clone())
+    }
+
+    fn set_cpu_params(&mut self, lp: CpuParams) {
+        self.lr_params = lp;
+    }
+
+    fn layer_type(&self) -> &str {
+        "ReshapeLayer"
+    }
+
+    fn trainable_bufs(&self) -> TrainableBufsIds {
+        (&[], &[])
+    }
+
+    fn serializable_bufs(&self) -> &[i32] {
+        &[]
+    }
+
+    fn set_input_shape(&mut self, sh: &[usize]) {
+        let size: usize = sh.iter().product();
+
+        let known: usize = self
+            .target_shape
+            .iter()
+            .filter(|d| **d > 0)
+            .map(|d| *d as usize)
+            .product();
+        let inferred_cnt = self.target_shape.iter().filter(|d| **d == -1).count();
+
+        let shape: Option<Vec<usize>> = if inferred_cnt > 1 || known == 0 {
+            None
+        } else if inferred_cnt == 1 && size % known == 0 {
+            Some(
+                self.target_shape
+                    .iter()
+                    .map(|d| {
+                        if *d == -1 {
+                            size / known
+                        } else {
+                            *d as usize
+                        }
+                    })
+                    .collect(),
+            )
+        } else if inferred_cnt == 0 && known == size {
+            Some(self.target_shape.iter().map(|d| *d as usize).collect())
+        } else {
+            None
+        };
+
+        if let Some(shape) = shape {
+            self.shape = shape;
+            self.size = size;
+        } else {
+            error!(
+                "ReshapeLayer couldn't reshape {:?} to {:?}",
+                sh, self.target_shape
+            );
+            // forward() rejects any input of the layer with zero size
+            self.size = 0;
+            return;
+        }
+
+        self.lr_params = CpuParams::new_only_output(self.size);
+        self.lr_params.add_input_grad(self.size);
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Output buffer of the previous layer is shared, no computation is done
+    fn forward_flops(&self) -> usize {
+        0
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        self.shape.clone()
+    }
+
+    fn copy_layer(&self) -> Box<dyn AbstractLayer> {
+        let mut copy_l = self.clone();
+        copy_l.set_cpu_params(self.lr_params.copy());
+        Box::new(copy_l)
+    }
+
+    fn clone_layer(&self) -> Box<dyn AbstractLayer> {
+        Box::new(self.clone())
+    }
+}
+
+impl ReshapeLayer {
+    pub fn new(shape: &[i32]) -> Self {
+        Self {
+            lr_params: CpuParams::empty(),
+            target_shape: shape.to_vec(),
+            shape: vec![0],
+            size: 0,
+        }
+    }
+
+    pub fn new_box(shape: &[i32]) -> Box<Self> {
+        Box::new(ReshapeLayer::new(shape))
+    }
+}
+
+impl WithParams for ReshapeLayer {
+    fn cfg(&self) -> HashMap<String, Variant> {
+        let mut cfg: HashMap<String, Variant> = HashMap::new();
+
+        cfg.insert(
+            "shape".to_owned(),
+            Variant::IntArray(self.target_shape.clone()),
+        );
+
+        cfg
+    }
+
+    fn set_cfg(&mut self, cfg: &HashMap<String, Variant>) {
+        if let Some(Variant::IntArray(shape)) = cfg.get("shape") {
+            self.target_shape = shape.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::grad_check::*;
+
+    #[test]
+    fn inferred_dim_passes_grad_through() {
+        let mut l = ReshapeLayer::new(&[3, -1]);
+        check_layer(&mut l, &[6], test_values(2, 6, 1));
+    }
+
+    #[test]
+    fn impossible_target_shape_fails_forward() {
+        let mut l = ReshapeLayer::new(&[5, -1]);
+        l.set_input_shape(&[12]);
+
+        assert_eq!(l.size(), 0);
+        assert!(l.forward(vec![output_params(test_values(1, 12, 1))]).is_err());
+    }
+}
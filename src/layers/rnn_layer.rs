@@ -0,0 +1,381 @@
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+
+use ndarray::{s, Axis};
+
+use log::{debug, error};
+
+use super::abstract_layer::{
+    next_layer_grad, params_count, AbstractLayer, LayerBackwardResult, LayerError,
+    LayerForwardResult, TrainableBufsIds,
+};
+use crate::cpu_params::*;
+use crate::util::*;
+
+/// Splits input shape into (seq_len, input_dim).
+/// [seq_len, input_dim] shape is used as is, flat shape is split by input_dim
+pub(crate) fn seq_input_shape(sh: &[usize], input_dim: usize) -> Option<(usize, usize)> {
+    if sh.len() == 2 {
+        return Some((sh[0], sh[1]));
+    }
+
+    let size: usize = sh.iter().product();
+
+    if input_dim == 0 || size % input_dim != 0 {
+        return None;
+    }
+
+    Some((size / input_dim, input_dim))
+}
+
+/// Returns true if hidden state gradient shouldn't be passed from step t to step t - 1
+pub(crate) fn is_bptt_boundary(t: usize, bptt_steps: usize) -> bool {
+    bptt_steps > 0 && t % bptt_steps == 0
+}
+
+/// Vanilla recurrent layer h(t) = tanh(W * x(t) + U * h(t - 1) + b).
+/// Input row is a sequence of seq_len vectors of input_dim values.
+/// Outputs all hidden states [seq_len, hidden_size] or only the last one [hidden_size].
+/// Trained with truncated backpropagation through time, bptt_steps = 0 means full sequence
+#[derive(Clone)]
+pub struct RnnLayer {
+    pub lr_params: CpuParams,
+    hidden_size: usize,
+    input_dim: usize,
+    seq_len: usize,
+    return_sequences: bool,
+    bptt_steps: usize,
+    hs: Vec<Array2D>, // hidden states of the last forward pass, hs[0] is initial state
+    trainable: bool,
+}
+
+impl AbstractLayer for RnnLayer {
+    fn forward(&mut self, input: ParamsBlob) -> LayerForwardResult {
+        let inp_m = input[0].get_2d_buf_t(TypeBuffer::Output);
+        let inp_m = inp_m.borrow();
+        let inp_m = inp_m.deref();
+
+        if self.seq_len == 0 || inp_m.ncols() != self.seq_len * self.input_dim {
+            error!(
+                "Invalid input size for RnnLayer : {}, expected : {}",
+                inp_m.ncols(),
+                self.seq_len * self.input_dim
+            );
+            return Err(LayerError::InvalidSize);
+        }
+
+        let out_m = self.lr_params.get_2d_buf_t(TypeBuffer::Output);
+        let mut out_m = out_m.borrow_mut();
+        let out_m = out_m.deref_mut();
+
+        let ws = self.lr_params.get_2d_buf_t(TypeBuffer::Weights);
+        let ws = ws.borrow();
+        let ws = ws.deref();
+
+        let rec_ws = self.lr_params.get_2d_buf_t(TypeBuffer::RecurrentWeights);
+        let rec_ws = rec_ws.borrow();
+        let rec_ws = rec_ws.deref();
+
+        let bias = self.lr_params.get_1d_buf_t(TypeBuffer::Bias);
+        let bias = bias.borrow();
+        let bias = bias.deref();
+
+        let (hid, inp_dim) = (self.hidden_size, self.input_dim);
+
+        self.hs.clear();
+        self.hs.push(Array2D::zeros((inp_m.nrows(), hid)));
+
+        for t in 0..self.seq_len {
+            let x_t = inp_m.slice(s![.., t * inp_dim..(t + 1) * inp_dim]);
+
+            let mut h_t = x_t.dot(&ws.t()) + self.hs[t].dot(&rec_ws.t()) + bias;
+            h_t.par_mapv_inplace(|v| v.tanh());
+
+            if self.return_sequences {
+                out_m.slice_mut(s![.., t * hid..(t + 1) * hid]).assign(&h_t);
+            }
+
+            self.hs.push(h_t);
+        }
+
+        if !self.return_sequences {
+            out_m.assign(&self.hs[self.seq_len]);
+        }
+
+        debug!("[ok] RnnLayer forward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn backward(&mut self, prev_input: ParamsBlob, next_input: ParamsBlob) -> LayerBackwardResult {
+        let next_grad = next_layer_grad(&next_input[0]);
+
+        let prev_input = prev_input[0].get_2d_buf_t(TypeBuffer::Output);
+        let prev_input = prev_input.borrow();
+        let prev_input = prev_input.deref();
+
+        let ws = self.lr_params.get_2d_buf_t(TypeBuffer::Weights);
+        let ws = ws.borrow();
+        let ws = ws.deref();
+
+        let rec_ws = self.lr_params.get_2d_buf_t(TypeBuffer::RecurrentWeights);
+        let rec_ws = rec_ws.borrow();
+        let rec_ws = rec_ws.deref();
+
+        let ws_grad = self.lr_params.get_2d_buf_t(TypeBuffer::WeightsGrad);
+        let mut ws_grad = ws_grad.borrow_mut();
+        let ws_grad = ws_grad.deref_mut();
+
+        let rec_ws_grad = self.lr_params.get_2d_buf_t(TypeBuffer::RecurrentWeightsGrad);
+        let mut rec_ws_grad = rec_ws_grad.borrow_mut();
+        let rec_ws_grad = rec_ws_grad.deref_mut();
+
+        let bias_grad = self.lr_params.get_1d_buf_t(TypeBuffer::BiasGrad);
+        let mut bias_grad = bias_grad.borrow_mut();
+        let bias_grad = bias_grad.deref_mut();
+
+        let inp_grad = self.lr_params.get_2d_buf_t(TypeBuffer::InputGrad);
+        let mut inp_grad = inp_grad.borrow_mut();
+        let inp_grad = inp_grad.deref_mut();
+
+        let (hid, inp_dim) = (self.hidden_size, self.input_dim);
+        let batch_len = prev_input.nrows() as f32;
+
+        ws_grad.fill(0.0);
+        rec_ws_grad.fill(0.0);
+        bias_grad.fill(0.0);
+
+        let mut dh_next = Array2D::zeros((prev_input.nrows(), hid));
+
+        for t in (0..self.seq_len).rev() {
+            let mut dh = dh_next;
+
+            if self.return_sequences {
+                dh += &next_grad.slice(s![.., t * hid..(t + 1) * hid]);
+            } else if t == self.seq_len - 1 {
+                dh += &next_grad;
+            }
+
+            // tanh derivative from its output
+            let h_t = &self.hs[t + 1];
+            let dz = dh * &h_t.mapv(|h| 1.0 - h * h);
+
+            let x_t = prev_input.slice(s![.., t * inp_dim..(t + 1) * inp_dim]);
+
+            if self.trainable {
+                *ws_grad += &dz.t().dot(&x_t);
+                *rec_ws_grad += &dz.t().dot(&self.hs[t]);
+                *bias_grad += &dz.sum_axis(Axis(0));
+            }
+
+            inp_grad
+                .slice_mut(s![.., t * inp_dim..(t + 1) * inp_dim])
+                .assign(&dz.dot(ws));
+
+            dh_next = if is_bptt_boundary(t, self.bptt_steps) {
+                Array2D::zeros(dz.dim())
+            } else {
+                dz.dot(rec_ws)
+            };
+        }
+
+        if self.trainable {
+            *ws_grad /= batch_len;
+            *rec_ws_grad /= batch_len;
+            *bias_grad /= batch_len;
+        }
+
+        debug!("[ok] RnnLayer backward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn cpu_params(&self) -> Option<CpuParams> {
+        Some(self.lr_params.clone())
+    }
+
+    fn set_cpu_params(&mut self, lp: CpuParams) {
+        self.lr_params = lp;
+    }
+
+    fn layer_type(&self) -> &str {
+        "RnnLayer"
+    }
+
+    fn is_trainable(&self) -> bool {
+        self.trainable
+    }
+
+    fn set_trainable(&mut self, trainable: bool) {
+        self.trainable = trainable;
+    }
+
+    fn trainable_bufs(&self) -> TrainableBufsIds {
+        (
+            &[
+                TypeBuffer::Weights as i32,
+                TypeBuffer::RecurrentWeights as i32,
+                TypeBuffer::Bias as i32,
+            ],
+            &[
+                TypeBuffer::WeightsGrad as i32,
+                TypeBuffer::RecurrentWeightsGrad as i32,
+                TypeBuffer::BiasGrad as i32,
+            ],
+        )
+    }
+
+    fn serializable_bufs(&self) -> &[i32] {
+        &[
+            TypeBuffer::Weights as i32,
+            TypeBuffer::RecurrentWeights as i32,
+            TypeBuffer::Bias as i32,
+        ]
+    }
+
+    /// Accepts [seq_len, input_dim] shape or flat shape, which is split by input_dim.
+    /// Carefull this method overwrites weights and all other params
+    fn set_input_shape(&mut self, sh: &[usize]) {
+        if let Some((seq_len, input_dim)) = seq_input_shape(sh, self.input_dim) {
+            self.seq_len = seq_len;
+            self.input_dim = input_dim;
+        } else {
+            error!(
+                "RnnLayer couldn't split input shape {:?} by input_dim {}",
+                sh, self.input_dim
+            );
+            return;
+        }
+
+        self.lr_params = CpuParams::new_recurrent(
+            self.hidden_size,
+            self.input_dim,
+            self.hidden_size,
+            self.size(),
+            self.seq_len * self.input_dim,
+        );
+    }
+
+    fn size(&self) -> usize {
+        if self.return_sequences {
+            self.seq_len * self.hidden_size
+        } else {
+            self.hidden_size
+        }
+    }
+
+    /// Weights are applied at each timestep
+    fn forward_flops(&self) -> usize {
+        let weights = params_count(&self.lr_params, self.trainable_bufs().0);
+        self.seq_len * (2 * weights + self.hidden_size)
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        if self.return_sequences {
+            vec![self.seq_len, self.hidden_size]
+        } else {
+            vec![self.hidden_size]
+        }
+    }
+
+    fn copy_layer(&self) -> Box<dyn AbstractLayer> {
+        let mut copy_l = self.clone();
+        copy_l.set_cpu_params(self.lr_params.copy());
+        Box::new(copy_l)
+    }
+
+    fn clone_layer(&self) -> Box<dyn AbstractLayer> {
+        Box::new(self.clone())
+    }
+}
+
+impl RnnLayer {
+    pub fn new(hidden_size: usize) -> Self {
+        Self {
+            lr_params: CpuParams::empty(),
+            hidden_size,
+            input_dim: 1,
+            seq_len: 0,
+            return_sequences: false,
+            bptt_steps: 0,
+            hs: Vec::new(),
+            trainable: true,
+        }
+    }
+
+    pub fn new_box(hidden_size: usize) -> Box<Self> {
+        Box::new(RnnLayer::new(hidden_size))
+    }
+
+    /// Used only when previous layer provides flat [size] output shape
+    pub fn input_dim(mut self, input_dim: usize) -> Self {
+        self.input_dim = input_dim;
+        self
+    }
+
+    pub fn return_sequences(mut self, state: bool) -> Self {
+        self.return_sequences = state;
+        self
+    }
+
+    pub fn bptt_steps(mut self, steps: usize) -> Self {
+        self.bptt_steps = steps;
+        self
+    }
+}
+
+impl WithParams for RnnLayer {
+    fn cfg(&self) -> HashMap<String, Variant> {
+        let mut cfg: HashMap<String, Variant> = HashMap::new();
+
+        cfg.insert(
+            "hidden_size".to_owned(),
+            Variant::Int(self.hidden_size as i32),
+        );
+        cfg.insert("input_dim".to_owned(), Variant::Int(self.input_dim as i32));
+        cfg.insert(
+            "return_sequences".to_owned(),
+            Variant::Bool(self.return_sequences),
+        );
+        cfg.insert("bptt_steps".to_owned(), Variant::Int(self.bptt_steps as i32));
+        cfg.insert("trainable".to_owned(), Variant::Bool(self.trainable));
+
+        cfg
+    }
+
+    fn set_cfg(&mut self, cfg: &HashMap<String, Variant>) {
+        if let Some(Variant::Int(hidden_size)) = cfg.get("hidden_size") {
+            self.hidden_size = *hidden_size as usize;
+        }
+
+        if let Some(Variant::Int(input_dim)) = cfg.get("input_dim") {
+            self.input_dim = *input_dim as usize;
+        }
+
+        if let Some(Variant::Bool(return_sequences)) = cfg.get("return_sequences") {
+            self.return_sequences = *return_sequences;
+        }
+
+        if let Some(Variant::Int(bptt_steps)) = cfg.get("bptt_steps") {
+            self.bptt_steps = *bptt_steps as usize;
+        }
+
+        self.lr_params = CpuParams::empty();
+
+        if let Some(Variant::Bool(trainable)) = cfg.get("trainable") {
+            self.trainable = *trainable;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::grad_check::*;
+
+    #[test]
+    fn full_bptt_sequence_grads_match_numeric() {
+        let mut l = RnnLayer::new(3).return_sequences(true);
+        check_layer(&mut l, &[4, 2], test_values(2, 8, 1));
+    }
+}
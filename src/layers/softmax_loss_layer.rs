@@ -1,26 +1,23 @@
-use std::{
-    ops::{Deref, DerefMut},
-    sync::{
-        atomic::{AtomicU32, Ordering},
-    },
-};
+use std::ops::{Deref, DerefMut};
 
 use std::collections::HashMap;
 use std::f32::consts::E;
 
-use ndarray::{Array1, Axis, Zip, indices};
+use ndarray::{Zip, indices};
 
-use log::{debug, info, warn};
+use log::debug;
 
 use crate::cpu_params::*;
 use crate::layers::*;
+use crate::losses::*;
 use crate::util::*;
 
-#[derive(Default, Clone)]
+#[derive(Clone)]
 pub struct SoftmaxLossLayer {
     pub size: usize,
     pub lr_params: CpuParams,
-    metrics: HashMap<String, f64>,
+    loss: SoftmaxCrossEntropyLoss,
+    trainable: bool,
 }
 
 impl AbstractLayer for SoftmaxLossLayer {
@@ -64,6 +61,7 @@ impl AbstractLayer for SoftmaxLossLayer {
         &mut self,
         prev_input: ParamsBlob,
         expected_vec: Array2D,
+        loss_weights: &LossWeights,
     ) -> LayerBackwardResult {
         let prev_input = &prev_input[0].get_2d_buf_t(TypeBuffer::Output);
         let prev_input = prev_input.borrow();
@@ -74,46 +72,18 @@ impl AbstractLayer for SoftmaxLossLayer {
         let self_neu_grad = self_neu_grad.deref_mut();
 
         let self_output = self.lr_params.get_2d_buf_t(TypeBuffer::Output);
-        let mut self_output = self_output.borrow_mut();
-        let self_output = self_output.deref_mut();
+        let self_output = self_output.borrow();
+        let self_output = self_output.deref();
 
-        let match_cnt = AtomicU32::new(0);
-        let batch_len = self_output.len_of(Axis(0)) as f64;
+        // output already holds softmax probabilities
+        *self_neu_grad = self
+            .loss
+            .backward_probs(self_output, &expected_vec, loss_weights)?;
 
-        Zip::from(self_neu_grad.rows_mut())
-            .and(self_output.rows())
-            .and(expected_vec.rows())
-            .par_for_each(|err_val_b, out_b, expected_b| {
-                // for each batch
-                let (mut out_idx, mut out_max_val) = (-1, 0.0);
-                let mut idx = 0;
-                let mut b_expected_idx = -1;
-
-                Zip::from(err_val_b).and(out_b).and(expected_b).for_each(
-                    |err_val, output, expected| {
-                        if *output > out_max_val {
-                            out_max_val = *output;
-                            out_idx = idx;
-                        }
-
-                        if *expected == 1.0 {
-                            b_expected_idx = idx;
-                            *err_val = 1.0 - *output;
-                        } else {
-                            *err_val = (-1.0) * *output;
-                        }
-
-                        idx += 1;
-                    },
-                );
-
-                if b_expected_idx == out_idx {
-                    match_cnt.fetch_add(1, Ordering::Relaxed);
-                }
-            });
-
-        let accuracy = match_cnt.load(Ordering::SeqCst) as f64 / batch_len;
-        self.metrics.insert("accuracy".to_string(), accuracy);
+        // frozen layer only passes gradient to previous layer with NeuGrad
+        if !self.trainable {
+            return Ok(vec![self.lr_params.clone()]);
+        }
 
         let ws_grad = self.lr_params.get_2d_buf_t(TypeBuffer::WeightsGrad);
         let mut ws_grad = ws_grad.borrow_mut();
@@ -149,6 +119,14 @@ impl AbstractLayer for SoftmaxLossLayer {
         "SoftmaxLossLayer"
     }
 
+    fn is_trainable(&self) -> bool {
+        self.trainable
+    }
+
+    fn set_trainable(&mut self, trainable: bool) {
+        self.trainable = trainable;
+    }
+
     fn cpu_params(&self) -> Option<CpuParams> {
         Some(self.lr_params.clone())
     }
@@ -162,7 +140,7 @@ impl AbstractLayer for SoftmaxLossLayer {
     }
 
     fn metrics(&self) -> Option<&HashMap<String, f64>> {
-        Some(&self.metrics)
+        self.loss.metrics()
     }
 
     fn trainable_bufs(&self) -> TrainableBufsIds {
@@ -177,7 +155,7 @@ impl AbstractLayer for SoftmaxLossLayer {
     }
 
     fn copy_layer(&self) -> Box<dyn AbstractLayer> {
-        let mut copy_l = SoftmaxLossLayer::new(self.size);
+        let mut copy_l = self.clone();
         copy_l.set_cpu_params(self.lr_params.copy());
         Box::new(copy_l)
     }
@@ -187,7 +165,7 @@ impl AbstractLayer for SoftmaxLossLayer {
     }
 
     fn set_input_shape(&mut self, sh: &[usize]) {
-        self.lr_params = CpuParams::new(self.size, sh[0]);
+        self.lr_params = CpuParams::new(self.size, sh.iter().product());
     }
 }
 
@@ -196,13 +174,32 @@ impl SoftmaxLossLayer {
         Self {
             size,
             lr_params: CpuParams::empty(),
-            metrics: HashMap::new(),
+            loss: SoftmaxCrossEntropyLoss::new(),
+            trainable: true,
         }
     }
 
     pub fn new_box(size: usize) -> Box<Self> {
         Box::new(SoftmaxLossLayer::new(size))
     }
+
+    /// Expected values hold a single class index per row instead of a one-hot vector
+    pub fn sparse(mut self, sparse: bool) -> Self {
+        self.loss = self.loss.sparse(sparse);
+        self
+    }
+
+    /// Mixes targets with uniform distribution : t * (1 - factor) + factor / size
+    pub fn label_smoothing(mut self, factor: f32) -> Self {
+        self.loss = self.loss.label_smoothing(factor);
+        self
+    }
+
+    /// Loss multipliers for each class
+    pub fn class_weights(mut self, class_weights: &[f32]) -> Self {
+        self.loss = self.loss.class_weights(class_weights);
+        self
+    }
 }
 
 impl WithParams for SoftmaxLossLayer {
@@ -210,6 +207,8 @@ impl WithParams for SoftmaxLossLayer {
         let mut cfg = HashMap::new();
 
         cfg.insert("size".to_owned(), Variant::Int(self.size as i32));
+        cfg.extend(self.loss.cfg());
+        cfg.insert("trainable".to_owned(), Variant::Bool(self.trainable));
 
         cfg
     }
@@ -227,5 +226,83 @@ impl WithParams for SoftmaxLossLayer {
             self.size = size;
             self.lr_params = CpuParams::empty();
         }
+
+        self.loss.set_cfg(cfg);
+
+        if let Some(Variant::Bool(trainable)) = cfg.get("trainable") {
+            self.trainable = *trainable;
+        }
+    }
+}
+
+impl Default for SoftmaxLossLayer {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataloader::{LabeledEntry, MiniBatch};
+    use crate::layers::grad_check::*;
+    use crate::models::*;
+
+    use ndarray::array;
+
+    const MODEL_YAML: &str = "
+ls:
+  - name: InputLayer
+    params:
+      size:
+        Int: 3
+  - name: SoftmaxLossLayer
+    params:
+      size:
+        Int: 2
+mdl_type: mdl_sequential_cpu
+batch_size: 2
+";
+
+    #[test]
+    fn loaded_without_trainable_key_is_trained() {
+        let mut mdl: Sequential = serde_yaml::from_str(MODEL_YAML).unwrap();
+        mdl.compile_shapes();
+        mdl.set_batch_size(2);
+
+        let out_ws = |mdl: &Sequential| {
+            let lp = mdl.last_layer().cpu_params().unwrap();
+            let ws = lp.get_2d_buf_t(TypeBuffer::Weights).borrow().clone();
+            ws
+        };
+
+        assert!(mdl.last_layer().is_trainable());
+
+        let ws_before = out_ws(&mdl);
+
+        mdl.feedforward(array![[0.5, -0.2, 0.1], [0.3, 0.8, -0.4]]);
+        mdl.backpropagate(array![[1.0, 0.0], [0.0, 1.0]]);
+        mdl.optimize();
+
+        assert_ne!(out_ws(&mdl), ws_before);
+    }
+
+    #[test]
+    fn masked_sparse_labels_grads_match_numeric() {
+        let entries = vec![
+            LabeledEntry::new(vec![0.5, -0.2, 0.1], vec![2.0]).mask(vec![1.0]),
+            LabeledEntry::new(vec![0.3, 0.8, -0.4], vec![0.0]).mask(vec![0.0]),
+            LabeledEntry::new(vec![-0.6, 0.4, 0.9], vec![3.0]),
+        ];
+        let batch = MiniBatch::new_no_ref(entries);
+
+        let mut l = SoftmaxLossLayer::new(4).sparse(true);
+        let loss_weights = batch.loss_weights();
+        check_sample_loss_layer(&mut l, batch.input, batch.output, &loss_weights);
+
+        // masked sample doesn't pass gradient
+        let neu_grad = l.lr_params.get_2d_buf_t(TypeBuffer::NeuGrad).borrow().clone();
+        assert!(neu_grad.row(1).iter().all(|v| *v == 0.0));
+        assert!(neu_grad.row(0).iter().any(|v| *v != 0.0));
     }
 }
@@ -3,7 +3,7 @@ use crate::cpu_params::*;
 use crate::ocl::*;
 use crate::util::*;
 
-use log::{debug, warn};
+use log::{debug, error, warn};
 
 use ndarray_stats::QuantileExt;
 use ocl::MemFlags;
@@ -70,9 +70,11 @@ static SOFTMAX_LOSS_KERNEL_BWD: &'static str = r#"
                 __private int const batch_size,
                 __private int const prev_shape,
                 __private int const self_shape,
+                __private int const trainable,
                 __global const float *self_out,
                 __global const float *prev_out,
                 __global const float *labels,
+                __global const float *factors,
                 __global float *neu_grad, // counter
                 __global float *ws_grad)
     {
@@ -80,19 +82,28 @@ static SOFTMAX_LOSS_KERNEL_BWD: &'static str = r#"
 
         for (int i = 0; i < batch_size; ++i) {
             __private int inner_idx = i * self_shape + idx;
-            neu_grad[inner_idx] = (labels[inner_idx] - self_out[inner_idx]);
+            __private float labels_sum = 0.0;
+
+            for (int j = 0; j < self_shape; ++j) {
+                labels_sum += factors[i * self_shape + j] * labels[i * self_shape + j];
+            }
+
+            neu_grad[inner_idx] = factors[inner_idx] * labels[inner_idx] - self_out[inner_idx] * labels_sum;
         }
             
-        for (int i = 0; i < prev_shape; ++i) {
-            __private float avg_grad = 0.0;
+        // frozen layer only computes neurons gradient for the previous layer
+        if (trainable) {
+            for (int i = 0; i < prev_shape; ++i) {
+                __private float avg_grad = 0.0;
 
-            for (int j = 0; j < batch_size; ++j) {
-                avg_grad += neu_grad[j * self_shape + idx] * prev_out[j * prev_shape + i];
-            }
+                for (int j = 0; j < batch_size; ++j) {
+                    avg_grad += neu_grad[j * self_shape + idx] * prev_out[j * prev_shape + i];
+                }
 
-            avg_grad = avg_grad / batch_size;
+                avg_grad = avg_grad / batch_size;
 
-            ws_grad[idx * prev_shape + i] = avg_grad;
+                ws_grad[idx * prev_shape + i] = avg_grad;
+            }
         }
     }
 "#;
@@ -107,6 +118,7 @@ pub struct SoftmaxLossLayerOcl {
     ocl_queue: Option<Queue>,
     ocl_kernel: Option<Kernel>,
     ocl_kernel_grad: Option<Kernel>,
+    trainable: bool,
 }
 
 impl SoftmaxLossLayerOcl {
@@ -120,6 +132,7 @@ impl SoftmaxLossLayerOcl {
             ocl_queue: None,
             ocl_kernel: None,
             ocl_kernel_grad: None,
+            trainable: true,
         }
     }
 }
@@ -129,6 +142,14 @@ impl AbstractLayer for SoftmaxLossLayerOcl {
         "SoftmaxLossLayerOcl"
     }
 
+    fn is_trainable(&self) -> bool {
+        self.trainable
+    }
+
+    fn set_trainable(&mut self, trainable: bool) {
+        self.trainable = trainable;
+    }
+
     fn size(&self) -> usize {
         self.size
     }
@@ -249,9 +270,11 @@ impl AbstractLayerOcl for SoftmaxLossLayerOcl {
             .arg_named("batch_size", self.batch_size as i32)
             .arg_named("prev_shape", 0 as i32)
             .arg_named("self_shape", self.size as i32)
+            .arg_named("trainable", self.trainable as i32)
             .arg_named("self_out", None::<&Buffer<f32>>)
             .arg_named("prev_out", None::<&Buffer<f32>>)
             .arg_named("labels", None::<&Buffer<f32>>)
+            .arg_named("factors", None::<&Buffer<f32>>)
             .arg_named("neu_grad", None::<&Buffer<f32>>)
             .arg_named("ws_grad", None::<&Buffer<f32>>)
             .build()?;
@@ -301,6 +324,7 @@ impl AbstractLayerOcl for SoftmaxLossLayerOcl {
         &mut self,
         prev_input: OclParamsBlob,
         expected: Array2D,
+        loss_weights: &LossWeights,
     ) -> LayerOclResult {
         let ocl_queue = self.ocl_queue.as_ref().unwrap();
 
@@ -312,6 +336,22 @@ impl AbstractLayerOcl for SoftmaxLossLayerOcl {
             .build()
             .expect("[euc_ocl] Couldn't create label buffer");
 
+        let factors = match loss_weights.factors(expected.dim()) {
+            Ok(factors) => factors,
+            Err(e) => {
+                error!("{}", e);
+                return Err(LayerError::InvalidSize);
+            }
+        };
+
+        let factors_buf = Buffer::builder()
+            .queue(ocl_queue.clone())
+            .flags(MemFlags::new().read_only())
+            .len(factors.len())
+            .copy_host_slice(factors.as_slice().unwrap())
+            .build()
+            .expect("[euc_ocl] Couldn't create loss factors buffer");
+
         let self_out = self.ocl_params.get_buf_t(TypeBuffer::Output);
         let self_out = self_out.0.borrow();
 
@@ -361,12 +401,18 @@ impl AbstractLayerOcl for SoftmaxLossLayerOcl {
         self_kern
             .set_arg("labels", &lbl_buf)
             .expect("[euc_ocl] Setting param LABELS failure");
+        self_kern
+            .set_arg("factors", &factors_buf)
+            .expect("[euc_ocl] Setting param FACTORS failure");
         self_kern
             .set_arg("neu_grad", &*self_neu_grad)
             .expect("[euc_ocl] Setting param NEU_GRAD failure");
         self_kern
             .set_arg("ws_grad", &*self_ws_grad)
             .expect("[euc_ocl] Setting param WS_GRAD failure");
+        self_kern
+            .set_arg("trainable", self.trainable as i32)
+            .expect("[euc_ocl] Setting param TRAINABLE failure");
 
         unsafe {
             self_kern
@@ -405,6 +451,7 @@ impl Default for SoftmaxLossLayerOcl {
             ocl_queue: None,
             ocl_kernel: None,
             ocl_kernel_grad: None,
+            trainable: true,
         }
     }
 }
@@ -422,6 +469,7 @@ impl Clone for SoftmaxLossLayerOcl {
             ocl_kernel: None,
             ocl_kernel_grad: None,
             ocl_queue: Some(queue.clone()),
+            trainable: self.trainable,
         }
     }
 
@@ -434,6 +482,8 @@ impl WithParams for SoftmaxLossLayerOcl {
     fn cfg(&self) -> HashMap<String, Variant> {
         let mut out = HashMap::new();
         out.insert("size".to_string(), Variant::Int(self.size as i32));
+        out.insert("trainable".to_owned(), Variant::Bool(self.trainable));
+
         out
     }
 
@@ -443,5 +493,9 @@ impl WithParams for SoftmaxLossLayerOcl {
                 self.size = *size as usize;
             }
         }
+
+        if let Some(Variant::Bool(trainable)) = args.get("trainable") {
+            self.trainable = *trainable;
+        }
     }
 }
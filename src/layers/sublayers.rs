@@ -0,0 +1,94 @@
+use std::cell::RefCell;
+use std::sync::Arc;
+
+use super::abstract_layer::AbstractLayer;
+use crate::cpu_params::*;
+use crate::util::*;
+
+// Helpers for layers composed of other layers (Bidirectional, TransformerEncoderLayer and etc.).
+// Trainable buffers of i-th sublayer are stored in the composite layer params
+// with ids shifted by i * SUBLAYER_BUF_OFFSET, so optimizers and serialization
+// work with a single CpuParams per layer
+
+pub(crate) const SUBLAYER_BUF_OFFSET: i32 = 100;
+
+pub(crate) fn new_2d_buf(arr: Array2D) -> VariantParamArc {
+    VariantParamArc::Array2(Arc::new(RefCell::new(arr)))
+}
+
+/// Params with only Output buffer, used as input for sublayers forward pass
+pub(crate) fn output_params(out: Array2D) -> CpuParams {
+    let mut lp = CpuParams::empty();
+    lp.insert_buf(TypeBuffer::Output as i32, new_2d_buf(out));
+    lp
+}
+
+/// Params with only InputGrad buffer, used as next layer for sublayers backward pass
+pub(crate) fn grad_params(grad: Array2D) -> CpuParams {
+    let mut lp = CpuParams::empty();
+    lp.insert_buf(TypeBuffer::InputGrad as i32, new_2d_buf(grad));
+    lp
+}
+
+/// Returns shifted (trainable bufs, trainable grads) and serializable bufs ids of sublayers
+pub(crate) fn sublayers_buf_ids(
+    layers: &[&dyn AbstractLayer],
+) -> ((Vec<i32>, Vec<i32>), Vec<i32>) {
+    let mut trainable = (Vec::new(), Vec::new());
+    let mut serializable = Vec::new();
+
+    for (i, l) in layers.iter().enumerate() {
+        let offset = i as i32 * SUBLAYER_BUF_OFFSET;
+        let (bufs, grads) = l.trainable_bufs();
+
+        trainable.0.extend(bufs.iter().map(|id| id + offset));
+        trainable.1.extend(grads.iter().map(|id| id + offset));
+        serializable.extend(l.serializable_bufs().iter().map(|id| id + offset));
+    }
+
+    (trainable, serializable)
+}
+
+/// Inserts trainable and serializable buffers of sublayers into composite layer params
+pub(crate) fn collect_sublayers_params(lp: &mut CpuParams, layers: &[&dyn AbstractLayer]) {
+    for (i, l) in layers.iter().enumerate() {
+        let offset = i as i32 * SUBLAYER_BUF_OFFSET;
+        let l_lp = l.cpu_params().unwrap();
+        let (bufs, grads) = l.trainable_bufs();
+
+        // sublayer with invalid input shape has no buffers allocated
+        for id in bufs.iter().chain(grads).chain(l.serializable_bufs()) {
+            if l_lp.contains_buf(*id) {
+                lp.insert_buf(id + offset, l_lp.get_param(*id));
+            }
+        }
+    }
+}
+
+/// Passes buffers stored in composite layer params back to sublayers.
+/// Sublayers get their own output and gradient buffers,
+/// so the cloned layer doesn't overwrite ones of the original layer
+pub(crate) fn distribute_sublayers_params(lp: &CpuParams, layers: &mut [&mut dyn AbstractLayer]) {
+    for (i, l) in layers.iter_mut().enumerate() {
+        let offset = i as i32 * SUBLAYER_BUF_OFFSET;
+        let mut l_lp = l.cpu_params().unwrap();
+        let (bufs, grads) = l.trainable_bufs();
+
+        for id in bufs.iter().chain(grads).chain(l.serializable_bufs()) {
+            if lp.contains_buf(id + offset) {
+                l_lp.insert_buf(*id, lp.get_param(id + offset));
+            }
+        }
+
+        for id in [TypeBuffer::Output, TypeBuffer::NeuGrad, TypeBuffer::InputGrad] {
+            let id = id as i32;
+
+            if l_lp.contains_buf(id) {
+                let dim = l_lp.get_2d_buf(id).borrow().dim();
+                l_lp.insert_buf(id, new_2d_buf(Array2D::zeros(dim)));
+            }
+        }
+
+        l.set_cpu_params(l_lp);
+    }
+}
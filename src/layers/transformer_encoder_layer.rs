@@ -0,0 +1,465 @@
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+
+use log::{debug, error};
+
+use super::abstract_layer::{
+    next_layer_grad, AbstractLayer, LayerBackwardResult, LayerError, LayerForwardResult,
+    TrainableBufsIds,
+};
+use super::layer_norm_layer::LayerNormLayer;
+use super::multi_head_attention_layer::{MultiHeadAttentionLayer, PositionalEncoding};
+use super::rnn_layer::seq_input_shape;
+use super::sublayers::*;
+use crate::cpu_params::*;
+use crate::layer_fabric::create_layer;
+use crate::util::*;
+
+// sublayers indices
+const ATTENTION: usize = 0;
+const NORM_1: usize = 1;
+const FEED_FORWARD_1: usize = 2;
+const FEED_FORWARD_2: usize = 3;
+const NORM_2: usize = 4;
+
+/// Transformer encoder block with post layer normalization :
+/// h = LayerNorm(x + MultiHeadAttention(x)), y = LayerNorm(h + Fc(Relu(Fc(h)))).
+/// Feed-forward and normalization sublayers are applied to each sequence element.
+/// Input and output shapes are [seq_len, model_dim]
+pub struct TransformerEncoderLayer {
+    pub lr_params: CpuParams,
+    num_heads: usize,
+    model_dim: usize,
+    ff_size: usize,
+    seq_len: usize,
+    causal: bool,
+    pos_encoding: PositionalEncoding,
+    layers: Vec<Box<dyn AbstractLayer>>,
+    // inputs of normalization sublayers from the last forward pass
+    norm_1_input: CpuParams,
+    norm_2_input: CpuParams,
+    trainable_ids: (Vec<i32>, Vec<i32>),
+    serializable_ids: Vec<i32>,
+    trainable: bool,
+}
+
+impl TransformerEncoderLayer {
+    pub fn new(num_heads: usize, model_dim: usize, ff_size: usize) -> Self {
+        Self {
+            lr_params: CpuParams::empty(),
+            num_heads,
+            model_dim,
+            ff_size,
+            seq_len: 0,
+            causal: false,
+            pos_encoding: PositionalEncoding::None,
+            layers: Vec::new(),
+            norm_1_input: CpuParams::empty(),
+            norm_2_input: CpuParams::empty(),
+            trainable_ids: (Vec::new(), Vec::new()),
+            serializable_ids: Vec::new(),
+            trainable: true,
+        }
+    }
+
+    pub fn new_box(num_heads: usize, model_dim: usize, ff_size: usize) -> Box<Self> {
+        Box::new(TransformerEncoderLayer::new(num_heads, model_dim, ff_size))
+    }
+
+    pub fn causal(mut self, state: bool) -> Self {
+        self.causal = state;
+        self
+    }
+
+    pub fn pos_encoding(mut self, pos_encoding: PositionalEncoding) -> Self {
+        self.pos_encoding = pos_encoding;
+        self
+    }
+
+    fn fc_layer(size: usize, activation: &str) -> Box<dyn AbstractLayer> {
+        let mut cfg = HashMap::new();
+        cfg.insert("size".to_owned(), Variant::Int(size as i32));
+        cfg.insert(
+            "activation".to_owned(),
+            Variant::String(activation.to_owned()),
+        );
+
+        create_layer("FcLayer", Some(&cfg)).unwrap()
+    }
+
+    fn create_sublayers(&mut self) {
+        let mha = MultiHeadAttentionLayer::new(self.num_heads, self.model_dim)
+            .causal(self.causal)
+            .pos_encoding(self.pos_encoding);
+
+        self.layers = vec![
+            Box::new(mha),
+            LayerNormLayer::new_box(),
+            TransformerEncoderLayer::fc_layer(self.ff_size, "relu"),
+            TransformerEncoderLayer::fc_layer(self.model_dim, "raw"),
+            LayerNormLayer::new_box(),
+        ];
+
+        for l in self.layers.iter_mut() {
+            l.set_trainable(self.trainable);
+        }
+    }
+
+    fn sublayers(&self) -> Vec<&dyn AbstractLayer> {
+        self.layers.iter().map(|l| l.as_ref()).collect()
+    }
+
+    fn update_buf_ids(&mut self) {
+        let (trainable, serializable) = sublayers_buf_ids(&self.sublayers());
+
+        self.trainable_ids = trainable;
+        self.serializable_ids = serializable;
+    }
+
+    /// Collects trainable buffers of sublayers into own params
+    fn collect_params(&mut self) {
+        let mut lp = CpuParams::new_only_output(self.size());
+        lp.add_input_grad(self.size());
+
+        collect_sublayers_params(&mut lp, &self.sublayers());
+
+        self.lr_params = lp;
+    }
+
+    fn sublayer_output(&self, idx: usize) -> Array2D {
+        self.layers[idx]
+            .cpu_params()
+            .unwrap()
+            .get_2d_buf_t(TypeBuffer::Output)
+            .borrow()
+            .clone()
+    }
+
+    /// Input gradient of sublayer, computed from its weights if it has no InputGrad buffer
+    fn sublayer_grad(&self, idx: usize) -> Array2D {
+        next_layer_grad(&self.layers[idx].cpu_params().unwrap())
+    }
+
+    /// Reshapes (batch, seq_len * model_dim) matrix to (batch * seq_len, model_dim)
+    fn to_tokens(&self, m: &Array2D) -> Array2D {
+        let rows = m.nrows() * self.seq_len;
+        m.to_owned().into_shape((rows, self.model_dim)).unwrap()
+    }
+
+    fn to_sequences(&self, m: Array2D) -> Array2D {
+        let rows = m.nrows() / self.seq_len;
+        m.into_shape((rows, self.size())).unwrap()
+    }
+
+    /// Token-wise sublayers average gradients over batch * seq_len rows,
+    /// rescales them to the mean over the batch
+    fn scale_token_grads(&self) {
+        for l in self.layers.iter().skip(1) {
+            let lp = l.cpu_params().unwrap();
+
+            for id in l.trainable_bufs().1 {
+                match lp.get_param(*id) {
+                    VariantParamArc::Array1(arr) => *arr.borrow_mut() *= self.seq_len as f32,
+                    VariantParamArc::Array2(arr) => *arr.borrow_mut() *= self.seq_len as f32,
+                }
+            }
+        }
+    }
+
+    fn copy_with_layers(&self, layers: Vec<Box<dyn AbstractLayer>>) -> Self {
+        Self {
+            lr_params: CpuParams::empty(),
+            num_heads: self.num_heads,
+            model_dim: self.model_dim,
+            ff_size: self.ff_size,
+            seq_len: self.seq_len,
+            causal: self.causal,
+            pos_encoding: self.pos_encoding,
+            layers,
+            norm_1_input: CpuParams::empty(),
+            norm_2_input: CpuParams::empty(),
+            trainable_ids: self.trainable_ids.clone(),
+            serializable_ids: self.serializable_ids.clone(),
+            trainable: self.trainable,
+        }
+    }
+}
+
+impl AbstractLayer for TransformerEncoderLayer {
+    fn forward(&mut self, input: ParamsBlob) -> LayerForwardResult {
+        let inp_m = input[0].get_2d_buf_t(TypeBuffer::Output);
+        let inp_m = inp_m.borrow();
+        let inp_m = inp_m.deref();
+
+        if self.seq_len == 0 || inp_m.ncols() != self.size() {
+            error!(
+                "Invalid input size for TransformerEncoderLayer : {}, expected : {}",
+                inp_m.ncols(),
+                self.size()
+            );
+            return Err(LayerError::InvalidSize);
+        }
+
+        let batch_len = inp_m.nrows();
+
+        self.layers[ATTENTION].set_batch_size(batch_len);
+
+        for l in self.layers.iter_mut().skip(1) {
+            l.set_batch_size(batch_len * self.seq_len);
+        }
+
+        self.layers[ATTENTION].forward(input.clone())?;
+
+        let res_1 = self.sublayer_output(ATTENTION) + inp_m;
+        self.norm_1_input = output_params(self.to_tokens(&res_1));
+
+        let norm_1_out = self.layers[NORM_1].forward(vec![self.norm_1_input.clone()])?;
+        let ff_1_out = self.layers[FEED_FORWARD_1].forward(norm_1_out)?;
+        self.layers[FEED_FORWARD_2].forward(ff_1_out)?;
+
+        let res_2 = self.sublayer_output(FEED_FORWARD_2) + self.sublayer_output(NORM_1);
+        self.norm_2_input = output_params(res_2);
+
+        self.layers[NORM_2].forward(vec![self.norm_2_input.clone()])?;
+
+        let out = self.to_sequences(self.sublayer_output(NORM_2));
+
+        let out_m = self.lr_params.get_2d_buf_t(TypeBuffer::Output);
+        let mut out_m = out_m.borrow_mut();
+        let out_m = out_m.deref_mut();
+
+        out_m.assign(&out);
+
+        debug!("[ok] TransformerEncoderLayer forward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn backward(&mut self, prev_input: ParamsBlob, next_input: ParamsBlob) -> LayerBackwardResult {
+        let next_grad = self.to_tokens(&next_layer_grad(&next_input[0]));
+
+        self.layers[NORM_2].backward(
+            vec![self.norm_2_input.clone()],
+            vec![grad_params(next_grad)],
+        )?;
+
+        let norm_2_grad = self.sublayer_grad(NORM_2);
+
+        let ff_1_params = self.layers[FEED_FORWARD_1].cpu_params().unwrap();
+        self.layers[FEED_FORWARD_2]
+            .backward(vec![ff_1_params], vec![grad_params(norm_2_grad.clone())])?;
+
+        let norm_1_params = self.layers[NORM_1].cpu_params().unwrap();
+        let ff_2_params = self.layers[FEED_FORWARD_2].cpu_params().unwrap();
+        self.layers[FEED_FORWARD_1].backward(vec![norm_1_params], vec![ff_2_params])?;
+
+        // residual connection passes gradient around feed-forward sublayers
+        let norm_1_grad = norm_2_grad + self.sublayer_grad(FEED_FORWARD_1);
+
+        self.layers[NORM_1].backward(
+            vec![self.norm_1_input.clone()],
+            vec![grad_params(norm_1_grad)],
+        )?;
+
+        if self.trainable {
+            self.scale_token_grads();
+        }
+
+        let res_1_grad = self.to_sequences(self.sublayer_grad(NORM_1));
+
+        self.layers[ATTENTION].backward(prev_input, vec![grad_params(res_1_grad.clone())])?;
+
+        let inp_grad = self.lr_params.get_2d_buf_t(TypeBuffer::InputGrad);
+        let mut inp_grad = inp_grad.borrow_mut();
+
+        *inp_grad = res_1_grad + self.sublayer_grad(ATTENTION);
+
+        debug!("[ok] TransformerEncoderLayer backward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn cpu_params(&self) -> Option<CpuParams> {
+        Some(self.lr_params.clone())
+    }
+
+    fn set_cpu_params(&mut self, lp: CpuParams) {
+        self.lr_params = lp;
+
+        let mut layers: Vec<&mut dyn AbstractLayer> =
+            self.layers.iter_mut().map(|l| l.as_mut() as &mut dyn AbstractLayer).collect();
+        distribute_sublayers_params(&self.lr_params, &mut layers);
+    }
+
+    fn layer_type(&self) -> &str {
+        "TransformerEncoderLayer"
+    }
+
+    fn is_trainable(&self) -> bool {
+        self.trainable
+    }
+
+    fn set_trainable(&mut self, trainable: bool) {
+        self.trainable = trainable;
+
+        for l in self.layers.iter_mut() {
+            l.set_trainable(trainable);
+        }
+    }
+
+    fn set_train_mode(&mut self, is_train: bool) {
+        for l in self.layers.iter_mut() {
+            l.set_train_mode(is_train);
+        }
+    }
+
+    fn trainable_bufs(&self) -> TrainableBufsIds {
+        (&self.trainable_ids.0, &self.trainable_ids.1)
+    }
+
+    fn serializable_bufs(&self) -> &[i32] {
+        &self.serializable_ids
+    }
+
+    /// Accepts [seq_len, model_dim] shape or flat shape, which is split by model_dim.
+    /// Carefull this method overwrites weights and all other params
+    fn set_input_shape(&mut self, sh: &[usize]) {
+        let (seq_len, model_dim) = match seq_input_shape(sh, self.model_dim) {
+            Some(split) => split,
+            None => {
+                error!(
+                    "TransformerEncoderLayer couldn't split input shape {:?} by model_dim {}",
+                    sh, self.model_dim
+                );
+                return;
+            }
+        };
+
+        // attention sublayer couldn't be initialized, so there are no params to collect
+        if self.num_heads == 0 || model_dim % self.num_heads != 0 {
+            error!(
+                "TransformerEncoderLayer model_dim {} isn't divisible by num_heads {}",
+                model_dim, self.num_heads
+            );
+            return;
+        }
+
+        self.seq_len = seq_len;
+        self.model_dim = model_dim;
+
+        self.create_sublayers();
+
+        let token_sh = [self.model_dim];
+
+        self.layers[ATTENTION].set_input_shape(&[self.seq_len, self.model_dim]);
+        self.layers[NORM_1].set_input_shape(&token_sh);
+        self.layers[FEED_FORWARD_1].set_input_shape(&token_sh);
+        self.layers[FEED_FORWARD_2].set_input_shape(&[self.ff_size]);
+        self.layers[NORM_2].set_input_shape(&token_sh);
+
+        self.update_buf_ids();
+        self.collect_params();
+    }
+
+    fn size(&self) -> usize {
+        self.seq_len * self.model_dim
+    }
+
+    /// Token-wise sublayers are applied to each token
+    fn forward_flops(&self) -> usize {
+        self.layers
+            .iter()
+            .enumerate()
+            .map(|(idx, l)| match idx {
+                ATTENTION => l.forward_flops(),
+                _ => self.seq_len * l.forward_flops(),
+            })
+            .sum()
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        vec![self.seq_len, self.model_dim]
+    }
+
+    fn copy_layer(&self) -> Box<dyn AbstractLayer> {
+        let layers = self.layers.iter().map(|l| l.copy_layer()).collect();
+        let mut copy_l = self.copy_with_layers(layers);
+
+        if self.seq_len > 0 {
+            copy_l.collect_params();
+        }
+        Box::new(copy_l)
+    }
+
+    fn clone_layer(&self) -> Box<dyn AbstractLayer> {
+        let layers = self.layers.iter().map(|l| l.clone_layer()).collect();
+        let mut clone_l = self.copy_with_layers(layers);
+
+        clone_l.lr_params = self.lr_params.clone();
+        Box::new(clone_l)
+    }
+}
+
+impl WithParams for TransformerEncoderLayer {
+    fn cfg(&self) -> HashMap<String, Variant> {
+        let mut cfg: HashMap<String, Variant> = HashMap::new();
+
+        cfg.insert("num_heads".to_owned(), Variant::Int(self.num_heads as i32));
+        cfg.insert("model_dim".to_owned(), Variant::Int(self.model_dim as i32));
+        cfg.insert("ff_size".to_owned(), Variant::Int(self.ff_size as i32));
+        cfg.insert("causal".to_owned(), Variant::Bool(self.causal));
+        cfg.insert(
+            "pos_encoding".to_owned(),
+            Variant::String(self.pos_encoding.name().to_owned()),
+        );
+        cfg.insert("trainable".to_owned(), Variant::Bool(self.trainable));
+
+        cfg
+    }
+
+    fn set_cfg(&mut self, cfg: &HashMap<String, Variant>) {
+        if let Some(Variant::Int(num_heads)) = cfg.get("num_heads") {
+            self.num_heads = *num_heads as usize;
+        }
+
+        if let Some(Variant::Int(model_dim)) = cfg.get("model_dim") {
+            self.model_dim = *model_dim as usize;
+        }
+
+        if let Some(Variant::Int(ff_size)) = cfg.get("ff_size") {
+            self.ff_size = *ff_size as usize;
+        }
+
+        if let Some(Variant::Bool(causal)) = cfg.get("causal") {
+            self.causal = *causal;
+        }
+
+        if let Some(Variant::String(pos_encoding)) = cfg.get("pos_encoding") {
+            if let Some(pos_encoding) = PositionalEncoding::from_name(pos_encoding) {
+                self.pos_encoding = pos_encoding;
+            } else {
+                error!("Unknown positional encoding : {}", pos_encoding);
+            }
+        }
+
+        self.lr_params = CpuParams::empty();
+        self.layers.clear();
+        self.seq_len = 0;
+
+        if let Some(Variant::Bool(trainable)) = cfg.get("trainable") {
+            self.trainable = *trainable;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::grad_check::*;
+
+    #[test]
+    fn encoder_block_grads_match_numeric() {
+        let mut l = TransformerEncoderLayer::new(2, 4, 6);
+        check_layer(&mut l, &[3, 4], test_values(2, 12, 1));
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+
+use ndarray::Zip;
+
+use log::{debug, error};
+
+use super::abstract_layer::{
+    next_layer_grad, AbstractLayer, LayerBackwardResult, LayerError, LayerForwardResult,
+    TrainableBufsIds,
+};
+use crate::cpu_params::*;
+use crate::util::*;
+
+/// Interpolation mode of Upsample2DLayer
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UpsampleMode {
+    Nearest,
+    Bilinear,
+}
+
+impl UpsampleMode {
+    pub fn name(&self) -> &str {
+        match self {
+            UpsampleMode::Nearest => "nearest",
+            UpsampleMode::Bilinear => "bilinear",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "nearest" => Some(UpsampleMode::Nearest),
+            "bilinear" => Some(UpsampleMode::Bilinear),
+            _ => None,
+        }
+    }
+}
+
+/// Source indices and weights of the two input values used for each output coordinate
+#[derive(Clone, Copy, Default)]
+struct InterpPoint {
+    idx_0: usize,
+    idx_1: usize,
+    w_0: f32,
+    w_1: f32,
+}
+
+fn interp_points(inp_len: usize, scale: usize, mode: UpsampleMode) -> Vec<InterpPoint> {
+    (0..inp_len * scale)
+        .map(|out_i| match mode {
+            UpsampleMode::Nearest => InterpPoint {
+                idx_0: out_i / scale,
+                idx_1: out_i / scale,
+                w_0: 1.0,
+                w_1: 0.0,
+            },
+            UpsampleMode::Bilinear => {
+                // pixel centers are aligned, values outside of input are clamped
+                let src = ((out_i as f32 + 0.5) / scale as f32 - 0.5).max(0.0);
+                let idx_0 = (src.floor() as usize).min(inp_len - 1);
+                let idx_1 = (idx_0 + 1).min(inp_len - 1);
+                let w_1 = src - idx_0 as f32;
+
+                InterpPoint {
+                    idx_0,
+                    idx_1,
+                    w_0: 1.0 - w_1,
+                    w_1,
+                }
+            }
+        })
+        .collect()
+}
+
+/// Upsamples (channels, height, width) input by integer scale factor
+/// with nearest or bilinear interpolation
+#[derive(Clone)]
+pub struct Upsample2DLayer {
+    pub lr_params: CpuParams,
+    scale: usize,
+    mode: UpsampleMode,
+    channels: usize,
+    height: usize,
+    width: usize,
+    rows: Vec<InterpPoint>,
+    cols: Vec<InterpPoint>,
+}
+
+impl AbstractLayer for Upsample2DLayer {
+    fn forward(&mut self, input: ParamsBlob) -> LayerForwardResult {
+        let inp_m = input[0].get_2d_buf_t(TypeBuffer::Output);
+        let inp_m = inp_m.borrow();
+        let inp_m = inp_m.deref();
+
+        let inp_len = self.channels * self.height * self.width;
+
+        if inp_len == 0 || inp_m.ncols() != inp_len {
+            error!(
+                "Invalid input size for Upsample2DLayer : {}, expected : {}",
+                inp_m.ncols(),
+                inp_len
+            );
+            return Err(LayerError::InvalidSize);
+        }
+
+        let out_m = self.lr_params.get_2d_buf_t(TypeBuffer::Output);
+        let mut out_m = out_m.borrow_mut();
+        let out_m = out_m.deref_mut();
+
+        let (h, w) = (self.height, self.width);
+        let (out_h, out_w) = (self.rows.len(), self.cols.len());
+
+        Zip::from(inp_m.rows())
+            .and(out_m.rows_mut())
+            .par_for_each(|inp_r, mut out_r| {
+                // for each batch
+                for c in 0..self.channels {
+                    for (oy, r) in self.rows.iter().enumerate() {
+                        for (ox, col) in self.cols.iter().enumerate() {
+                            let at = |y: usize, x: usize| inp_r[(c * h + y) * w + x];
+
+                            out_r[(c * out_h + oy) * out_w + ox] = r.w_0
+                                * (col.w_0 * at(r.idx_0, col.idx_0)
+                                    + col.w_1 * at(r.idx_0, col.idx_1))
+                                + r.w_1
+                                    * (col.w_0 * at(r.idx_1, col.idx_0)
+                                        + col.w_1 * at(r.idx_1, col.idx_1));
+                        }
+                    }
+                }
+            });
+
+        debug!("[ok] Upsample2DLayer forward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn backward(&mut self, _prev_input: ParamsBlob, next_input: ParamsBlob) -> LayerBackwardResult {
+        let next_grad = next_layer_grad(&next_input[0]);
+
+        let inp_grad = self.lr_params.get_2d_buf_t(TypeBuffer::InputGrad);
+        let mut inp_grad = inp_grad.borrow_mut();
+        let inp_grad = inp_grad.deref_mut();
+
+        let (h, w) = (self.height, self.width);
+        let (out_h, out_w) = (self.rows.len(), self.cols.len());
+
+        Zip::from(inp_grad.rows_mut())
+            .and(next_grad.rows())
+            .par_for_each(|mut inp_grad_r, grad_r| {
+                inp_grad_r.fill(0.0);
+
+                for c in 0..self.channels {
+                    for (oy, r) in self.rows.iter().enumerate() {
+                        for (ox, col) in self.cols.iter().enumerate() {
+                            let grad = grad_r[(c * out_h + oy) * out_w + ox];
+
+                            for (y, w_y) in [(r.idx_0, r.w_0), (r.idx_1, r.w_1)] {
+                                for (x, w_x) in [(col.idx_0, col.w_0), (col.idx_1, col.w_1)] {
+                                    inp_grad_r[(c * h + y) * w + x] += w_y * w_x * grad;
+                                }
+                            }
+                        }
+                    }
+                }
+            });
+
+        debug!("[ok] Upsample2DLayer backward()");
+
+        Ok(vec![self.lr_params.clone()])
+    }
+
+    fn cpu_params(&self) -> Option<CpuParams> {
+        Some(self.lr_params.clone())
+    }
+
+    fn set_cpu_params(&mut self, lp: CpuParams) {
+        self.lr_params = lp;
+    }
+
+    fn layer_type(&self) -> &str {
+        "Upsample2DLayer"
+    }
+
+    fn trainable_bufs(&self) -> TrainableBufsIds {
+        (&[], &[])
+    }
+
+    fn serializable_bufs(&self) -> &[i32] {
+        &[]
+    }
+
+    fn set_input_shape(&mut self, sh: &[usize]) {
+        if sh.len() != 3 || sh.contains(&0) || self.scale == 0 {
+            error!(
+                "Upsample2DLayer requires [channels, height, width] input shape, got {:?}",
+                sh
+            );
+            return;
+        }
+
+        self.channels = sh[0];
+        self.height = sh[1];
+        self.width = sh[2];
+
+        self.rows = interp_points(self.height, self.scale, self.mode);
+        self.cols = interp_points(self.width, self.scale, self.mode);
+
+        self.lr_params = CpuParams::new_only_output(self.size());
+        self.lr_params
+            .add_input_grad(self.channels * self.height * self.width);
+    }
+
+    fn size(&self) -> usize {
+        self.channels * self.rows.len() * self.cols.len()
+    }
+
+    fn output_shape(&self) -> Vec<usize> {
+        vec![self.channels, self.rows.len(), self.cols.len()]
+    }
+
+    fn copy_layer(&self) -> Box<dyn AbstractLayer> {
+        let mut copy_l = self.clone();
+        copy_l.set_cpu_params(self.lr_params.copy());
+        Box::new(copy_l)
+    }
+
+    fn clone_layer(&self) -> Box<dyn AbstractLayer> {
+        Box::new(self.clone())
+    }
+}
+
+impl Upsample2DLayer {
+    pub fn new(scale: usize) -> Self {
+        Self {
+            lr_params: CpuParams::empty(),
+            scale,
+            mode: UpsampleMode::Nearest,
+            channels: 0,
+            height: 0,
+            width: 0,
+            rows: Vec::new(),
+            cols: Vec::new(),
+        }
+    }
+
+    pub fn new_box(scale: usize) -> Box<Self> {
+        Box::new(Upsample2DLayer::new(scale))
+    }
+
+    pub fn mode(mut self, mode: UpsampleMode) -> Self {
+        self.mode = mode;
+        self
+    }
+}
+
+impl WithParams for Upsample2DLayer {
+    fn cfg(&self) -> HashMap<String, Variant> {
+        let mut cfg: HashMap<String, Variant> = HashMap::new();
+
+        cfg.insert("scale".to_owned(), Variant::Int(self.scale as i32));
+        cfg.insert(
+            "mode".to_owned(),
+            Variant::String(self.mode.name().to_owned()),
+        );
+
+        cfg
+    }
+
+    fn set_cfg(&mut self, cfg: &HashMap<String, Variant>) {
+        if let Some(Variant::Int(scale)) = cfg.get("scale") {
+            self.scale = *scale as usize;
+        }
+
+        if let Some(Variant::String(mode)) = cfg.get("mode") {
+            if let Some(mode) = UpsampleMode::from_name(mode) {
+                self.mode = mode;
+            } else {
+                error!("Unknown Upsample2DLayer mode : {}", mode);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::grad_check::*;
+
+    #[test]
+    fn bilinear_grads_match_numeric() {
+        let mut l = Upsample2DLayer::new(2).mode(UpsampleMode::Bilinear);
+        check_layer(&mut l, &[2, 3, 3], test_values(2, 18, 1));
+    }
+}
@@ -4,7 +4,7 @@ use std::fmt;
 
 use log::{debug, error};
 
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
 use std::slice::IterMut;
 
@@ -23,14 +23,26 @@ pub trait LayersStorage {
     
 }
 
+/// Default name of the layer, layer type with the index among the layers of the same type
+pub fn default_layer_name(layer_type: &str, names: &[String]) -> String {
+    (0..)
+        .map(|idx| format!("{}_{}", layer_type, idx))
+        .find(|name| !names.contains(name))
+        .unwrap()
+}
+
 #[derive(Default)]
 pub struct SequentialLayersStorage {
     layers: Vec<Box<dyn AbstractLayer>>,
+    names: Vec<String>, // unique, used to match layers on state loading
 }
 
 impl SequentialLayersStorage {
     pub fn empty() -> Self {
-        SequentialLayersStorage { layers: Vec::new() }
+        SequentialLayersStorage {
+            layers: Vec::new(),
+            names: Vec::new(),
+        }
     }
 
     /// Setup the network with [0] - input size, [...] - hidden neurons, [N] - output size
@@ -78,6 +90,7 @@ impl SequentialLayersStorage {
             let mut lr = i.cpu_params().unwrap();
             lr.prepare_for_tests(batch_size);
             i.set_cpu_params(lr);
+            i.set_train_mode(false);
         }
     }
 
@@ -89,11 +102,38 @@ impl SequentialLayersStorage {
         return self.layers.iter();
     }
 
+    /// Adds layer with default name
     pub fn add_layer(&mut self, l: Box<dyn AbstractLayer>) {
+        let name = default_layer_name(l.layer_type(), &self.names);
+        self.add_named_layer(&name, l);
+    }
+
+    /// Adds layer with the given name, layer with duplicated name gets default name
+    pub fn add_named_layer(&mut self, name: &str, l: Box<dyn AbstractLayer>) {
+        if self.names.iter().any(|n| n == name) {
+            let default_name = default_layer_name(l.layer_type(), &self.names);
+            error!("Duplicated layer name {}, renamed to {}", name, default_name);
+            self.names.push(default_name);
+        } else {
+            self.names.push(name.to_owned());
+        }
 
         self.layers.push(l);
     }
 
+    pub fn name(&self, id: usize) -> &str {
+        &self.names[id]
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Layers with their names
+    pub fn iter_named_mut(&mut self) -> impl Iterator<Item = (&str, &mut Box<dyn AbstractLayer>)> {
+        self.names.iter().map(|n| n.as_str()).zip(self.layers.iter_mut())
+    }
+
     pub fn len(&self) -> usize {
         self.layers.len()
     }
@@ -141,6 +181,9 @@ impl fmt::Display for SequentialLayersStorage {
 pub struct SerdeLayerParam {
     pub name: String,
     pub params: HashMap<String, Variant>,
+    /// Layer name, default one is set if missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layer_name: Option<String>,
 }
 
 /// Helper class to easy ser/deserialize
@@ -156,10 +199,11 @@ impl Serialize for SequentialLayersStorage {
     {
         let mut s_layers_storage = SerdeLayersStorage::default();
 
-        for l in self.layers.iter() {
+        for (l, l_name) in self.layers.iter().zip(self.names.iter()) {
             let s_layer_param = SerdeLayerParam {
                 name: l.layer_type().to_owned(),
                 params: l.cfg(),
+                layer_name: Some(l_name.clone()),
             };
             s_layers_storage.layers_cfg.push(s_layer_param);
         }
@@ -182,10 +226,12 @@ impl<'de> Deserialize<'de> for SequentialLayersStorage {
 
             if let Some(l) = l_opt {
                 debug!("Create layer : {}", i.name);
-                ls.layers.push(l);
+                match i.layer_name.as_ref() {
+                    Some(l_name) => ls.add_named_layer(l_name, l),
+                    None => ls.add_layer(l),
+                }
             } else {
-                // TODO : impl return D::Error
-                panic!("Bad deserialization");
+                return Err(de::Error::custom(format!("Can't create layer : {}", i.name)));
             }
         }
 
@@ -197,8 +243,8 @@ impl Clone for SequentialLayersStorage {
     fn clone(&self) -> Self {
         let mut ls = SequentialLayersStorage::empty();
 
-        for i in &self.layers {
-            ls.add_layer(i.clone_layer());
+        for (i, name) in self.layers.iter().zip(self.names.iter()) {
+            ls.add_named_layer(name, i.clone_layer());
         }
 
         ls
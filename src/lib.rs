@@ -1,5 +1,6 @@
 /// Folder
 pub mod layers;
+pub mod losses;
 pub mod optimizers;
 pub mod util;
 pub mod dataloader;
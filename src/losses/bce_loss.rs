@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+
+use log::error;
+
+use ndarray::{Axis, Zip};
+
+use crate::layers::LayerError;
+use crate::losses::*;
+use crate::util::*;
+
+/// Numerically stable sigmoid, doesn't overflow for large negative values
+pub(crate) fn stable_sigmoid(x: f32) -> f32 {
+    if x >= 0.0 {
+        1.0 / (1.0 + (-x).exp())
+    } else {
+        let e = x.exp();
+        e / (1.0 + e)
+    }
+}
+
+/// Binary cross-entropy of sigmoid over the model output logits, for multi-label problems.
+/// Optional per-label positive weights scale the loss of positive targets
+#[derive(Clone, Default)]
+pub struct BceLoss {
+    pos_weights: Vec<f32>,
+    metrics: Metrics,
+}
+
+impl BceLoss {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loss multipliers of positive targets for each label
+    pub fn pos_weights(mut self, pos_weights: &[f32]) -> Self {
+        self.pos_weights = pos_weights.to_vec();
+        self
+    }
+}
+
+impl Loss for BceLoss {
+    fn loss_type(&self) -> &str {
+        "BceLoss"
+    }
+
+    fn backward(
+        &mut self,
+        output: &Array2D,
+        expected: &Array2D,
+        loss_weights: &LossWeights,
+    ) -> Result<Array2D, LayerError> {
+        let size = output.ncols();
+
+        if expected.dim() != output.dim() {
+            error!(
+                "Invalid expected shape for BceLoss : {:?}, expected : {:?}",
+                expected.dim(),
+                output.dim()
+            );
+            return Err(LayerError::InvalidSize);
+        }
+
+        let pos_weights = if self.pos_weights.is_empty() {
+            Array1D::ones(size)
+        } else if self.pos_weights.len() == size {
+            Array1D::from_vec(self.pos_weights.clone())
+        } else {
+            error!(
+                "Invalid pos_weights size for BceLoss : {}, expected : {}",
+                self.pos_weights.len(),
+                size
+            );
+            return Err(LayerError::InvalidSize);
+        };
+
+        let mut losses = Array2D::zeros(output.dim());
+        let mut grad = Array2D::zeros(output.dim());
+
+        Zip::from(grad.rows_mut())
+            .and(losses.rows_mut())
+            .and(output.rows())
+            .and(expected.rows())
+            .par_for_each(|grad_r, loss_r, logit_r, expected_r| {
+                // for each batch
+                Zip::from(grad_r)
+                    .and(loss_r)
+                    .and(logit_r)
+                    .and(expected_r)
+                    .and(&pos_weights)
+                    .for_each(|grad_el, loss, logit, expected, pos_w| {
+                        let prob = stable_sigmoid(*logit);
+
+                        // log(sigmoid(x)) = -softplus(-x), log(1 - sigmoid(x)) = -softplus(x)
+                        *loss = pos_w * expected * softplus(-logit)
+                            + (1.0 - expected) * softplus(*logit);
+                        *grad_el = pos_w * expected * (1.0 - prob) - (1.0 - expected) * prob;
+                    });
+            });
+
+        if !loss_weights.is_empty() {
+            match loss_weights.factors(output.dim()) {
+                Ok(factors) => {
+                    grad *= &factors;
+                    losses *= &factors;
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    return Err(LayerError::InvalidSize);
+                }
+            }
+        }
+
+        let mut matches = Array2D::zeros(output.dim());
+
+        Zip::from(&mut matches)
+            .and(output)
+            .and(expected)
+            .par_for_each(|is_match, logit, expected| {
+                if (*logit >= 0.0) == (*expected >= 0.5) {
+                    *is_match = 1.0;
+                }
+            });
+
+        // masked-out outputs aren't counted, the mask shape is checked by factors above
+        let counted = match loss_weights.mask.as_ref() {
+            Some(mask) => {
+                matches *= mask;
+                mask.sum_axis(Axis(0))
+            }
+            None => Array1D::from_elem(output.ncols(), output.nrows() as f32),
+        };
+        let matched = matches.sum_axis(Axis(0));
+
+        let labels_acc = Zip::from(&matched)
+            .and(&counted)
+            .map_collect(|matched, counted| {
+                if *counted > 0.0 {
+                    matched / counted
+                } else {
+                    0.0
+                }
+            });
+        let total_counted = counted.sum();
+        let accuracy = if total_counted > 0.0 {
+            matched.sum() / total_counted
+        } else {
+            0.0
+        };
+
+        self.metrics.clear();
+        self.metrics.insert("accuracy".to_owned(), accuracy as f64);
+        self.metrics
+            .insert("loss".to_owned(), losses.mean().unwrap_or(0.0) as f64);
+
+        for (i, acc) in labels_acc.iter().enumerate() {
+            self.metrics.insert(format!("accuracy_{}", i), *acc as f64);
+        }
+
+        Ok(grad)
+    }
+
+    /// Per-label accuracy as "accuracy_{label}", accuracy over all labels as "accuracy"
+    /// and the mean loss value as "loss", masked-out outputs aren't counted by accuracy
+    fn metrics(&self) -> Option<&Metrics> {
+        Some(&self.metrics)
+    }
+
+    fn clone_loss(&self) -> Box<dyn Loss> {
+        Box::new(self.clone())
+    }
+}
+
+impl WithParams for BceLoss {
+    fn cfg(&self) -> HashMap<String, Variant> {
+        let mut cfg = HashMap::new();
+
+        if !self.pos_weights.is_empty() {
+            cfg.insert(
+                "pos_weights".to_owned(),
+                Variant::FloatArray(self.pos_weights.clone()),
+            );
+        }
+
+        cfg
+    }
+
+    fn set_cfg(&mut self, cfg: &HashMap<String, Variant>) {
+        if let Some(Variant::FloatArray(pos_weights)) = cfg.get("pos_weights") {
+            self.pos_weights = pos_weights.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn accuracy_skips_masked_outputs() {
+        let mut loss = BceLoss::new();
+        let output = array![[1.0, -1.0], [-1.0, -1.0], [1.0, 1.0]];
+        let expected = array![[1.0, 1.0], [1.0, 0.0], [1.0, 1.0]];
+        // wrong predictions of the first label are masked out, second label has no outputs left
+        let mask = array![[1.0, 0.0], [0.0, 0.0], [1.0, 0.0]];
+
+        loss.backward(&output, &expected, &LossWeights::new(None, Some(mask)))
+            .unwrap();
+
+        let metrics = loss.metrics().unwrap();
+        assert_eq!(metrics["accuracy_0"], 1.0);
+        assert_eq!(metrics["accuracy_1"], 0.0);
+        assert_eq!(metrics["accuracy"], 1.0);
+
+        loss.backward(&output, &expected, &LossWeights::default())
+            .unwrap();
+
+        let metrics = loss.metrics().unwrap();
+        assert_eq!(metrics["accuracy_0"], (2.0f32 / 3.0) as f64);
+        assert_eq!(metrics["accuracy_1"], (2.0f32 / 3.0) as f64);
+    }
+}
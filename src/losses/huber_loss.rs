@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use log::error;
+
+use crate::layers::LayerError;
+use crate::losses::*;
+use crate::util::*;
+
+/// Huber loss : squared error for residuals smaller than delta, absolute error otherwise,
+/// so outliers don't dominate the gradient
+#[derive(Clone)]
+pub struct HuberLoss {
+    delta: f32,
+    metrics: Metrics,
+}
+
+impl HuberLoss {
+    pub fn new(delta: f32) -> Self {
+        Self {
+            delta,
+            metrics: HashMap::new(),
+        }
+    }
+
+    pub fn delta(&self) -> f32 {
+        self.delta
+    }
+
+    pub fn set_delta(&mut self, delta: f32) {
+        self.delta = delta;
+    }
+}
+
+impl Default for HuberLoss {
+    fn default() -> Self {
+        Self::new(1.0)
+    }
+}
+
+impl Loss for HuberLoss {
+    fn loss_type(&self) -> &str {
+        "HuberLoss"
+    }
+
+    fn backward(
+        &mut self,
+        output: &Array2D,
+        expected: &Array2D,
+        loss_weights: &LossWeights,
+    ) -> Result<Array2D, LayerError> {
+        let delta = self.delta;
+
+        let (losses, grad) = elementwise_loss(
+            self.loss_type(),
+            output,
+            expected,
+            loss_weights,
+            |output, expected| {
+                let diff = expected - output;
+
+                if diff.abs() <= delta {
+                    (0.5 * diff * diff, diff)
+                } else {
+                    (delta * (diff.abs() - 0.5 * delta), delta * sign(diff))
+                }
+            },
+        )?;
+
+        regression_metrics(&mut self.metrics, output, expected, &losses);
+
+        Ok(grad)
+    }
+
+    fn metrics(&self) -> Option<&Metrics> {
+        Some(&self.metrics)
+    }
+
+    fn clone_loss(&self) -> Box<dyn Loss> {
+        Box::new(self.clone())
+    }
+}
+
+impl WithParams for HuberLoss {
+    fn cfg(&self) -> HashMap<String, Variant> {
+        let mut cfg = HashMap::new();
+
+        cfg.insert("delta".to_owned(), Variant::Float(self.delta));
+
+        cfg
+    }
+
+    fn set_cfg(&mut self, cfg: &HashMap<String, Variant>) {
+        if let Some(Variant::Float(delta)) = cfg.get("delta") {
+            if *delta > 0.0 {
+                self.delta = *delta;
+            } else {
+                error!("HuberLoss delta must be positive, got : {}", delta);
+            }
+        }
+    }
+}
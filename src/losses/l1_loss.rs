@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+
+use crate::layers::LayerError;
+use crate::losses::*;
+use crate::util::*;
+
+/// Absolute error loss : |expected - output|
+#[derive(Clone, Default)]
+pub struct L1Loss {
+    metrics: Metrics,
+}
+
+impl L1Loss {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Loss for L1Loss {
+    fn loss_type(&self) -> &str {
+        "L1Loss"
+    }
+
+    fn backward(
+        &mut self,
+        output: &Array2D,
+        expected: &Array2D,
+        loss_weights: &LossWeights,
+    ) -> Result<Array2D, LayerError> {
+        let (losses, grad) = elementwise_loss(
+            self.loss_type(),
+            output,
+            expected,
+            loss_weights,
+            |output, expected| {
+                let diff = expected - output;
+                (diff.abs(), sign(diff))
+            },
+        )?;
+
+        regression_metrics(&mut self.metrics, output, expected, &losses);
+
+        Ok(grad)
+    }
+
+    fn metrics(&self) -> Option<&Metrics> {
+        Some(&self.metrics)
+    }
+
+    fn clone_loss(&self) -> Box<dyn Loss> {
+        Box::new(self.clone())
+    }
+}
+
+impl WithParams for L1Loss {
+    fn cfg(&self) -> HashMap<String, Variant> {
+        HashMap::new()
+    }
+
+    fn set_cfg(&mut self, _args: &HashMap<String, Variant>) {}
+}
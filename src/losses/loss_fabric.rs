@@ -0,0 +1,30 @@
+use std::collections::HashMap;
+
+use log::warn;
+
+use crate::losses::*;
+use crate::util::*;
+
+/// Fabric used to create loss functions, when deserialing and other cases
+pub fn create_loss(
+    loss_type: &str,
+    cfg: Option<&HashMap<String, Variant>>,
+) -> Option<Box<dyn Loss>> {
+    let mut loss: Box<dyn Loss> = match loss_type {
+        "EuclideanLoss" => Box::new(EuclideanLoss::new()),
+        "L1Loss" => Box::new(L1Loss::new()),
+        "HuberLoss" => Box::new(HuberLoss::default()),
+        "SoftmaxCrossEntropyLoss" => Box::new(SoftmaxCrossEntropyLoss::new()),
+        "BceLoss" => Box::new(BceLoss::new()),
+        _ => {
+            warn!("Unknown loss type : {}", loss_type);
+            return None;
+        }
+    };
+
+    if let Some(cfg_val) = cfg {
+        loss.set_cfg(cfg_val);
+    }
+
+    Some(loss)
+}
@@ -0,0 +1,166 @@
+mod bce_loss;
+mod euclidean_loss;
+mod huber_loss;
+mod l1_loss;
+mod softmax_cross_entropy_loss;
+
+mod loss_fabric;
+
+pub use bce_loss::*;
+pub use euclidean_loss::*;
+pub use huber_loss::*;
+pub use l1_loss::*;
+pub use loss_fabric::*;
+pub use softmax_cross_entropy_loss::*;
+
+use std::ops::{Deref, DerefMut};
+
+use log::error;
+
+use ndarray::{Axis, Zip};
+
+use crate::cpu_params::*;
+use crate::layers::LayerError;
+use crate::util::*;
+
+/// Loss function applied to the model output, independent of the last layer type
+pub trait Loss: WithParams {
+    fn loss_type(&self) -> &str;
+
+    /// Returns negative gradient of the loss with respect to the model output
+    /// (the same sign convention as layers NeuGrad). Loss values are multiplied by loss_weights factors
+    fn backward(
+        &mut self,
+        output: &Array2D,
+        expected: &Array2D,
+        loss_weights: &LossWeights,
+    ) -> Result<Array2D, LayerError>;
+
+    /// Mean loss value as "loss" and loss specific metrics of the last backward() call
+    fn metrics(&self) -> Option<&Metrics>;
+
+    fn clone_loss(&self) -> Box<dyn Loss>;
+}
+
+impl Clone for Box<dyn Loss> {
+    fn clone(&self) -> Self {
+        self.clone_loss()
+    }
+}
+
+/// Regression metrics of the output : "mae", "rmse" and mean loss value as "loss"
+pub(crate) fn regression_metrics(
+    metrics: &mut Metrics,
+    output: &Array2D,
+    expected: &Array2D,
+    losses: &Array2D,
+) {
+    let diff = expected - output;
+
+    metrics.clear();
+    metrics.insert(
+        "mae".to_owned(),
+        diff.mapv(f32::abs).mean().unwrap_or(0.0) as f64,
+    );
+    metrics.insert(
+        "rmse".to_owned(),
+        diff.mapv(|v| v * v).mean().unwrap_or(0.0).sqrt() as f64,
+    );
+    metrics.insert("loss".to_owned(), losses.mean().unwrap_or(0.0) as f64);
+}
+
+/// Loss values and negative gradients of the loss applied to each output independently.
+/// loss_fn(output, expected) returns (loss, negative gradient)
+pub(crate) fn elementwise_loss<F>(
+    loss_type: &str,
+    output: &Array2D,
+    expected: &Array2D,
+    loss_weights: &LossWeights,
+    loss_fn: F,
+) -> Result<(Array2D, Array2D), LayerError>
+where
+    F: Fn(f32, f32) -> (f32, f32) + Sync,
+{
+    if output.dim() != expected.dim() {
+        error!(
+            "Invalid expected shape for {} : {:?}, expected : {:?}",
+            loss_type,
+            expected.dim(),
+            output.dim()
+        );
+        return Err(LayerError::InvalidSize);
+    }
+
+    let mut losses = Array2D::zeros(output.dim());
+    let mut grad = Array2D::zeros(output.dim());
+
+    Zip::from(&mut losses)
+        .and(&mut grad)
+        .and(output)
+        .and(expected)
+        .par_for_each(|loss, grad_el, output, expected| {
+            (*loss, *grad_el) = loss_fn(*output, *expected);
+        });
+
+    if !loss_weights.is_empty() {
+        match loss_weights.factors(output.dim()) {
+            Ok(factors) => {
+                losses *= &factors;
+                grad *= &factors;
+            }
+            Err(e) => {
+                error!("{}", e);
+                return Err(LayerError::InvalidSize);
+            }
+        }
+    }
+
+    Ok((losses, grad))
+}
+
+/// Weights and bias gradients of the loss layer from its neurons gradients
+pub(crate) fn loss_params_grad(
+    lr_params: &CpuParams,
+    prev_input: &Array2D,
+    l1_regul: f32,
+    l2_regul: f32,
+) {
+    let self_neu_grad = lr_params.get_2d_buf_t(TypeBuffer::NeuGrad);
+    let self_neu_grad = self_neu_grad.borrow();
+    let self_neu_grad = self_neu_grad.deref();
+
+    let ws = lr_params.get_2d_buf_t(TypeBuffer::Weights);
+    let ws = ws.borrow();
+    let ws = ws.deref();
+
+    let ws_grad = lr_params.get_2d_buf_t(TypeBuffer::WeightsGrad);
+    let mut ws_grad = ws_grad.borrow_mut();
+    let ws_grad = ws_grad.deref_mut();
+
+    let bias = lr_params.get_1d_buf_t(TypeBuffer::Bias);
+    let bias = bias.borrow();
+    let bias = bias.deref();
+
+    let bias_grad = lr_params.get_1d_buf_t(TypeBuffer::BiasGrad);
+    let mut bias_grad = bias_grad.borrow_mut();
+    let bias_grad = bias_grad.deref_mut();
+
+    let batch_len = prev_input.nrows() as f32;
+
+    *ws_grad = self_neu_grad.t().dot(prev_input) / batch_len;
+    *bias_grad = self_neu_grad.mean_axis(Axis(0)).unwrap();
+
+    let penalty = |val: f32| l2_regul * val + l1_regul * sign(val);
+
+    Zip::from(ws_grad)
+        .and(ws)
+        .par_for_each(|val_ws_grad, val_ws| {
+            *val_ws_grad -= penalty(*val_ws);
+        });
+
+    Zip::from(bias_grad)
+        .and(bias)
+        .for_each(|val_bias_grad, val_bias| {
+            *val_bias_grad -= penalty(*val_bias);
+        });
+}
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use log::error;
+
+use ndarray::{Array1, ArrayView1, Zip};
+use ndarray_stats::QuantileExt;
+
+use crate::layers::LayerError;
+use crate::losses::*;
+use crate::util::*;
+
+/// Target distribution of one sample with label smoothing applied
+fn target_distribution(
+    expected: ArrayView1<f32>,
+    size: usize,
+    sparse: bool,
+    label_smoothing: f32,
+) -> Array1<f32> {
+    let mut target = if sparse {
+        let mut one_hot = Array1::zeros(size);
+        one_hot[expected[0] as usize] = 1.0;
+        one_hot
+    } else {
+        expected.to_owned()
+    };
+
+    if label_smoothing != 0.0 {
+        let uniform = label_smoothing / size as f32;
+        target.mapv_inplace(|t| t * (1.0 - label_smoothing) + uniform);
+    }
+
+    target
+}
+
+/// Cross-entropy of softmax over the model output logits.
+/// Expected values are target distributions (one-hot or soft) or class indices in sparse mode
+#[derive(Clone, Default)]
+pub struct SoftmaxCrossEntropyLoss {
+    sparse: bool,
+    label_smoothing: f32,
+    class_weights: Vec<f32>,
+    metrics: Metrics,
+}
+
+impl SoftmaxCrossEntropyLoss {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Expected values hold a single class index per row instead of a one-hot vector
+    pub fn sparse(mut self, sparse: bool) -> Self {
+        self.sparse = sparse;
+        self
+    }
+
+    /// Mixes targets with uniform distribution : t * (1 - factor) + factor / size
+    pub fn label_smoothing(mut self, factor: f32) -> Self {
+        self.label_smoothing = factor;
+        self
+    }
+
+    /// Loss multipliers for each class
+    pub fn class_weights(mut self, class_weights: &[f32]) -> Self {
+        self.class_weights = class_weights.to_vec();
+        self
+    }
+
+    fn check_expected(&self, size: usize, expected: &Array2D) -> Result<(), String> {
+        if !self.class_weights.is_empty() && self.class_weights.len() != size {
+            return Err(format!(
+                "Invalid class weights count for softmax cross-entropy : {}, expected : {}",
+                self.class_weights.len(),
+                size
+            ));
+        }
+
+        if !self.sparse {
+            if expected.ncols() != size {
+                return Err(format!(
+                    "Invalid expected size for softmax cross-entropy : {}, expected : {}",
+                    expected.ncols(),
+                    size
+                ));
+            }
+
+            return Ok(());
+        }
+
+        if expected.ncols() != 1 {
+            return Err(format!(
+                "Sparse softmax cross-entropy expects single class index per row, got : {}",
+                expected.ncols()
+            ));
+        }
+
+        for idx in expected.iter() {
+            if *idx < 0.0 || idx.fract() != 0.0 || *idx as usize >= size {
+                return Err(format!(
+                    "Invalid class index for softmax cross-entropy : {}, classes count : {}",
+                    idx, size
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Same as backward(), but takes softmax probabilities instead of logits.
+    /// Returned gradient is still with respect to the logits
+    pub(crate) fn backward_probs(
+        &mut self,
+        probs: &Array2D,
+        expected: &Array2D,
+        loss_weights: &LossWeights,
+    ) -> Result<Array2D, LayerError> {
+        let size = probs.ncols();
+
+        if let Err(e) = self.check_expected(size, expected) {
+            error!("{}", e);
+            return Err(LayerError::InvalidSize);
+        }
+
+        let class_weights = if self.class_weights.is_empty() {
+            Array1::ones(size)
+        } else {
+            Array1::from_vec(self.class_weights.clone())
+        };
+
+        // sample weights and mask scale terms of the cross-entropy sum like class weights do.
+        // Mask of sparse labels has single value for each sample, it's applied to all classes
+        let factors = match loss_weights.mask.as_ref() {
+            Some(mask) if self.sparse && mask.ncols() == 1 => loss_weights
+                .factors((probs.nrows(), 1))
+                .map(|f| f.broadcast(probs.dim()).unwrap().to_owned()),
+            _ => loss_weights.factors(probs.dim()),
+        };
+
+        let factors = match factors {
+            Ok(factors) => factors,
+            Err(e) => {
+                error!("{}", e);
+                return Err(LayerError::InvalidSize);
+            }
+        };
+
+        let (sparse, label_smoothing) = (self.sparse, self.label_smoothing);
+        let match_cnt = AtomicU32::new(0);
+        let mut losses = Array1::<f32>::zeros(probs.nrows());
+        let mut grad = Array2D::zeros(probs.dim());
+
+        Zip::from(grad.rows_mut())
+            .and(probs.rows())
+            .and(expected.rows())
+            .and(factors.rows())
+            .and(&mut losses)
+            .par_for_each(|grad_b, probs_b, expected_b, factors_b, loss| {
+                // for each batch
+                let target = target_distribution(expected_b, size, sparse, label_smoothing);
+
+                if let (Ok(out_idx), Ok(expected_idx)) = (probs_b.argmax(), target.argmax()) {
+                    if out_idx == expected_idx {
+                        match_cnt.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+
+                // dL/dz_j = p_j * sum(w_k * t_k) - w_j * t_j for the weighted cross-entropy
+                let weighted_target = &target * &class_weights * factors_b;
+                let target_sum = weighted_target.sum();
+
+                *loss = 0.0;
+
+                Zip::from(grad_b)
+                    .and(probs_b)
+                    .and(&weighted_target)
+                    .for_each(|grad_el, prob, w_t| {
+                        *grad_el = w_t - prob * target_sum;
+                        *loss -= w_t * prob.max(f32::MIN_POSITIVE).ln();
+                    });
+            });
+
+        let accuracy = match_cnt.load(Ordering::SeqCst) as f64 / probs.nrows() as f64;
+
+        self.metrics.clear();
+        self.metrics.insert("accuracy".to_owned(), accuracy);
+        self.metrics
+            .insert("loss".to_owned(), losses.mean().unwrap_or(0.0) as f64);
+
+        Ok(grad)
+    }
+}
+
+impl Loss for SoftmaxCrossEntropyLoss {
+    fn loss_type(&self) -> &str {
+        "SoftmaxCrossEntropyLoss"
+    }
+
+    fn backward(
+        &mut self,
+        output: &Array2D,
+        expected: &Array2D,
+        loss_weights: &LossWeights,
+    ) -> Result<Array2D, LayerError> {
+        let mut probs = output.clone();
+        array_helpers::softmax_rows(&mut probs);
+
+        self.backward_probs(&probs, expected, loss_weights)
+    }
+
+    fn metrics(&self) -> Option<&Metrics> {
+        Some(&self.metrics)
+    }
+
+    fn clone_loss(&self) -> Box<dyn Loss> {
+        Box::new(self.clone())
+    }
+}
+
+impl WithParams for SoftmaxCrossEntropyLoss {
+    fn cfg(&self) -> HashMap<String, Variant> {
+        let mut cfg = HashMap::new();
+
+        cfg.insert("sparse".to_owned(), Variant::Bool(self.sparse));
+        cfg.insert(
+            "label_smoothing".to_owned(),
+            Variant::Float(self.label_smoothing),
+        );
+
+        if !self.class_weights.is_empty() {
+            cfg.insert(
+                "class_weights".to_owned(),
+                Variant::FloatArray(self.class_weights.clone()),
+            );
+        }
+
+        cfg
+    }
+
+    fn set_cfg(&mut self, cfg: &HashMap<String, Variant>) {
+        if let Some(Variant::Bool(sparse)) = cfg.get("sparse") {
+            self.sparse = *sparse;
+        }
+
+        if let Some(Variant::Float(label_smoothing)) = cfg.get("label_smoothing") {
+            if (0.0..1.0).contains(label_smoothing) {
+                self.label_smoothing = *label_smoothing;
+            } else {
+                error!(
+                    "Softmax cross-entropy label_smoothing must be in [0, 1), got : {}",
+                    label_smoothing
+                );
+            }
+        }
+
+        if let Some(Variant::FloatArray(class_weights)) = cfg.get("class_weights") {
+            self.class_weights = class_weights.clone();
+        }
+    }
+}
@@ -0,0 +1,831 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use crate::models::pb::{PbBufBlob, PbGraphModel, PbGraphNode};
+use crate::optimizers::{Optimizer, OptimizerRMS};
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::sync::Arc;
+use std::{cell::RefCell, fs};
+
+use log::{debug, error};
+use std::io::ErrorKind;
+
+use prost::Message;
+use serde::{Deserialize, Deserializer, Serialize, *};
+
+use crate::layer_fabric::*;
+use crate::layers::*;
+use crate::losses::*;
+use crate::models::*;
+use crate::util::*;
+
+/// Named layer of the graph model with names of the nodes it takes input from
+pub struct GraphNode {
+    pub name: String,
+    pub inputs: Vec<String>,
+    pub layer: Box<dyn AbstractLayer>,
+}
+
+impl Clone for GraphNode {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            inputs: self.inputs.clone(),
+            layer: self.layer.clone_layer(),
+        }
+    }
+}
+
+/// Output node of the graph model with its loss and loss weight.
+/// Output node without loss must be a loss layer
+#[derive(Clone)]
+pub struct GraphOutput {
+    pub node: String,
+    pub loss: Option<Box<dyn Loss>>,
+    pub weight: f32,
+}
+
+/// Model with layers connected as directed acyclic graph.
+/// Nodes without inputs are input nodes. The first output is the main one,
+/// it is the last added node unless set and its output is returned by output_params().
+/// Layers are run in topological order, gradients of nodes with several consumers are summed
+#[derive(Clone)]
+pub struct Graph {
+    nodes: Vec<GraphNode>, // in topological order after compile()
+    input_ids: Vec<Vec<usize>>,
+    outputs: Vec<GraphOutput>,
+    head_losses: Metrics,
+    batch_size: usize,
+    optim: Box<dyn Optimizer>,
+}
+
+fn graph_err(msg: String) -> Box<dyn Error> {
+    error!("[graph_mdl] {}", msg);
+    Box::new(std::io::Error::new(ErrorKind::InvalidInput, msg))
+}
+
+impl Graph {
+    pub fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            input_ids: Vec::new(),
+            outputs: Vec::new(),
+            head_losses: Metrics::new(),
+            batch_size: 1,
+            optim: Box::new(OptimizerRMS::new(1e-2, 0.9)),
+        }
+    }
+
+    pub fn from_file(filepath: &str) -> Result<Self, Box<dyn Error>> {
+        let cfg_file = File::open(filepath)?;
+        let mut mdl: Graph = serde_yaml::from_reader(cfg_file)?;
+
+        mdl.compile()?;
+        mdl.set_batch_size(mdl.batch_size);
+
+        Ok(mdl)
+    }
+
+    pub fn to_file(&self, filepath: &str) -> Result<(), Box<dyn Error>> {
+        let yaml_str_result = serde_yaml::to_string(&self);
+
+        let mut output = File::create(filepath)?;
+
+        match yaml_str_result {
+            Ok(yaml_str) => {
+                output.write_all(yaml_str.as_bytes())?;
+            }
+            Err(x) => {
+                error!("Error (serde-yaml) serializing graph model !!!");
+                return Err(Box::new(std::io::Error::new(ErrorKind::Other, x)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds node, which takes outputs of the given nodes as input.
+    /// Input node (InputLayer) has no inputs
+    pub fn add_node(&mut self, name: &str, layer: Box<dyn AbstractLayer>, inputs: &[&str]) {
+        self.nodes.push(GraphNode {
+            name: name.to_owned(),
+            inputs: inputs.iter().map(|i| i.to_string()).collect(),
+            layer,
+        });
+    }
+
+    /// Sets the main output node
+    pub fn set_output(&mut self, name: &str) {
+        self.main_output_mut().node = name.to_owned();
+    }
+
+    /// Adds output node with its own loss and loss weight, the first added output is the main one
+    pub fn add_output(&mut self, name: &str, loss: Option<Box<dyn Loss>>, weight: f32) {
+        self.outputs.push(GraphOutput {
+            node: name.to_owned(),
+            loss,
+            weight,
+        });
+    }
+
+    pub fn outputs(&self) -> &[GraphOutput] {
+        &self.outputs
+    }
+
+    fn main_output_mut(&mut self) -> &mut GraphOutput {
+        if self.outputs.is_empty() {
+            self.add_output("", None, 1.0);
+        }
+
+        &mut self.outputs[0]
+    }
+
+    pub fn set_optim(&mut self, optim: Box<dyn Optimizer>) {
+        self.optim = optim;
+    }
+
+    /// Sets loss computed from the main output node, so the output node could be of any type
+    pub fn set_loss(&mut self, loss: Box<dyn Loss>) {
+        self.main_output_mut().loss = Some(loss);
+    }
+
+    pub fn loss(&self) -> Option<&dyn Loss> {
+        self.outputs.first().and_then(|o| o.loss.as_deref())
+    }
+
+    pub fn node(&self, name: &str) -> Option<&GraphNode> {
+        self.nodes.iter().find(|n| n.name == name)
+    }
+
+    pub fn nodes(&self) -> &[GraphNode] {
+        &self.nodes
+    }
+
+    /// Checks edges, sorts nodes in topological order and sets up layers input shapes.
+    /// Must be called after nodes are added
+    pub fn compile(&mut self) -> Result<(), Box<dyn Error>> {
+        let last_node = match self.nodes.last() {
+            Some(n) => n.name.clone(),
+            None => return Err(graph_err("Graph has no nodes".to_owned())),
+        };
+
+        // the last added node is the main output by default
+        let main_output = self.main_output_mut();
+        if main_output.node.is_empty() {
+            main_output.node = last_node;
+        }
+
+        let mut ids = HashMap::new();
+
+        for (idx, n) in self.nodes.iter().enumerate() {
+            if ids.insert(n.name.clone(), idx).is_some() {
+                return Err(graph_err(format!("Duplicate node name : {}", n.name)));
+            }
+        }
+
+        let mut input_ids = Vec::with_capacity(self.nodes.len());
+
+        for n in self.nodes.iter() {
+            let mut n_inputs = Vec::with_capacity(n.inputs.len());
+
+            for i in n.inputs.iter() {
+                match ids.get(i) {
+                    Some(id) => n_inputs.push(*id),
+                    None => {
+                        return Err(graph_err(format!("Unknown input {} of node {}", i, n.name)))
+                    }
+                }
+            }
+
+            input_ids.push(n_inputs);
+        }
+
+        if !input_ids.iter().any(|i| i.is_empty()) {
+            return Err(graph_err("Graph has no input node".to_owned()));
+        }
+
+        // Kahn's algorithm, keeps insertion order of independent nodes
+        let mut deps: Vec<usize> = input_ids.iter().map(|i| i.len()).collect();
+        let mut order = Vec::with_capacity(self.nodes.len());
+        let mut done = vec![false; self.nodes.len()];
+
+        while order.len() < self.nodes.len() {
+            let next = (0..self.nodes.len()).find(|idx| !done[*idx] && deps[*idx] == 0);
+
+            let Some(next) = next else {
+                return Err(graph_err("Graph contains a cycle".to_owned()));
+            };
+
+            done[next] = true;
+            order.push(next);
+
+            for (idx, n_inputs) in input_ids.iter().enumerate() {
+                deps[idx] -= n_inputs.iter().filter(|i| **i == next).count();
+            }
+        }
+
+        // reorder nodes, so indices follow topological order
+        let mut new_pos = vec![0; order.len()];
+        for (pos, idx) in order.iter().enumerate() {
+            new_pos[*idx] = pos;
+        }
+
+        let mut nodes: Vec<Option<GraphNode>> = self.nodes.drain(..).map(Some).collect();
+        self.nodes = order
+            .iter()
+            .map(|idx| nodes[*idx].take().unwrap())
+            .collect();
+        self.input_ids = order
+            .iter()
+            .map(|idx| input_ids[*idx].iter().map(|i| new_pos[*i]).collect())
+            .collect();
+
+        let out_ids = self.output_ids()?;
+
+        for (h, out_id) in out_ids.iter().enumerate() {
+            if out_ids[..h].contains(out_id) {
+                return Err(graph_err(format!(
+                    "Duplicate output node : {}",
+                    self.nodes[*out_id].name
+                )));
+            }
+
+            if self.input_ids.iter().any(|i| i.contains(out_id)) {
+                return Err(graph_err(format!(
+                    "Output node {} can't be an input of other nodes",
+                    self.nodes[*out_id].name
+                )));
+            }
+        }
+
+        for idx in 0..self.nodes.len() {
+            if self.input_ids[idx].is_empty() {
+                continue;
+            }
+
+            let shapes: Vec<Vec<usize>> = self.input_ids[idx]
+                .iter()
+                .map(|i| self.nodes[*i].layer.output_shape())
+                .collect();
+
+            self.nodes[idx].layer.set_input_shapes(&shapes);
+        }
+
+        Ok(())
+    }
+
+    /// Node indices of outputs, the main one goes first
+    fn output_ids(&self) -> Result<Vec<usize>, Box<dyn Error>> {
+        if self.outputs.is_empty() {
+            return Err(graph_err("Graph has no output node".to_owned()));
+        }
+
+        self.outputs
+            .iter()
+            .map(|o| {
+                self.nodes
+                    .iter()
+                    .position(|n| n.name == o.node)
+                    .ok_or_else(|| graph_err(format!("Unknown output node : {}", o.node)))
+            })
+            .collect()
+    }
+
+    fn inputs_params(&self, idx: usize) -> ParamsBlob {
+        self.input_ids[idx]
+            .iter()
+            .map(|i| self.nodes[*i].layer.cpu_params().unwrap())
+            .collect()
+    }
+}
+
+impl Model for Graph {
+    fn feedforward(&mut self, train_data: Array2D) {
+        self.feedforward_named(train_data, &NamedArrays::new());
+    }
+
+    fn feedforward_named(&mut self, input: Array2D, named_inputs: &NamedArrays) {
+        let mut input = Some(input);
+
+        for idx in 0..self.nodes.len() {
+            let result_out = if self.input_ids[idx].is_empty() {
+                let name = &self.nodes[idx].name;
+
+                let data = match (named_inputs.get(name), input.take()) {
+                    (Some(data), unnamed) => {
+                        input = unnamed;
+                        data.clone()
+                    }
+                    (None, Some(data)) => data,
+                    (None, None) => {
+                        error!("[graph_mdl] No input data for node {}", name);
+                        return;
+                    }
+                };
+
+                self.nodes[idx].layer.forward_input(data)
+            } else {
+                let input = self.inputs_params(idx);
+                self.nodes[idx].layer.forward(input)
+            };
+
+            if let Err(reason) = result_out {
+                error!(
+                    "[graph_mdl] Node {} error feedforward : {}",
+                    self.nodes[idx].name, reason
+                );
+                return;
+            }
+        }
+    }
+
+    fn backpropagate_weighted(&mut self, expected: Array2D, loss_weights: &LossWeights) {
+        self.backpropagate_named(expected, &NamedArrays::new(), loss_weights);
+    }
+
+    fn backpropagate_named(
+        &mut self,
+        expected: Array2D,
+        named_expected: &NamedArrays,
+        loss_weights: &LossWeights,
+    ) {
+        let out_ids = match self.output_ids() {
+            Ok(ids) => ids,
+            Err(_) => return,
+        };
+
+        // backward results, layers with several inputs return params for each of them
+        let mut back_out: Vec<Option<ParamsBlob>> = vec![None; self.nodes.len()];
+        let mut expected = Some(expected);
+
+        self.head_losses.clear();
+
+        for (h, out_id) in out_ids.iter().enumerate() {
+            let out_id = *out_id;
+            let name = self.nodes[out_id].name.clone();
+
+            // the mask belongs to the unnamed target of the main output
+            let (head_expected, head_weights) = match named_expected.get(&name) {
+                Some(e) => (
+                    e.clone(),
+                    LossWeights::new(loss_weights.sample_weights.clone(), None),
+                ),
+                None if h == 0 => (expected.take().unwrap(), loss_weights.clone()),
+                None => {
+                    error!("[graph_mdl] No target for output node {}", name);
+                    return;
+                }
+            };
+
+            let head_weights = head_weights.scaled(self.outputs[h].weight, head_expected.nrows());
+
+            let prev_out = self.inputs_params(out_id);
+
+            let result_out = if let Some(loss) = self.outputs[h].loss.as_mut() {
+                let output = self.nodes[out_id].layer.cpu_params().unwrap();
+                let output = output.get_2d_buf_t(TypeBuffer::Output);
+
+                let grad = loss.backward(&output.borrow(), &head_expected, &head_weights);
+
+                // loss gradient is passed to the output node like an input gradient of the next layer
+                grad.and_then(|grad| {
+                    let mut loss_params = CpuParams::empty();
+                    loss_params.insert_buf(
+                        TypeBuffer::InputGrad as i32,
+                        VariantParamArc::Array2(Arc::new(RefCell::new(grad))),
+                    );
+
+                    self.nodes[out_id]
+                        .layer
+                        .backward(prev_out, vec![loss_params])
+                })
+            } else {
+                self.nodes[out_id]
+                    .layer
+                    .backward_output(prev_out, head_expected, &head_weights)
+            };
+
+            match result_out {
+                Err(reason) => {
+                    error!(
+                        "[graph_mdl] Output node {} error backpropagate : {}",
+                        name, reason
+                    );
+                    return;
+                }
+                Ok(val) => {
+                    back_out[out_id] = Some(val);
+                }
+            }
+
+            let metrics = match self.outputs[h].loss.as_ref() {
+                Some(loss) => loss.metrics(),
+                None => self.nodes[out_id].layer.metrics(),
+            };
+
+            if let Some(loss_val) = metrics.and_then(|m| m.get("loss")) {
+                self.head_losses.insert(name, *loss_val);
+            }
+        }
+
+        for idx in (0..self.nodes.len()).rev() {
+            if out_ids.contains(&idx) || self.input_ids[idx].is_empty() {
+                continue;
+            }
+
+            // gradients from consumers, which were reached from the output node
+            let mut next_params = Vec::new();
+
+            for (c_id, c_inputs) in self.input_ids.iter().enumerate() {
+                let Some(c_out) = back_out[c_id].as_ref() else {
+                    continue;
+                };
+
+                for (pos, _) in c_inputs.iter().enumerate().filter(|(_, i)| **i == idx) {
+                    if c_out.len() == c_inputs.len() {
+                        next_params.push(c_out[pos].clone());
+                    } else {
+                        next_params.push(self.nodes[c_id].layer.cpu_params().unwrap());
+                    }
+                }
+            }
+
+            if next_params.is_empty() {
+                continue; // node doesn't affect outputs
+            }
+
+            let next_input = if next_params.len() == 1 {
+                next_params.pop().unwrap()
+            } else {
+                let mut grad = next_layer_grad(&next_params[0]);
+
+                for p in next_params.iter().skip(1) {
+                    grad += &next_layer_grad(p);
+                }
+
+                let mut sum_params = CpuParams::empty();
+                sum_params.insert_buf(
+                    TypeBuffer::InputGrad as i32,
+                    VariantParamArc::Array2(Arc::new(RefCell::new(grad))),
+                );
+                sum_params
+            };
+
+            let prev_out = self.inputs_params(idx);
+
+            let result_out = self.nodes[idx].layer.backward(prev_out, vec![next_input]);
+
+            match result_out {
+                Err(reason) => {
+                    error!(
+                        "[graph_mdl] Node {} error backpropagate : {}",
+                        self.nodes[idx].name, reason
+                    );
+                    return;
+                }
+                Ok(val) => {
+                    back_out[idx] = Some(val);
+                }
+            }
+        }
+    }
+
+    fn optimize(&mut self) {
+        for n in self.nodes.iter_mut() {
+            // layers without weights (input, merge and etc.) and frozen ones
+            if n.layer.trainable_bufs().0.is_empty() || !n.layer.is_trainable() {
+                continue;
+            }
+
+            self.optim
+                .optimize_params(&mut n.layer.cpu_params().unwrap(), n.layer.trainable_bufs());
+        }
+    }
+
+    fn optimizer(&self) -> &Box<dyn WithParams> {
+        // https://github.com/rust-lang/rust/issues/65991
+        unsafe {
+            let out = std::mem::transmute::<&Box<dyn Optimizer>, &Box<dyn WithParams>>(&self.optim);
+            return out;
+        }
+    }
+
+    fn optimizer_mut(&mut self) -> &mut Box<dyn WithParams> {
+        // https://github.com/rust-lang/rust/issues/65991
+        unsafe {
+            let out = std::mem::transmute::<&mut Box<dyn Optimizer>, &mut Box<dyn WithParams>>(
+                &mut self.optim,
+            );
+            return out;
+        }
+    }
+
+    fn model_type(&self) -> &str {
+        "mdl_graph_cpu"
+    }
+
+    /// Nodes in topological order with shapes of all their inputs
+    fn summary(&self, batch_size: usize) -> ModelSummary {
+        let mut summary = ModelSummary {
+            layers: Vec::with_capacity(self.nodes.len()),
+            batch_size,
+        };
+
+        for (idx, n) in self.nodes.iter().enumerate() {
+            let input_shapes = match self.input_ids.get(idx) {
+                Some(ids) if !ids.is_empty() => ids
+                    .iter()
+                    .map(|i| self.nodes[*i].layer.output_shape())
+                    .collect(),
+                _ => vec![n.layer.output_shape()],
+            };
+
+            summary.layers.push(LayerSummary::new(
+                &n.name,
+                n.layer.as_ref(),
+                input_shapes,
+                batch_size,
+            ));
+        }
+
+        summary
+    }
+
+    fn output_params(&self) -> CpuParams {
+        self.last_layer().cpu_params().unwrap()
+    }
+
+    /// Metrics of the main output
+    fn last_layer_metrics(&self) -> Option<&Metrics> {
+        if let Some(loss) = self.loss() {
+            return loss.metrics();
+        }
+
+        self.last_layer().metrics()
+    }
+
+    /// Loss values of each output, keyed by output node name
+    fn head_losses(&self) -> Option<&Metrics> {
+        if self.outputs.len() > 1 {
+            Some(&self.head_losses)
+        } else {
+            None
+        }
+    }
+
+    fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    fn set_batch_size(&mut self, batch_size: usize) {
+        self.batch_size = batch_size;
+
+        for n in self.nodes.iter_mut() {
+            n.layer.set_batch_size(batch_size);
+        }
+    }
+
+    fn set_batch_size_for_tests(&mut self, batch_size: usize) {
+        self.batch_size = batch_size;
+
+        for n in self.nodes.iter_mut() {
+            let mut lr = n.layer.cpu_params().unwrap();
+            lr.prepare_for_tests(batch_size);
+            n.layer.set_cpu_params(lr);
+            n.layer.set_train_mode(false);
+        }
+    }
+
+    /// Layer by index in topological order
+    fn layer(&self, id: usize) -> &Box<dyn AbstractLayer> {
+        &self.nodes[id].layer
+    }
+
+    fn layers_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn layer_name(&self, id: usize) -> String {
+        self.nodes[id].name.clone()
+    }
+
+    /// Layer of the main output node
+    fn last_layer(&self) -> &Box<dyn AbstractLayer> {
+        let out_id = self
+            .outputs
+            .first()
+            .and_then(|o| self.nodes.iter().position(|n| n.name == o.node))
+            .expect("There is no output node in model !!!");
+        &self.nodes[out_id].layer
+    }
+
+    fn save_state(&self, filepath: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let mut pb_model = PbGraphModel::default();
+
+        for n in self.nodes.iter() {
+            pb_model.nodes.push(PbGraphNode {
+                name: n.name.clone(),
+                bufs: Some(model_helper::convert_layer_to_pb(n.layer.as_ref())),
+            });
+        }
+
+        let mut file = File::create(filepath)?;
+
+        file.write_all(pb_model.encode_to_vec().as_slice())?;
+
+        Ok(())
+    }
+
+    /// Buffers are matched by node name
+    fn load_state_with(
+        &mut self,
+        filepath: &str,
+        strict: bool,
+    ) -> Result<LoadStateReport, Box<dyn std::error::Error>> {
+        let buf = fs::read(filepath)?;
+
+        let pb_model = PbGraphModel::decode(buf.as_slice())?;
+
+        let pb_nodes: Vec<PbBufBlob> = pb_model
+            .nodes
+            .into_iter()
+            .map(|n| PbBufBlob {
+                name: n.name,
+                bufs: n.bufs.map(|b| b.bufs).unwrap_or_default(),
+            })
+            .collect();
+
+        model_helper::load_named_layers_from_pb(
+            self.nodes
+                .iter_mut()
+                .map(|n| (n.name.as_str(), &mut n.layer))
+                .collect(),
+            &pb_nodes,
+            strict,
+        )
+    }
+}
+
+impl Serialize for Graph {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut graph_mdl = SerdeGraphModel::default();
+
+        for n in self.nodes.iter() {
+            graph_mdl.nodes.push(SerdeGraphNode {
+                name: n.name.clone(),
+                layer: n.layer.layer_type().to_owned(),
+                inputs: n.inputs.clone(),
+                params: n.layer.cfg(),
+            });
+        }
+
+        for o in self.outputs.iter() {
+            graph_mdl.outputs.push(SerdeGraphOutput {
+                node: o.node.clone(),
+                loss: o.loss.as_ref().map(|loss| SerdeLayerParam {
+                    name: loss.loss_type().to_owned(),
+                    params: loss.cfg(),
+                    layer_name: None,
+                }),
+                weight: o.weight,
+            });
+        }
+
+        graph_mdl.batch_size = self.batch_size();
+        graph_mdl.mdl_type = self.model_type().to_string();
+
+        graph_mdl.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Graph {
+    fn deserialize<D>(deserializer: D) -> Result<Graph, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let serde_mdl = SerdeGraphModel::deserialize(deserializer)?;
+        let mut graph_mdl = Graph::new();
+
+        if serde_mdl.mdl_type != graph_mdl.model_type() {
+            return Err(de::Error::custom(format!(
+                "Invalid model type : {}",
+                serde_mdl.mdl_type
+            )));
+        }
+
+        for n in serde_mdl.nodes.iter() {
+            let l_opt = create_layer(n.layer.as_str(), Some(&n.params));
+
+            if let Some(l) = l_opt {
+                debug!("Create node : {} ({})", n.name, n.layer);
+                let inputs: Vec<&str> = n.inputs.iter().map(|i| i.as_str()).collect();
+                graph_mdl.add_node(&n.name, l, &inputs);
+            } else {
+                return Err(de::Error::custom(format!(
+                    "Can't create layer : {}",
+                    n.layer
+                )));
+            }
+        }
+
+        for o in serde_mdl.outputs.iter() {
+            let loss = match o.loss.as_ref() {
+                Some(loss) => match create_loss(loss.name.as_str(), Some(&loss.params)) {
+                    Some(loss) => Some(loss),
+                    None => {
+                        return Err(de::Error::custom(format!("Unknown loss : {}", loss.name)));
+                    }
+                },
+                None => None,
+            };
+
+            graph_mdl.add_output(&o.node, loss, o.weight);
+        }
+
+        graph_mdl.batch_size = serde_mdl.batch_size;
+
+        Ok(graph_mdl)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::grad_check::*;
+
+    fn node_output(mdl: &Graph, name: &str) -> Array2D {
+        let lp = mdl.node(name).unwrap().layer.cpu_params().unwrap();
+        let out = lp.get_2d_buf_t(TypeBuffer::Output).borrow().clone();
+        out
+    }
+
+    fn euclidean_sum(output: &Array2D, expected: &Array2D) -> f32 {
+        (expected - output).mapv(|v| 0.5 * v * v).sum()
+    }
+
+    #[test]
+    fn skip_connections_and_merge_grads_match_numeric() {
+        let mut mdl = Graph::new();
+        mdl.add_node("in", InputLayer::new_box(3), &[]);
+        mdl.add_node("fc_a", FcLayer::new_box(4, Activation::Tanh), &["in"]);
+        mdl.add_node("fc_b", FcLayer::new_box(4, Activation::Tanh), &["fc_a"]);
+        mdl.add_node("add", AddLayer::new_box(), &["fc_a", "fc_b"]);
+        mdl.add_node("concat", ConcatLayer::new_box(), &["add", "in"]);
+        mdl.add_node("out", FcLayer::new_box(2, Activation::Tanh), &["concat"]);
+        mdl.set_loss(Box::new(EuclideanLoss::new()));
+        mdl.compile().unwrap();
+        mdl.set_batch_size(2);
+
+        let input = test_values(2, 3, 1);
+        let expected = test_values(2, 2, 2);
+
+        mdl.feedforward(input.clone());
+        mdl.backpropagate(expected.clone());
+
+        check_model(&mut mdl, |mdl| {
+            mdl.feedforward(input.clone());
+            euclidean_sum(&node_output(mdl, "out"), &expected)
+        });
+    }
+
+    #[test]
+    fn named_inputs_and_weighted_outputs_grads_match_numeric() {
+        let mut mdl = Graph::new();
+        mdl.add_node("a", InputLayer::new_box(3), &[]);
+        mdl.add_node("b", InputLayer::new_box(2), &[]);
+        mdl.add_node("fc_a", FcLayer::new_box(3, Activation::Tanh), &["a"]);
+        mdl.add_node("fc_b", FcLayer::new_box(3, Activation::Tanh), &["b"]);
+        mdl.add_node("add", AddLayer::new_box(), &["fc_a", "fc_b"]);
+        mdl.add_node("head_1", FcLayer::new_box(2, Activation::Raw), &["add"]);
+        mdl.add_node("head_2", FcLayer::new_box(1, Activation::Tanh), &["fc_b"]);
+        mdl.add_output("head_1", Some(Box::new(EuclideanLoss::new())), 1.0);
+        mdl.add_output("head_2", Some(Box::new(EuclideanLoss::new())), 0.5);
+        mdl.compile().unwrap();
+        mdl.set_batch_size(2);
+
+        let mut inputs = NamedArrays::new();
+        inputs.insert("a".to_owned(), test_values(2, 3, 1));
+        inputs.insert("b".to_owned(), test_values(2, 2, 2));
+
+        let expected_1 = test_values(2, 2, 3);
+        let mut named_expected = NamedArrays::new();
+        named_expected.insert("head_2".to_owned(), test_values(2, 1, 4));
+
+        let no_input = Array2D::zeros((0, 0));
+
+        mdl.feedforward_named(no_input.clone(), &inputs);
+        mdl.backpropagate_named(expected_1.clone(), &named_expected, &LossWeights::default());
+
+        check_model(&mut mdl, |mdl| {
+            mdl.feedforward_named(no_input.clone(), &inputs);
+
+            euclidean_sum(&node_output(mdl, "head_1"), &expected_1)
+                + 0.5 * euclidean_sum(&node_output(mdl, "head_2"), &named_expected["head_2"])
+        });
+    }
+}
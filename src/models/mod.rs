@@ -1,20 +1,59 @@
+mod graph;
 mod sequential;
 mod model_helper;
 #[cfg(feature = "opencl")]
 mod sequential_ocl;
+mod summary;
 
-use std::{error::Error, rc::Rc, cell::RefCell};
+use std::{collections::HashMap, error::Error, rc::Rc, cell::RefCell};
 use crate::{util::*, layers::AbstractLayer, cpu_params::*, layers_storage::SerdeLayersStorage};
 use crate::layers_storage::*;
 
+pub use graph::*;
 pub use sequential::*;
 #[cfg(feature = "opencl")]
 pub use sequential_ocl::*;
+pub use summary::*;
+pub use model_helper::LoadStateReport;
 use serde::{Serialize, Deserialize};
+use log::warn;
 
 pub trait Model {
     fn feedforward(&mut self, train_data: Array2D);
-    fn backpropagate(&mut self, expected: Array2D);
+    fn backpropagate(&mut self, expected: Array2D) {
+        self.backpropagate_weighted(expected, &LossWeights::default());
+    }
+    /// Backpropagation with per-sample weights and output mask applied by the loss layer
+    fn backpropagate_weighted(&mut self, expected: Array2D, loss_weights: &LossWeights);
+
+    /// Feedforward of models with several input nodes, named inputs are taken by node name
+    /// and the unnamed one goes to the remaining input node
+    fn feedforward_named(&mut self, input: Array2D, named_inputs: &NamedArrays) {
+        if !named_inputs.is_empty() {
+            warn!("[{}] Model has a single input, named inputs are ignored", self.model_type());
+        }
+        self.feedforward(input);
+    }
+
+    /// Backpropagation of models with several outputs, named targets are taken by output node name
+    /// and the unnamed one goes to the main output.
+    /// Sample weights are applied to all outputs, mask only to the main one
+    fn backpropagate_named(
+        &mut self,
+        expected: Array2D,
+        named_expected: &NamedArrays,
+        loss_weights: &LossWeights,
+    ) {
+        if !named_expected.is_empty() {
+            warn!("[{}] Model has a single output, named targets are ignored", self.model_type());
+        }
+        self.backpropagate_weighted(expected, loss_weights);
+    }
+
+    /// Mean loss of each output of the last backpropagation, for models with several outputs
+    fn head_losses(&self) -> Option<&Metrics> {
+        None
+    }
     fn optimize(&mut self);
     fn batch_size(&self) -> usize;
     fn set_batch_size(&mut self, batch_size: usize);
@@ -23,6 +62,11 @@ pub trait Model {
     // TODO : maybe make return value Option<...>
     fn layer(&self, id: usize) -> &Box<dyn AbstractLayer>;
     fn layers_count(&self) -> usize;
+
+    /// Name of the layer used by summary, layer index by default
+    fn layer_name(&self, id: usize) -> String {
+        id.to_string()
+    }
     fn last_layer(&self) -> &Box<dyn AbstractLayer >;
     fn last_layer_metrics(&self) -> Option<&Metrics> {
         None  // accuracy and another possible types of metrics
@@ -35,13 +79,51 @@ pub trait Model {
 
     fn model_type(&self) -> &str;
 
+    /// Per-layer shapes, parameters counts, forward FLOPs and memory for the given batch size.
+    /// Layers are named by layer_name(), input layer takes its own output shape
+    fn summary(&self, batch_size: usize) -> ModelSummary {
+        let mut summary = ModelSummary {
+            layers: Vec::with_capacity(self.layers_count()),
+            batch_size,
+        };
+
+        for idx in 0..self.layers_count() {
+            let l = self.layer(idx);
+            let input_shape = match idx {
+                0 => l.output_shape(),
+                _ => self.layer(idx - 1).output_shape(),
+            };
+
+            summary.layers.push(LayerSummary::new(
+                &self.layer_name(idx),
+                l.as_ref(),
+                vec![input_shape],
+                batch_size,
+            ));
+        }
+
+        summary
+    }
+
     fn save_state(&self, filepath: &str) -> Result<(), Box<dyn Error>>;
-    fn load_state(&mut self, filepath: &str) -> Result<(), Box<dyn Error>>;
+
+    /// Loads state, layers are matched by name.
+    /// Fails if any layer with buffers is missing in state or has buffers of another shape
+    fn load_state(&mut self, filepath: &str) -> Result<(), Box<dyn Error>> {
+        self.load_state_with(filepath, true).map(|_| ())
+    }
+
+    /// Loads state, layers are matched by name. Non-strict loading skips missing
+    /// and mismatched layers, they keep their initialization, e.g. a new head on top of pretrained body
+    fn load_state_with(&mut self, filepath: &str, strict: bool) -> Result<LoadStateReport, Box<dyn Error>>;
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct SerdeSequentialModel {
     pub ls: Vec<SerdeLayerParam>,
+    /// Loss applied after the last layer, if the last layer isn't a loss layer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loss: Option<SerdeLayerParam>,
     pub mdl_type: String,
     pub batch_size: usize,
 }
@@ -50,6 +132,52 @@ impl Default for SerdeSequentialModel {
     fn default() -> Self {
         SerdeSequentialModel {
             ls: Vec::new(),
+            loss: None,
+            mdl_type: "none".to_string(),
+            batch_size: 1,
+        }
+    }
+}
+
+/// Graph model node, layer is created from its type name and params
+#[derive(Serialize, Deserialize)]
+pub struct SerdeGraphNode {
+    pub name: String,
+    pub layer: String,
+    #[serde(default)]
+    pub inputs: Vec<String>,
+    #[serde(default)]
+    pub params: HashMap<String, Variant>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SerdeGraphOutput {
+    pub node: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loss: Option<SerdeLayerParam>,
+    #[serde(default = "default_loss_weight")]
+    pub weight: f32,
+}
+
+fn default_loss_weight() -> f32 {
+    1.0
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SerdeGraphModel {
+    pub nodes: Vec<SerdeGraphNode>,
+    /// Output nodes, the first one is main. The last node if not set
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<SerdeGraphOutput>,
+    pub mdl_type: String,
+    pub batch_size: usize,
+}
+
+impl Default for SerdeGraphModel {
+    fn default() -> Self {
+        SerdeGraphModel {
+            nodes: Vec::new(),
+            outputs: Vec::new(),
             mdl_type: "none".to_string(),
             batch_size: 1,
         }
@@ -2,11 +2,41 @@ use std::collections::HashMap;
 
 use ndarray::Array2;
 use std::{str::FromStr, sync::Arc, cell::RefCell};
+use std::error::Error;
+use std::io::ErrorKind;
+
+use log::{error, warn};
 
 use crate::cpu_params::{VariantParamArc, TypeBuffer};
+use crate::layers::AbstractLayer;
 use crate::models::pb::{PbBuf, PbBufBlob};
 use crate::util::*;
 
+/// Shapes of the layer serializable buffers by buffer id
+pub type BufShapes = Vec<(i32, Vec<usize>)>;
+
+/// Index of the matched state layer for each model layer
+pub type StateMatch = (Vec<Option<usize>>, LoadStateReport);
+
+/// Result of state loading, layers are matched by name
+#[derive(Clone, Debug, Default)]
+pub struct LoadStateReport {
+    pub loaded: Vec<String>,
+    /// Model layers missing in state, they keep their initialization
+    pub missing: Vec<String>,
+    /// Layers with buffers of another shape in state, they keep their initialization
+    pub mismatched: Vec<String>,
+    /// State layers which aren't present in model
+    pub unexpected: Vec<String>,
+}
+
+impl LoadStateReport {
+    /// All model layers with buffers were loaded
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty() && self.mismatched.is_empty()
+    }
+}
+
 pub fn convert_buf_2d_to_pb(buf: &Array2D, id: i32) -> PbBuf {
     let mut pb_ws_blob = PbBuf::default();
 
@@ -45,6 +75,166 @@ pub fn convert_pb_to_param_buf(pb_buf: &PbBuf) -> VariantParamArc {
     }
 }
 
+/// Serializable buffers of the layer
+pub fn convert_layer_to_pb(l: &dyn AbstractLayer) -> PbBufBlob {
+    let mut pb_buf_blob = PbBufBlob::default();
+
+    for i in l.serializable_bufs().iter() {
+        let buf = l.cpu_params().unwrap().get_param(*i);
+
+        match buf {
+            VariantParamArc::Array2(arr2) => {
+                pb_buf_blob.bufs.push(convert_buf_2d_to_pb(&arr2.borrow(), *i));
+            }
+            VariantParamArc::Array1(arr1) => {
+                pb_buf_blob.bufs.push(convert_buf_1d_to_pb(&arr1.borrow(), *i));
+            }
+        }
+    }
+
+    pb_buf_blob
+}
+
+/// Shapes of the cpu layer serializable buffers
+pub fn layer_buf_shapes(l: &dyn AbstractLayer) -> BufShapes {
+    let lp = match l.cpu_params() {
+        Some(lp) => lp,
+        None => return Vec::new(),
+    };
+
+    l.serializable_bufs()
+        .iter()
+        .filter(|id| lp.contains_buf(**id))
+        .map(|id| {
+            let shape = match lp.get_param(*id) {
+                VariantParamArc::Array1(arr1) => arr1.borrow().shape().to_vec(),
+                VariantParamArc::Array2(arr2) => arr2.borrow().shape().to_vec(),
+            };
+            (*id, shape)
+        })
+        .collect()
+}
+
+fn pb_buf_fits(pb_buf: &PbBuf, shape: &[usize]) -> bool {
+    let pb_shape: Vec<usize> = pb_buf.shape.iter().map(|s| *s as usize).collect();
+
+    pb_shape == shape && pb_buf.vals.len() == shape.iter().product::<usize>()
+}
+
+/// Matches state layers to the model layers by name, returns index of the state layer for each model layer.
+/// Layers of the state saved without names are matched by position.
+/// Strict matching fails if any model layer with buffers is missing in state or has buffers of another shape
+pub fn match_state_layers(
+    layers: &[(&str, BufShapes)],
+    pb_layers: &[PbBufBlob],
+    strict: bool,
+) -> Result<StateMatch, Box<dyn Error>> {
+    let unnamed = pb_layers.iter().all(|l| l.name.is_empty());
+
+    if unnamed && !pb_layers.is_empty() {
+        warn!("[state] State has no layer names, layers are matched by position");
+    }
+
+    let pb_names: Vec<String> = pb_layers
+        .iter()
+        .enumerate()
+        .map(|(idx, l)| match unnamed {
+            true => layers
+                .get(idx)
+                .map(|l| l.0.to_owned())
+                .unwrap_or_else(|| format!("#{}", idx)),
+            false => l.name.clone(),
+        })
+        .collect();
+
+    let mut report = LoadStateReport::default();
+    let mut matched = Vec::with_capacity(layers.len());
+
+    for (name, bufs) in layers.iter() {
+        if bufs.is_empty() {
+            matched.push(None);
+            continue;
+        }
+
+        match pb_names.iter().position(|n| n == name) {
+            Some(pb_idx) => {
+                let pb_bufs = &pb_layers[pb_idx].bufs;
+                let fits = pb_bufs.len() == bufs.len()
+                    && bufs.iter().all(|(id, shape)| {
+                        pb_bufs
+                            .iter()
+                            .any(|b| b.buf_id == *id && pb_buf_fits(b, shape))
+                    });
+
+                if fits {
+                    report.loaded.push(name.to_string());
+                    matched.push(Some(pb_idx));
+                } else {
+                    warn!("[state] Layer {} buffers don't fit the state", name);
+                    report.mismatched.push(name.to_string());
+                    matched.push(None);
+                }
+            }
+            None => {
+                warn!("[state] Layer {} is missing in state", name);
+                report.missing.push(name.to_string());
+                matched.push(None);
+            }
+        }
+    }
+
+    for (pb_name, pb_l) in pb_names.iter().zip(pb_layers.iter()) {
+        if !pb_l.bufs.is_empty() && !layers.iter().any(|l| l.0 == pb_name) {
+            warn!("[state] State layer {} isn't present in model", pb_name);
+            report.unexpected.push(pb_name.clone());
+        }
+    }
+
+    if strict && !report.is_complete() {
+        let msg = format!(
+            "State doesn't fit the model, missing layers : {:?}, mismatched layers : {:?}",
+            report.missing, report.mismatched
+        );
+        error!("[state] {}", msg);
+        return Err(Box::new(std::io::Error::new(ErrorKind::InvalidData, msg)));
+    }
+
+    Ok((matched, report))
+}
+
+/// Loads named cpu layers from the state layers matched by name, see match_state_layers()
+pub fn load_named_layers_from_pb(
+    layers: Vec<(&str, &mut Box<dyn AbstractLayer>)>,
+    pb_layers: &[PbBufBlob],
+    strict: bool,
+) -> Result<LoadStateReport, Box<dyn Error>> {
+    let shapes: Vec<(&str, BufShapes)> = layers
+        .iter()
+        .map(|(name, l)| (*name, layer_buf_shapes(l.as_ref())))
+        .collect();
+
+    let (matched, report) = match_state_layers(&shapes, pb_layers, strict)?;
+
+    for ((_, l), pb_idx) in layers.into_iter().zip(matched) {
+        if let Some(pb_idx) = pb_idx {
+            load_layer_from_pb(l, &pb_layers[pb_idx]);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Replaces layer buffers with deserialized ones
+pub fn load_layer_from_pb(l: &mut Box<dyn AbstractLayer>, pb_buf_blob: &PbBufBlob) {
+    let mut layer_param = l.cpu_params().unwrap();
+
+    for b_i in pb_buf_blob.bufs.iter() {
+        layer_param.insert_buf(b_i.buf_id, convert_pb_to_param_buf(b_i))
+    }
+
+    l.set_cpu_params(layer_param);
+}
+
 // pub fn convert_hash_ws_blob_to_pb(h: &HashMap<Uuid, WsBlob>) -> HashMap<String, PbWsBlob> {
 //     let mut out = HashMap::new();
 
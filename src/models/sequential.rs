@@ -1,5 +1,4 @@
 use std::error::Error;
-use std::ops::Deref;
 
 use crate::layers_storage::SequentialLayersStorage;
 use crate::models::Model;
@@ -20,14 +19,14 @@ use serde::{Deserialize, Deserializer, Serialize, *};
 
 use crate::layer_fabric::*;
 use crate::layers::*;
+use crate::losses::*;
 use crate::models::*;
 use crate::util::*;
 
-use super::pb::PbBufBlob;
-
 #[derive(Clone)]
 pub struct Sequential {
     ls: SequentialLayersStorage,
+    loss: Option<Box<dyn Loss>>,
     batch_size: usize,
     optim: Box<dyn Optimizer>,
 }
@@ -36,6 +35,7 @@ impl Sequential {
     pub fn new() -> Self {
         Self {
             ls: SequentialLayersStorage::empty(),
+            loss: None,
             batch_size: 1,
             optim: Box::new(OptimizerRMS::new(1e-2, 0.9)),
         }
@@ -44,6 +44,7 @@ impl Sequential {
     pub fn new_simple(net_cfg: &Vec<usize>) -> Self {
         let mut seq = Self {
             ls: SequentialLayersStorage::new_simple_network(net_cfg),
+            loss: None,
             batch_size: 1,
             optim: Box::new(OptimizerRMS::new(1e-2, 0.9)),
         };
@@ -55,6 +56,7 @@ impl Sequential {
     pub fn new_with_layers(ls: SequentialLayersStorage) -> Self {
         Self {
             ls,
+            loss: None,
             batch_size: 1,
             optim: Box::new(OptimizerRMS::new(1e-2, 0.9)),
         }
@@ -90,16 +92,16 @@ impl Sequential {
 
     pub fn compile_shapes(&mut self) {
         // TODO : may return some result in further
-        let mut prev_size = 0;
+        let mut prev_shape = Vec::new();
 
         for (idx, l) in self.ls.iter_mut().enumerate() {
             if idx == 0 {
-                prev_size = l.size();
+                prev_shape = l.output_shape();
                 continue;
             }
 
-            l.set_input_shape(&[prev_size]);
-            prev_size = l.size();
+            l.set_input_shape(&prev_shape);
+            prev_shape = l.output_shape();
         }
     }
 
@@ -107,9 +109,25 @@ impl Sequential {
         self.optim = optim;
     }
 
+    /// Adds layer with default name, layer type with the index among the layers of the same type
     pub fn add_layer(&mut self, l: Box<dyn AbstractLayer>) {
         self.ls.add_layer(l);
     }
+
+    /// Adds layer with the given name, which is used to match layers on state loading
+    pub fn add_named_layer(&mut self, name: &str, l: Box<dyn AbstractLayer>) {
+        self.ls.add_named_layer(name, l);
+    }
+
+    /// Sets loss computed from the last layer output, so the last layer could be of any type.
+    /// Without loss the last layer must be a loss layer
+    pub fn set_loss(&mut self, loss: Box<dyn Loss>) {
+        self.loss = Some(loss);
+    }
+
+    pub fn loss(&self) -> Option<&dyn Loss> {
+        self.loss.as_deref()
+    }
 }
 
 impl Model for Sequential {
@@ -146,16 +164,36 @@ impl Model for Sequential {
         }
     }
 
-    fn backpropagate(&mut self, expected: Array2D) {
+    fn backpropagate_weighted(&mut self, expected: Array2D, loss_weights: &LossWeights) {
         let expected_data = expected;
 
         // for the last layer
         {
             let prev_out = self.ls.at_mut(self.ls.len() - 2).cpu_params();
-            let result_out = self
-                .ls
-                .at_mut(self.ls.len() - 1)
-                .backward_output(vec![prev_out.unwrap()], expected_data);
+
+            let result_out = if let Some(loss) = self.loss.as_mut() {
+                let output = self.ls.last().unwrap().cpu_params().unwrap();
+                let output = output.get_2d_buf_t(TypeBuffer::Output);
+
+                let grad = loss.backward(&output.borrow(), &expected_data, loss_weights);
+
+                // loss gradient is passed to the last layer like an input gradient of the next layer
+                grad.and_then(|grad| {
+                    let mut loss_params = CpuParams::empty();
+                    loss_params.insert_buf(
+                        TypeBuffer::InputGrad as i32,
+                        VariantParamArc::Array2(Arc::new(RefCell::new(grad))),
+                    );
+
+                    self.ls
+                        .at_mut(self.ls.len() - 1)
+                        .backward(vec![prev_out.unwrap()], vec![loss_params])
+                })
+            } else {
+                self.ls
+                    .at_mut(self.ls.len() - 1)
+                    .backward_output(vec![prev_out.unwrap()], expected_data, loss_weights)
+            };
 
             match result_out {
                 Err(reason) => {
@@ -193,6 +231,11 @@ impl Model for Sequential {
 
     fn optimize(&mut self) {
         for l in self.ls.iter_mut() {
+            // layers without weights (input, pooling and etc.) and frozen ones
+            if l.trainable_bufs().0.is_empty() || !l.is_trainable() {
+                continue;
+            }
+
             self.optim
                 .optimize_params(&mut l.cpu_params().unwrap(), l.trainable_bufs());
         }
@@ -231,6 +274,10 @@ impl Model for Sequential {
     }
 
     fn last_layer_metrics(&self) -> Option<&Metrics> {
+        if let Some(loss) = self.loss.as_ref() {
+            return loss.metrics();
+        }
+
         self.last_layer().metrics()
     }
 
@@ -256,6 +303,10 @@ impl Model for Sequential {
         self.ls.len()
     }
 
+    fn layer_name(&self, id: usize) -> String {
+        self.ls.name(id).to_owned()
+    }
+
     fn last_layer(&self) -> &Box<dyn AbstractLayer> {
         self.ls.last().unwrap()
     }
@@ -264,36 +315,10 @@ impl Model for Sequential {
         // create vector of layers learn_params
         let mut vec_lr = Vec::with_capacity(self.ls.len());
 
-        for l in self.ls.iter() {
-            let mut pb_buf_blob = PbBufBlob::default(); // layer's buffers
-
-            let bufs_ids = l.serializable_bufs();
-
-            for i in bufs_ids.iter() {
-                let buf = l.cpu_params().unwrap().get_param(*i);
-
-                match buf {
-                    VariantParamArc::Array2(arr2) => {
-                        let arr2_bor = arr2.borrow();
-                        let arr2_bor = arr2_bor.deref();
-
-                        let pb_blob = model_helper::convert_buf_2d_to_pb(arr2_bor, *i);
-                        pb_buf_blob.bufs.push(pb_blob);
-                    }
-                    VariantParamArc::Array1(arr1) => {
-                        let arr1_bor = arr1.borrow();
-                        let arr1_bor = arr1_bor.deref();
-
-                        let pb_blob = model_helper::convert_buf_1d_to_pb(arr1_bor, *i);
-                        pb_buf_blob.bufs.push(pb_blob);
-                    }
-                    _ => {
-                        todo!()
-                    }
-                }
-            }
-
-            vec_lr.push(pb_buf_blob);
+        for (l, name) in self.ls.iter().zip(self.ls.names()) {
+            let mut pb_l = model_helper::convert_layer_to_pb(l.as_ref());
+            pb_l.name = name.clone();
+            vec_lr.push(pb_l);
         }
 
         let pb_model = PbSequentialModel { layers: vec_lr };
@@ -306,26 +331,20 @@ impl Model for Sequential {
         Ok(())
     }
 
-    fn load_state(&mut self, filepath: &str) -> Result<(), Box<dyn std::error::Error>> {
+    fn load_state_with(
+        &mut self,
+        filepath: &str,
+        strict: bool,
+    ) -> Result<LoadStateReport, Box<dyn std::error::Error>> {
         let buf = fs::read(filepath)?;
 
-        let mut pb_model = PbSequentialModel::decode(buf.as_slice())?;
-
-        for (self_l, l_pb) in self.ls.iter_mut().zip(&mut pb_model.layers) {
-            if self_l.layer_type() == "InputLayer" {
-                continue;
-            }
-
-            let mut layer_param = self_l.cpu_params().unwrap();
+        let pb_model = PbSequentialModel::decode(buf.as_slice())?;
 
-            for b_i in l_pb.bufs.iter() {
-                layer_param.insert_buf(b_i.buf_id, model_helper::convert_pb_to_param_buf(&b_i))
-            }
-
-            self_l.set_cpu_params(layer_param);
-        }
-
-        Ok(())
+        model_helper::load_named_layers_from_pb(
+            self.ls.iter_named_mut().collect(),
+            &pb_model.layers,
+            strict,
+        )
     }
 }
 
@@ -336,14 +355,23 @@ impl Serialize for Sequential {
     {
         let mut seq_mdl = SerdeSequentialModel::default();
 
-        for l in self.ls.iter() {
+        for (l, l_name) in self.ls.iter().zip(self.ls.names()) {
             let s_layer_param = SerdeLayerParam {
                 name: l.layer_type().to_owned(),
                 params: l.cfg(),
+                layer_name: Some(l_name.clone()),
             };
             seq_mdl.ls.push(s_layer_param);
         }
 
+        if let Some(loss) = self.loss.as_ref() {
+            seq_mdl.loss = Some(SerdeLayerParam {
+                name: loss.loss_type().to_owned(),
+                params: loss.cfg(),
+                layer_name: None,
+            });
+        }
+
         seq_mdl.batch_size = self.batch_size();
         seq_mdl.mdl_type = self.model_type().to_string();
 
@@ -368,10 +396,20 @@ impl<'de> Deserialize<'de> for Sequential {
 
             if let Some(l) = l_opt {
                 debug!("Create layer : {}", i.name);
-                seq_mdl.add_layer(l);
+                match i.layer_name.as_ref() {
+                    Some(l_name) => seq_mdl.add_named_layer(l_name, l),
+                    None => seq_mdl.add_layer(l),
+                }
+            } else {
+                return Err(de::Error::custom(format!("Can't create layer : {}", i.name)));
+            }
+        }
+
+        if let Some(loss) = serde_mdl.loss.as_ref() {
+            if let Some(loss) = create_loss(loss.name.as_str(), Some(&loss.params)) {
+                seq_mdl.set_loss(loss);
             } else {
-                // TODO : impl return D::Error
-                panic!("Bad deserialization");
+                return Err(de::Error::custom(format!("Unknown loss : {}", loss.name)));
             }
         }
 
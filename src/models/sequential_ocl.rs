@@ -16,7 +16,7 @@ use crate::optimizers::*;
 use crate::util::*;
 
 use prost::Message;
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
 use log::{debug, error, info};
 
@@ -24,10 +24,12 @@ use ocl::flags::{CommandQueueProperties, MapFlags, MemFlags};
 use ocl::{Buffer, Context, Device, Event, Kernel, Platform, Program, Queue, Result as OclResult};
 
 use crate::layer_fabric::*;
+use crate::ocl::OclParams;
 use crate::layers_storage::*;
 
 pub struct SequentialOcl {
     layers: Vec<Box<dyn AbstractLayerOcl>>,
+    names: Vec<String>, // unique, used to match layers on state loading
     batch_size: usize,
     ocl_ctx: Context,
     ocl_queue: Queue,
@@ -51,6 +53,7 @@ impl SequentialOcl {
 
         Ok(Self {
             layers: Vec::new(),
+            names: Vec::new(),
             batch_size: 1,
             ocl_ctx: context,
             ocl_queue: kern_queue.clone(),
@@ -105,6 +108,20 @@ impl SequentialOcl {
     }
 
     pub fn add_layer(&mut self, l: Box<dyn AbstractLayerOcl>) {
+        let name = default_layer_name(l.layer_type(), &self.names);
+        self.add_named_layer(&name, l);
+    }
+
+    /// Adds layer with the given name, which is used to match layers on state loading
+    pub fn add_named_layer(&mut self, name: &str, l: Box<dyn AbstractLayerOcl>) {
+        if self.names.iter().any(|n| n == name) {
+            let default_name = default_layer_name(l.layer_type(), &self.names);
+            error!("Duplicated layer name {}, renamed to {}", name, default_name);
+            self.names.push(default_name);
+        } else {
+            self.names.push(name.to_owned());
+        }
+
         self.layers.push(l);
     }
 
@@ -242,7 +259,7 @@ impl Model for SequentialOcl {
         }
     }
 
-    fn backpropagate(&mut self, expected: Array2D) {
+    fn backpropagate_weighted(&mut self, expected: Array2D, loss_weights: &LossWeights) {
         let layers_len = self.layers.len();
 
         // for the last layer
@@ -252,7 +269,7 @@ impl Model for SequentialOcl {
             let last_layer_idx = layers_len - 1;
 
             let result_out =
-                self.layers[last_layer_idx].backward_output_ocl(vec![prev_out.unwrap()], expected);
+                self.layers[last_layer_idx].backward_output_ocl(vec![prev_out.unwrap()], expected, loss_weights);
 
             match result_out {
                 Err(_reason) => {
@@ -289,6 +306,11 @@ impl Model for SequentialOcl {
 
     fn optimize(&mut self) {
         for l in self.layers.iter_mut() {
+            // frozen layers
+            if !l.is_trainable() {
+                continue;
+            }
+
             self.optim
                 .optimize_ocl_params(l.ocl_params().unwrap(), l.trainable_bufs());
         }
@@ -317,6 +339,55 @@ impl Model for SequentialOcl {
         "mdl_sequential_ocl"
     }
 
+    /// Parameters are counted from OpenCL buffers, layers are considered as fully-connected
+    fn summary(&self, batch_size: usize) -> ModelSummary {
+        let mut summary = ModelSummary {
+            layers: Vec::with_capacity(self.layers.len()),
+            batch_size,
+        };
+
+        let bufs_len = |ocl_params: &OclParams, ids: &[i32]| -> usize {
+            ids.iter()
+                .filter_map(|id| ocl_params.params.get(id))
+                .map(|buf| buf.0.borrow().len())
+                .sum()
+        };
+
+        for (idx, l) in self.layers.iter().enumerate() {
+            let input_shape = match idx {
+                0 => l.output_shape(),
+                _ => self.layers[idx - 1].output_shape(),
+            };
+
+            let (trainable_ids, _) = l.trainable_bufs();
+            let non_trainable_ids: Vec<i32> = l
+                .serializable_bufs()
+                .iter()
+                .filter(|id| !trainable_ids.contains(id))
+                .cloned()
+                .collect();
+
+            let params = match l.ocl_params() {
+                Some(ocl_params) => (
+                    bufs_len(&ocl_params, trainable_ids),
+                    bufs_len(&ocl_params, &non_trainable_ids),
+                ),
+                None => (0, 0),
+            };
+
+            summary.layers.push(LayerSummary::with_params(
+                &self.names[idx],
+                l.as_ref(),
+                vec![input_shape],
+                batch_size,
+                params,
+                2 * params.0 + l.size(),
+            ));
+        }
+
+        summary
+    }
+
     fn output_params(&self) -> CpuParams {
         let out_layer = self.layers.last().expect("Couldn't get output layer");
 
@@ -380,6 +451,11 @@ impl Model for SequentialOcl {
     fn layers_count(&self) -> usize {
         self.layers.len()
     }
+
+    fn layer_name(&self, id: usize) -> String {
+        self.names[id].clone()
+    }
+
     fn last_layer(&self) -> &Box<dyn AbstractLayer> {
         // https://github.com/rust-lang/rust/issues/65991
         unsafe {
@@ -393,11 +469,13 @@ impl Model for SequentialOcl {
     fn save_state(&self, filepath: &str) -> Result<(), Box<dyn Error>> {
         let mut vec_ws = Vec::with_capacity(self.layers.len());
 
-        for l in self.layers.iter() {
+        for (l, name) in self.layers.iter().zip(self.names.iter()) {
             let ocl_params = l.ocl_params().unwrap();
             let ser_ids = l.serializable_bufs();
 
-            vec_ws.push(ocl_params.serialize_to_pb(ser_ids));
+            let mut pb_l = ocl_params.serialize_to_pb(ser_ids);
+            pb_l.name = name.clone();
+            vec_ws.push(pb_l);
         }
 
         let pb_model = PbSequentialModel { layers: vec_ws };
@@ -407,23 +485,46 @@ impl Model for SequentialOcl {
 
         Ok(())
     }
-    fn load_state(&mut self, filepath: &str) -> Result<(), Box<dyn Error>> {
+    fn load_state_with(
+        &mut self,
+        filepath: &str,
+        strict: bool,
+    ) -> Result<LoadStateReport, Box<dyn Error>> {
         let buf = fs::read(filepath)?;
 
-        let mut pb_model = PbSequentialModel::decode(buf.as_slice())?;
+        let pb_model = PbSequentialModel::decode(buf.as_slice())?;
         let q = self.queue();
 
-        for (self_l, dec_l) in self.layers.iter_mut().zip(&mut pb_model.layers) {
-            if self_l.layer_type() == "InputLayerOcl" {
-                continue;
+        let shapes: Vec<(&str, model_helper::BufShapes)> = self
+            .layers
+            .iter()
+            .zip(self.names.iter())
+            .map(|(l, name)| {
+                let shapes = match l.ocl_params() {
+                    Some(ocl_params) => l
+                        .serializable_bufs()
+                        .iter()
+                        .filter_map(|id| ocl_params.params.get(id).map(|buf| (*id, &buf.1)))
+                        .map(|(id, shape)| (id, shape.iter().map(|s| *s as usize).collect()))
+                        .collect(),
+                    None => Vec::new(),
+                };
+                (name.as_str(), shapes)
+            })
+            .collect();
+
+        let (matched, report) = model_helper::match_state_layers(&shapes, &pb_model.layers, strict)?;
+
+        for (self_l, pb_idx) in self.layers.iter_mut().zip(matched) {
+            if let Some(pb_idx) = pb_idx {
+                let mut ocl_prms = self_l.ocl_params().unwrap();
+
+                ocl_prms.set_vals_from_pb(&pb_model.layers[pb_idx], q.clone());
+                self_l.set_ocl_params(ocl_prms);
             }
-            let mut ocl_prms = self_l.ocl_params().unwrap();
-
-            ocl_prms.set_vals_from_pb(dec_l, q.clone());
-            self_l.set_ocl_params(ocl_prms);
         }
 
-        Ok(())
+        Ok(report)
     }
 }
 
@@ -431,8 +532,8 @@ impl Clone for SequentialOcl {
     fn clone(&self) -> Self {
         let mut seq_mdl = SequentialOcl::new().unwrap();
 
-        for i in self.layers.iter() {
-            seq_mdl.layers.push(i.clone_layer_ocl());
+        for (i, name) in self.layers.iter().zip(self.names.iter()) {
+            seq_mdl.add_named_layer(name, i.clone_layer_ocl());
         }
 
         seq_mdl.init_layers_but_weights();
@@ -453,10 +554,11 @@ impl Serialize for SequentialOcl {
     {
         let mut seq_mdl = SerdeSequentialModel::default();
 
-        for l in self.layers.iter() {
+        for (l, l_name) in self.layers.iter().zip(self.names.iter()) {
             let s_layer_param = SerdeLayerParam {
                 name: l.layer_type().to_owned(),
                 params: l.cfg(),
+                layer_name: Some(l_name.clone()),
             };
             seq_mdl.ls.push(s_layer_param);
         }
@@ -475,6 +577,10 @@ impl<'de> Deserialize<'de> for SequentialOcl {
     {
         let serde_mdl = SerdeSequentialModel::deserialize(deserializer)?;
 
+        if serde_mdl.loss.is_some() {
+            return Err(de::Error::custom("loss isn't supported by mdl_sequential_ocl"));
+        }
+
         let mut seq_mdl = SequentialOcl::new().expect("Failed to create SequentialOcl model");
 
         if serde_mdl.mdl_type != seq_mdl.model_type() {
@@ -486,10 +592,12 @@ impl<'de> Deserialize<'de> for SequentialOcl {
 
             if let Some(l) = l_opt {
                 debug!("Create layer : {}", i.name);
-                seq_mdl.layers.push(l);
+                match i.layer_name.as_ref() {
+                    Some(l_name) => seq_mdl.add_named_layer(l_name, l),
+                    None => seq_mdl.add_layer(l),
+                }
             } else {
-                // TODO : impl return D::Error
-                panic!("Bad deserialization");
+                return Err(de::Error::custom(format!("Can't create layer : {}", i.name)));
             }
         }
 
@@ -0,0 +1,210 @@
+use std::fmt;
+use std::mem::size_of;
+
+use crate::layers::*;
+use crate::util::*;
+
+/// Summary row of a single layer, FLOPs and memory are estimated for the summary batch size
+#[derive(Clone, Debug)]
+pub struct LayerSummary {
+    pub name: String,
+    pub layer_type: String,
+    pub input_shapes: Vec<Vec<usize>>,
+    pub output_shape: Vec<usize>,
+    pub trainable_params: usize,
+    pub non_trainable_params: usize,
+    pub flops: usize,
+    /// Bytes of parameters and outputs
+    pub memory: usize,
+}
+
+impl LayerSummary {
+    /// Collects summary of the cpu layer, parameters are counted from its buffers
+    pub fn new(
+        name: &str,
+        l: &dyn AbstractLayer,
+        input_shapes: Vec<Vec<usize>>,
+        batch_size: usize,
+    ) -> Self {
+        let (trainable, non_trainable) = match l.cpu_params() {
+            Some(lp) => {
+                let trainable_ids = l.trainable_bufs().0;
+                let non_trainable_ids: Vec<i32> = l
+                    .serializable_bufs()
+                    .iter()
+                    .filter(|id| !trainable_ids.contains(id))
+                    .cloned()
+                    .collect();
+
+                (
+                    params_count(&lp, trainable_ids),
+                    params_count(&lp, &non_trainable_ids),
+                )
+            }
+            None => (0, 0),
+        };
+
+        Self::with_params(
+            name,
+            l,
+            input_shapes,
+            batch_size,
+            (trainable, non_trainable),
+            l.forward_flops(),
+        )
+    }
+
+    /// Summary with given parameters counts and FLOPs per sample,
+    /// used for layers which keep parameters outside of cpu buffers.
+    /// All parameters of the frozen layer are non-trainable
+    pub fn with_params<L: AbstractLayer + ?Sized>(
+        name: &str,
+        l: &L,
+        input_shapes: Vec<Vec<usize>>,
+        batch_size: usize,
+        params: (usize, usize),
+        sample_flops: usize,
+    ) -> Self {
+        let (trainable_params, non_trainable_params) = match l.is_trainable() {
+            true => params,
+            false => (0, params.0 + params.1),
+        };
+
+        Self {
+            name: name.to_owned(),
+            layer_type: l.layer_type().to_owned(),
+            input_shapes,
+            output_shape: l.output_shape(),
+            trainable_params,
+            non_trainable_params,
+            flops: sample_flops * batch_size,
+            memory: (trainable_params + non_trainable_params + l.size() * batch_size)
+                * size_of::<Float>(),
+        }
+    }
+}
+
+/// Per-layer table of the model, returned by Model::summary()
+#[derive(Clone, Debug, Default)]
+pub struct ModelSummary {
+    pub layers: Vec<LayerSummary>,
+    pub batch_size: usize,
+}
+
+impl ModelSummary {
+    pub fn trainable_params(&self) -> usize {
+        self.layers.iter().map(|l| l.trainable_params).sum()
+    }
+
+    pub fn non_trainable_params(&self) -> usize {
+        self.layers.iter().map(|l| l.non_trainable_params).sum()
+    }
+
+    pub fn flops(&self) -> usize {
+        self.layers.iter().map(|l| l.flops).sum()
+    }
+
+    pub fn memory(&self) -> usize {
+        self.layers.iter().map(|l| l.memory).sum()
+    }
+}
+
+fn shape_str(sh: &[usize]) -> String {
+    let dims: Vec<String> = sh.iter().map(|d| d.to_string()).collect();
+    format!("[{}]", dims.join(", "))
+}
+
+fn human_size(val: usize, units: &[&str]) -> String {
+    let mut val = val as f64;
+    let mut unit = 0;
+
+    while val >= 1000.0 && unit + 1 < units.len() {
+        val /= 1000.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{}{}", val, units[0])
+    } else {
+        format!("{:.2}{}", val, units[unit])
+    }
+}
+
+fn flops_str(flops: usize) -> String {
+    human_size(flops, &["", "K", "M", "G", "T"])
+}
+
+fn memory_str(bytes: usize) -> String {
+    human_size(bytes, &["B", "KB", "MB", "GB", "TB"])
+}
+
+impl fmt::Display for ModelSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let header = [
+            "Layer",
+            "Input shape",
+            "Output shape",
+            "Trainable",
+            "Non-trainable",
+            "FLOPs",
+            "Memory",
+        ];
+
+        let rows: Vec<[String; 7]> = self
+            .layers
+            .iter()
+            .map(|l| {
+                let input_shapes: Vec<String> =
+                    l.input_shapes.iter().map(|sh| shape_str(sh)).collect();
+
+                [
+                    format!("{} ({})", l.name, l.layer_type),
+                    input_shapes.join(", "),
+                    shape_str(&l.output_shape),
+                    l.trainable_params.to_string(),
+                    l.non_trainable_params.to_string(),
+                    flops_str(l.flops),
+                    memory_str(l.memory),
+                ]
+            })
+            .collect();
+
+        let mut widths: Vec<usize> = header.iter().map(|h| h.len()).collect();
+        for r in rows.iter() {
+            for (w, val) in widths.iter_mut().zip(r.iter()) {
+                *w = (*w).max(val.len());
+            }
+        }
+
+        let line = "-".repeat(widths.iter().sum::<usize>() + 2 * (widths.len() - 1));
+
+        let write_row = |f: &mut fmt::Formatter, row: &[&str]| -> fmt::Result {
+            let cells: Vec<String> = row
+                .iter()
+                .zip(widths.iter())
+                .map(|(val, w)| format!("{:<w$}", val, w = *w))
+                .collect();
+            writeln!(f, "{}", cells.join("  ").trim_end())
+        };
+
+        writeln!(f, "{}", line)?;
+        write_row(f, &header)?;
+        writeln!(f, "{}", line)?;
+
+        for r in rows.iter() {
+            let r: Vec<&str> = r.iter().map(|v| v.as_str()).collect();
+            write_row(f, &r)?;
+        }
+
+        writeln!(f, "{}", line)?;
+        writeln!(f, "Trainable params : {}", self.trainable_params())?;
+        writeln!(f, "Non-trainable params : {}", self.non_trainable_params())?;
+        writeln!(
+            f,
+            "Forward FLOPs (batch size {}) : {}",
+            self.batch_size,
+            flops_str(self.flops())
+        )?;
+        write!(f, "Memory : {}", memory_str(self.memory()))
+    }
+}
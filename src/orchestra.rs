@@ -57,6 +57,7 @@ where
     test_iter: usize,
     cur_iter_err: f32,
     cur_iter_acc: f64,
+    cur_iter_head_losses: Metrics, // loss of each output for multi-output models
     learn_rate_decay: f32,
     decay_step: usize,
     show_accuracy: bool,
@@ -88,6 +89,7 @@ where
             test_iter: 100,
             cur_iter_acc: 0.0,
             cur_iter_err: 0.0,
+            cur_iter_head_losses: Metrics::new(),
             learn_rate_decay: 1.0,
             decay_step: 0,
             show_accuracy: true,
@@ -111,6 +113,7 @@ where
             test_iter: 100,
             cur_iter_err: 0.0,
             cur_iter_acc: 0.0,
+            cur_iter_head_losses: Metrics::new(),
             learn_rate_decay: 1.0,
             decay_step: 0,
             show_accuracy: true,
@@ -230,7 +233,7 @@ where
         self.test_model
             .as_mut()
             .unwrap()
-            .feedforward(test_batch.input);
+            .feedforward_named(test_batch.input, &test_batch.named_inputs);
 
         let lr = self.test_model.as_ref().unwrap().output_params();
         let out = lr.get_2d_buf_t(TypeBuffer::Output);
@@ -238,17 +241,30 @@ where
 
         let mut accuracy_cnt = 0.0;
 
+        // expected values could hold single class index per row (sparse labels)
+        let sparse = test_batch.output.ncols() == 1 && out.ncols() > 1;
+
         Zip::from(out.rows())
             .and(test_batch.output.rows())
             .for_each(|out_r, exp_r| {
                 let mut local_err = 0.0;
 
-                if out_r.argmax() == exp_r.argmax() {
+                if sparse {
+                    if out_r.argmax() == Ok(exp_r[0] as usize) {
+                        accuracy_cnt += 1.0;
+                    }
+                } else if out_r.argmax() == exp_r.argmax() {
                     accuracy_cnt += 1.0;
                 }
 
                 for i in 0..out_r.shape()[0] {
-                    local_err += (exp_r[i] - out_r[i]).powf(2.0);
+                    let expected = if sparse {
+                        (i == exp_r[0] as usize) as i32 as f32
+                    } else {
+                        exp_r[i]
+                    };
+
+                    local_err += (expected - out_r[i]).powf(2.0);
                 }
 
                 err += (local_err / out_r.shape()[0] as f32).sqrt();
@@ -324,10 +340,10 @@ where
     }
 
     fn calc_accuracy(metrics: Option<&Metrics>) -> f64 {
-        if let Some(metrics) = metrics {
-            return metrics["accuracy"];
+        if let Some(acc) = metrics.and_then(|m| m.get("accuracy")) {
+            return *acc;
         } else {
-            return 0.0; // none ?
+            return 0.0; // none ? (regression losses have no accuracy)
         }
     }
 
@@ -359,8 +375,10 @@ where
 
     fn perform_step(&mut self, mb: MiniBatch) {
         if let Some(train_model) = self.train_model.as_mut() {
-            train_model.feedforward(mb.input);
-            train_model.backpropagate(mb.output);
+            let loss_weights = mb.loss_weights();
+
+            train_model.feedforward_named(mb.input, &mb.named_inputs);
+            train_model.backpropagate_named(mb.output, &mb.named_outputs, &loss_weights);
 
             train_model.optimize();
 
@@ -370,6 +388,16 @@ where
             self.cur_iter_err = Self::calc_avg_err(&lr);
             self.cur_iter_acc = Self::calc_accuracy(train_model.last_layer_metrics());
 
+            match train_model.head_losses() {
+                Some(head_losses) => {
+                    for (name, val) in head_losses.iter() {
+                        debug!("Loss of {} output : {}", name, val);
+                    }
+                    self.cur_iter_head_losses = head_losses.clone();
+                }
+                None => self.cur_iter_head_losses.clear(),
+            }
+
             self.test_err_accum += self.cur_iter_err as f64;
         }
     }
@@ -449,6 +477,7 @@ where
         let mut ten_perc_num = 0;
 
         let mut accuracy_sum = 0.0;
+        let mut head_losses_sum: HashMap<String, f64> = HashMap::new();
 
         let (tx_thr, rx_cur) = channel::bounded(2);
         let (tx_cur, rx_thr) = channel::bounded(2);
@@ -489,6 +518,10 @@ where
 
                 accuracy_sum += self.cur_iter_acc;
 
+                for (name, val) in self.cur_iter_head_losses.iter() {
+                    *head_losses_sum.entry(name.clone()).or_insert(0.0) += *val;
+                }
+
                 if train_batch_size * 10 < ds_len // for small datasets do not display percentages
                     && (ds_pos >= (ten_perc_num + 1) as usize * ten_perc_metric as usize
                         || prev_pos > ds_pos)
@@ -511,6 +544,19 @@ where
                         );
                     }
 
+                    let mut head_names: Vec<&String> = head_losses_sum.keys().collect();
+                    head_names.sort();
+
+                    for name in head_names {
+                        info!(
+                            "Loss of {} output : {:.5}",
+                            name,
+                            head_losses_sum[name]
+                                / (ten_perc_metric * (ten_perc_num + 1) as f64
+                                    / train_batch_size as f64)
+                        );
+                    }
+
                     ten_perc_num += 1;
 
                     if ten_perc_num > 9 {
@@ -534,6 +580,7 @@ where
                         ten_perc_num = 0;
                         prev_pos = 0;
                         accuracy_sum = 0.0;
+                        head_losses_sum.clear();
 
                         bench_time = Instant::now();
                     } else {
@@ -1,3 +1,8 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::util::{Array2D, Variant};
+
 pub fn sigmoid(val: f32) -> f32 {
     return 1.0 / (1.0 + (-val).exp());
 }
@@ -9,7 +14,7 @@ pub fn sigmoid_deriv(val: f32) -> f32 {
 pub fn tanh(val: f32) -> f32 {
     return val.tanh();
 }
- 
+
 pub fn tanh_deriv(val: f32) -> f32 {
     return 1.0 - tanh(val).powf(2.0);
 }
@@ -54,6 +59,67 @@ pub fn leaky_relu_deriv(val: f32) -> f32 {
     }
 }
 
+const GELU_COEF: f32 = 0.044715;
+const SQRT_2_DIV_PI: f32 = 0.797_884_6;
+
+/// Tanh approximation of GELU
+pub fn gelu(val: f32) -> f32 {
+    0.5 * val * (1.0 + (SQRT_2_DIV_PI * (val + GELU_COEF * val.powi(3))).tanh())
+}
+
+pub fn gelu_deriv(val: f32) -> f32 {
+    let t = (SQRT_2_DIV_PI * (val + GELU_COEF * val.powi(3))).tanh();
+    0.5 * (1.0 + t)
+        + 0.5 * val * (1.0 - t * t) * SQRT_2_DIV_PI * (1.0 + 3.0 * GELU_COEF * val * val)
+}
+
+/// Also known as swish
+pub fn silu(val: f32) -> f32 {
+    val * sigmoid(val)
+}
+
+pub fn silu_deriv(val: f32) -> f32 {
+    let s = sigmoid(val);
+    s * (1.0 + val * (1.0 - s))
+}
+
+pub fn elu(val: f32, alpha: f32) -> f32 {
+    if val > 0.0 {
+        val
+    } else {
+        alpha * val.exp_m1()
+    }
+}
+
+pub fn elu_deriv(val: f32, alpha: f32) -> f32 {
+    if val > 0.0 {
+        1.0
+    } else {
+        alpha * val.exp()
+    }
+}
+
+/// Numerically stable ln(1 + e^x)
+pub fn softplus(val: f32) -> f32 {
+    val.max(0.0) + (-val.abs()).exp().ln_1p()
+}
+
+pub fn softplus_deriv(val: f32) -> f32 {
+    sigmoid(val)
+}
+
+pub fn hard_sigmoid(val: f32) -> f32 {
+    (0.2 * val + 0.5).clamp(0.0, 1.0)
+}
+
+pub fn hard_sigmoid_deriv(val: f32) -> f32 {
+    if val > -2.5 && val < 2.5 {
+        0.2
+    } else {
+        0.0
+    }
+}
+
 pub fn sign(val: f32) -> f32 {
     if val < 0.0 {
         return -1.0;
@@ -63,23 +129,157 @@ pub fn sign(val: f32) -> f32 {
     0.0
 }
 
-#[derive(Clone)]
-pub struct Activation<T: Fn(f32) -> f32 + Clone, TD: Fn(f32) -> f32 + Clone> {
-    pub func: T,
-    pub func_deriv: TD,
-    pub name: String,
+/// Activation functions registry, shared by CPU and OpenCL layers.
+/// Derivatives are taken with respect to the activation input
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Activation {
+    #[default]
+    Raw,
+    Sigmoid,
+    Tanh,
+    ReLU,
+    LeakyReLU(f32), // negative slope
+    ELU(f32),       // alpha
+    GELU,
+    SiLU,
+    Softplus,
+    HardSigmoid,
+}
+
+impl Activation {
+    pub const DEFAULT_LEAKY_SLOPE: f32 = 0.01;
+    pub const DEFAULT_ELU_ALPHA: f32 = 1.0;
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Activation::Raw => "raw",
+            Activation::Sigmoid => "sigmoid",
+            Activation::Tanh => "tanh",
+            Activation::ReLU => "relu",
+            Activation::LeakyReLU(_) => "leaky_relu",
+            Activation::ELU(_) => "elu",
+            Activation::GELU => "gelu",
+            Activation::SiLU => "silu",
+            Activation::Softplus => "softplus",
+            Activation::HardSigmoid => "hard_sigmoid",
+        }
+    }
+
+    pub fn func(&self, val: f32) -> f32 {
+        match self {
+            Activation::Raw => raw(val),
+            Activation::Sigmoid => sigmoid(val),
+            Activation::Tanh => tanh(val),
+            Activation::ReLU => relu(val),
+            Activation::LeakyReLU(slope) => {
+                if val > 0.0 {
+                    val
+                } else {
+                    slope * val
+                }
+            }
+            Activation::ELU(alpha) => elu(val, *alpha),
+            Activation::GELU => gelu(val),
+            Activation::SiLU => silu(val),
+            Activation::Softplus => softplus(val),
+            Activation::HardSigmoid => hard_sigmoid(val),
+        }
+    }
+
+    pub fn func_deriv(&self, val: f32) -> f32 {
+        match self {
+            Activation::Raw => raw_deriv(val),
+            Activation::Sigmoid => sigmoid_deriv(val),
+            Activation::Tanh => tanh_deriv(val),
+            Activation::ReLU => relu_deriv(val),
+            Activation::LeakyReLU(slope) => {
+                if val > 0.0 {
+                    1.0
+                } else {
+                    *slope
+                }
+            }
+            Activation::ELU(alpha) => elu_deriv(val, *alpha),
+            Activation::GELU => gelu_deriv(val),
+            Activation::SiLU => silu_deriv(val),
+            Activation::Softplus => softplus_deriv(val),
+            Activation::HardSigmoid => hard_sigmoid_deriv(val),
+        }
+    }
+
+    /// Applies activation in place, keeping its input in pre_activation for the backward pass
+    pub fn apply(&self, out: &mut Array2D, pre_activation: &mut Array2D) {
+        pre_activation.clone_from(out);
+        out.par_mapv_inplace(|v| self.func(v));
+    }
+
+    /// Reads "activation" with its parameters ("leaky_slope", "elu_alpha") from layer config.
+    /// Missing activation means raw, unknown one is an error
+    pub fn from_cfg(cfg: &HashMap<String, Variant>) -> Result<Self, String> {
+        let mut act = match cfg.get("activation") {
+            Some(Variant::String(name)) => Activation::try_from(name.as_str())?,
+            Some(_) => return Err("Activation must be a string".to_owned()),
+            None => Activation::Raw,
+        };
+
+        match &mut act {
+            Activation::LeakyReLU(slope) => {
+                if let Some(Variant::Float(val)) = cfg.get("leaky_slope") {
+                    *slope = *val;
+                }
+            }
+            Activation::ELU(alpha) => {
+                if let Some(Variant::Float(val)) = cfg.get("elu_alpha") {
+                    *alpha = *val;
+                }
+            }
+            _ => {}
+        }
+
+        Ok(act)
+    }
+
+    /// Writes activation name and its parameters to layer config
+    pub fn write_cfg(&self, cfg: &mut HashMap<String, Variant>) {
+        cfg.insert(
+            "activation".to_owned(),
+            Variant::String(self.name().to_owned()),
+        );
+
+        match self {
+            Activation::LeakyReLU(slope) => {
+                cfg.insert("leaky_slope".to_owned(), Variant::Float(*slope));
+            }
+            Activation::ELU(alpha) => {
+                cfg.insert("elu_alpha".to_owned(), Variant::Float(*alpha));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for Activation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
 }
 
-impl<T, TD> Activation<T, TD>
-where
-    T: Fn(f32) -> f32 + Clone,
-    TD: Fn(f32) -> f32 + Clone,
-{
-    pub fn new(name: &str, func: T, func_deriv: TD) -> Self {
-        Self {
-            func,
-            func_deriv,
-            name: name.to_owned(),
+impl TryFrom<&str> for Activation {
+    type Error = String;
+
+    fn try_from(input: &str) -> Result<Self, Self::Error> {
+        match input {
+            "raw" => Ok(Activation::Raw),
+            "sigmoid" => Ok(Activation::Sigmoid),
+            "tanh" => Ok(Activation::Tanh),
+            "relu" => Ok(Activation::ReLU),
+            "leaky_relu" => Ok(Activation::LeakyReLU(Activation::DEFAULT_LEAKY_SLOPE)),
+            "elu" => Ok(Activation::ELU(Activation::DEFAULT_ELU_ALPHA)),
+            "gelu" => Ok(Activation::GELU),
+            "silu" | "swish" => Ok(Activation::SiLU),
+            "softplus" => Ok(Activation::Softplus),
+            "hard_sigmoid" => Ok(Activation::HardSigmoid),
+            _ => Err(format!("Unknown activation function : {}", input)),
         }
     }
 }
@@ -88,52 +288,43 @@ pub mod activation_macros {
     #[macro_export]
     macro_rules! sigmoid_activation {
         (  ) => {{
-            Activation::new("sigmoid", sigmoid, sigmoid_deriv)
+            Activation::Sigmoid
         }};
     }
 
     #[macro_export]
     macro_rules! tanh_activation {
         (  ) => {{
-            Activation::new("tanh", tanh, tanh_deriv)
+            Activation::Tanh
         }};
     }
 
     #[macro_export]
     macro_rules! raw_activation {
         () => {
-            Activation::new("raw", raw, raw_deriv)
+            Activation::Raw
         };
     }
 
     #[macro_export]
     macro_rules! relu_activation {
         () => {
-            Activation::new("relu", relu, relu_deriv)
+            Activation::ReLU
         };
     }
 
     #[macro_export]
     macro_rules! leaky_relu_activation {
         () => {
-            Activation::new("leaky_relu", leaky_relu, leaky_relu_deriv)
+            Activation::LeakyReLU(Activation::DEFAULT_LEAKY_SLOPE)
         };
     }
 
     #[macro_export]
     macro_rules! activation_by_name {
-        ("sigmoid") => {
-            Activation::new("sigmoid", sigmoid, sigmoid_deriv)
-        };
-        ("tanh") => {
-            Activation::new("tanh", tanh, tanh_deriv)
+        ($name:expr) => {
+            Activation::try_from($name)
         };
-        ("relu") => {
-            Activation::new("relu", relu, relu_deriv)
-        };
-        ("raw") => {
-            Activation::new("raw", raw, raw_deriv)
-        }
     }
 
     pub use raw_activation;
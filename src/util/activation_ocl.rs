@@ -1,114 +1,58 @@
-use std::fmt::{self, Debug};
-
-#[derive(Clone, Debug, PartialEq)]
-pub enum OclActivationFunc {
-    Raw,
-    Sigmoid,
-    Tanh,
-    ReLU,
-    LeakyReLU,
-}
-
-impl fmt::Display for OclActivationFunc {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let act_str = match self {
-            OclActivationFunc::Raw => "raw",
-            OclActivationFunc::Sigmoid => "sigmoid",
-            OclActivationFunc::Tanh => "tanh",
-            OclActivationFunc::ReLU => "relu",
-            OclActivationFunc::LeakyReLU => "leaky_relu",
+use crate::util::Activation;
+
+/// OpenCL layers share activation registry with CPU ones
+pub type OclActivationFunc = Activation;
+
+impl Activation {
+    /// OpenCL source of "float activation(float v)"
+    pub fn ocl_source(&self) -> String {
+        let body = match self {
+            Activation::Raw => "return v;".to_owned(),
+            Activation::Sigmoid => "return 1.0 / (1.0 + exp(-v));".to_owned(),
+            Activation::Tanh => "return tanh(v);".to_owned(),
+            Activation::ReLU => "return v > 0.0 ? v : 0.0;".to_owned(),
+            Activation::LeakyReLU(slope) => format!("return v > 0.0 ? v : {:?}f * v;", slope),
+            Activation::ELU(alpha) => {
+                format!("return v > 0.0 ? v : {:?}f * (exp(v) - 1.0);", alpha)
+            }
+            Activation::GELU => {
+                "return 0.5 * v * (1.0 + tanh(0.7978846 * (v + 0.044715 * v * v * v)));".to_owned()
+            }
+            Activation::SiLU => "return v / (1.0 + exp(-v));".to_owned(),
+            Activation::Softplus => "return max(v, 0.0f) + log1p(exp(-fabs(v)));".to_owned(),
+            Activation::HardSigmoid => "return clamp(0.2f * v + 0.5f, 0.0f, 1.0f);".to_owned(),
         };
 
-        write!(f, "{}", act_str)
-    }
-}
-
-impl TryFrom<&str> for OclActivationFunc {
-    type Error = &'static str;
+        format!(
+            "\n    float activation(float v)\n    {{\n        {}\n    }}\n",
+            body
+        )
+    }
+
+    /// OpenCL source of "float deriv(float v)", v is the activation input
+    pub fn ocl_deriv_source(&self) -> String {
+        let body = match self {
+            Activation::Raw => "return 1.0;".to_owned(),
+            Activation::Sigmoid => {
+                "float s = 1.0 / (1.0 + exp(-v));\n        return s * (1.0 - s);".to_owned()
+            }
+            Activation::Tanh => "float t = tanh(v);\n        return 1.0 - t * t;".to_owned(),
+            Activation::ReLU => "return v > 0.0 ? 1.0 : 0.0;".to_owned(),
+            Activation::LeakyReLU(slope) => format!("return v > 0.0 ? 1.0 : {:?}f;", slope),
+            Activation::ELU(alpha) => format!("return v > 0.0 ? 1.0 : {:?}f * exp(v);", alpha),
+            Activation::GELU => "float t = tanh(0.7978846 * (v + 0.044715 * v * v * v));\n        \
+                 return 0.5 * (1.0 + t) + 0.5 * v * (1.0 - t * t) * 0.7978846 * (1.0 + 3.0 * 0.044715 * v * v);"
+                .to_owned(),
+            Activation::SiLU => {
+                "float s = 1.0 / (1.0 + exp(-v));\n        return s * (1.0 + v * (1.0 - s));".to_owned()
+            }
+            Activation::Softplus => "return 1.0 / (1.0 + exp(-v));".to_owned(),
+            Activation::HardSigmoid => "return v > -2.5 && v < 2.5 ? 0.2 : 0.0;".to_owned(),
+        };
 
-    fn try_from(input: &str) -> Result<Self, Self::Error> {
-        match input {
-            "sigmoid" => Ok(OclActivationFunc::Sigmoid),
-            "tanh" => Ok(OclActivationFunc::Tanh),
-            "relu" => Ok(OclActivationFunc::ReLU),
-            "leaky_relu" => Ok(OclActivationFunc::LeakyReLU),
-            "raw" => Ok(OclActivationFunc::Raw),
-            _ => Err("Invalid input string"),
-        }
+        format!(
+            "\n    float deriv(float v)\n    {{\n        {}\n    }}\n",
+            body
+        )
     }
 }
-
-pub static OCL_ACTIVATION_SIGMOID: &'static str = r#"
-    float activation(float v)
-    {
-        return 1.0 / (1.0 + pow(M_E_F, -v));
-    }
-"#;
-
-pub static OCL_ACTIVATION_SIGMOID_DERIV: &'static str = r#"
-    float sigmoid(float v)
-    {
-        return 1.0 / (1.0 + pow(M_E_F, -v));
-    }
-
-    float deriv(float v) 
-    {
-        return (1.0 - sigmoid(v)) * sigmoid(v);
-    }
-"#;
-
-pub static OCL_ACTIVATION_TANH: &'static str = r#"
-    float activation(float v)
-    {
-        return tanh( v );
-    }
-"#;
-
-pub static OCL_ACTIVATION_TANH_DERIV: &'static str = r#"
-    float deriv(float v)
-    {
-        return 1.0 - pow(tanh(v), 2);
-    }
-"#;
-
-pub static OCL_ACTIVATION_RELU: &'static str = r#"
-    float activation(float v)
-    {
-        return v > 0.0 ? v : 0.0;
-    }
-"#;
-
-pub static OCL_ACTIVATION_RELU_DERIV: &'static str = r#"
-    float deriv(float v)
-    {
-        return v > 0.0 ? 1.0 : 0.0;
-    }
-"#;
-
-pub static OCL_ACTIVATION_LEAKY_RELU: &'static str = r#"
-    float activation(float v)
-    {
-        return v > 0.0 ? v : 0.01 * v;
-    }
-"#;
-
-pub static OCL_ACTIVATION_LEAKY_RELU_DERIV: &'static str = r#"
-    float deriv(float v)
-    {
-        return v > 0.0 ? 1.0 : 0.01;
-    }
-"#;
-
-pub static OCL_ACTIVATION_RAW: &'static str = r#"
-    float activation(float v)
-    {
-        return v;
-    }
-"#;
-
-pub static OCL_ACTIVATION_RAW_DERIV: &'static str = r#"
-    float deriv(float v)
-    {
-        return 1.0;
-    }
-"#;
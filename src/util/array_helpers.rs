@@ -1,5 +1,7 @@
 use ndarray::Array;
 
+use crate::util::Array2D;
+
 pub fn max<D>(arr: &Array<f32, D>) -> f32
 where D: ndarray::Dimension
 {
@@ -26,4 +28,14 @@ where D: ndarray::Dimension
     }
 
     out
-}
\ No newline at end of file
+}
+
+/// Numerically stable softmax of each row, values masked by -inf get zero probability
+pub fn softmax_rows(m: &mut Array2D) {
+    for mut row in m.rows_mut() {
+        let max = row.fold(f32::NEG_INFINITY, |acc, v| acc.max(*v));
+        row.mapv_inplace(|v| (v - max).exp());
+        let sum = row.sum();
+        row /= sum;
+    }
+}
@@ -0,0 +1,175 @@
+use ndarray::{ArrayView1, ArrayViewMut1};
+
+use crate::util::Array2D;
+
+/// Geometry of a convolution window sliding over the (channels, height, width) input.
+/// Input rows are stored flattened in channels-height-width order
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ConvGeometry {
+    pub channels: usize,
+    pub height: usize,
+    pub width: usize,
+    pub kernel_h: usize,
+    pub kernel_w: usize,
+    pub stride_h: usize,
+    pub stride_w: usize,
+    pub pad_h: usize,
+    pub pad_w: usize,
+    pub dilation_h: usize,
+    pub dilation_w: usize,
+    pub extra_pad_w: usize, // additional padding at the right end, used for odd "same" padding
+}
+
+impl ConvGeometry {
+    /// Square kernel with the same stride and padding along both axes
+    pub fn new_square(
+        channels: usize,
+        height: usize,
+        width: usize,
+        kernel: usize,
+        stride: usize,
+        padding: usize,
+    ) -> Self {
+        Self {
+            channels,
+            height,
+            width,
+            kernel_h: kernel,
+            kernel_w: kernel,
+            stride_h: stride,
+            stride_w: stride,
+            pad_h: padding,
+            pad_w: padding,
+            dilation_h: 1,
+            dilation_w: 1,
+            extra_pad_w: 0,
+        }
+    }
+
+    /// Convolution over (channels, length) input, represented as a single row image
+    pub fn new_1d(
+        channels: usize,
+        length: usize,
+        kernel: usize,
+        stride: usize,
+        dilation: usize,
+        pad_begin: usize,
+        pad_end: usize,
+    ) -> Self {
+        Self {
+            channels,
+            height: 1,
+            width: length,
+            kernel_h: 1,
+            kernel_w: kernel,
+            stride_h: 1,
+            stride_w: stride,
+            pad_h: 0,
+            pad_w: pad_begin,
+            dilation_h: 1,
+            dilation_w: dilation,
+            extra_pad_w: pad_end.saturating_sub(pad_begin),
+        }
+    }
+
+    /// Returns false if kernel doesn't fit into padded input
+    pub fn is_valid(&self) -> bool {
+        self.stride_h > 0
+            && self.stride_w > 0
+            && self.kernel_h > 0
+            && self.kernel_w > 0
+            && self.height + 2 * self.pad_h > self.dilation_h * (self.kernel_h - 1)
+            && self.dilation_h > 0
+            && self.dilation_w > 0
+            && self.width + 2 * self.pad_w + self.extra_pad_w
+                > self.dilation_w * (self.kernel_w - 1)
+    }
+
+    pub fn out_height(&self) -> usize {
+        (self.height + 2 * self.pad_h - self.dilation_h * (self.kernel_h - 1) - 1) / self.stride_h
+            + 1
+    }
+
+    pub fn out_width(&self) -> usize {
+        (self.width + 2 * self.pad_w + self.extra_pad_w - self.dilation_w * (self.kernel_w - 1) - 1)
+            / self.stride_w
+            + 1
+    }
+
+    pub fn input_len(&self) -> usize {
+        self.channels * self.height * self.width
+    }
+
+    /// Number of rows of im2col matrix, equals to the length of a single kernel
+    pub fn cols_rows(&self) -> usize {
+        self.channels * self.kernel_h * self.kernel_w
+    }
+
+    /// Number of columns of im2col matrix, equals to the output positions count
+    pub fn cols_cols(&self) -> usize {
+        self.out_height() * self.out_width()
+    }
+
+    /// Index in the flattened input for the kernel element at output position,
+    /// returns None when it points to the padding
+    pub fn input_idx(&self, c: usize, ki: usize, kj: usize, oy: usize, ox: usize) -> Option<usize> {
+        let y = (oy * self.stride_h + ki * self.dilation_h) as isize - self.pad_h as isize;
+        let x = (ox * self.stride_w + kj * self.dilation_w) as isize - self.pad_w as isize;
+
+        if y < 0 || x < 0 || y >= self.height as isize || x >= self.width as isize {
+            return None;
+        }
+
+        Some((c * self.height + y as usize) * self.width + x as usize)
+    }
+}
+
+/// Unrolls input patches into matrix (channels * kernel_h * kernel_w, out_height * out_width),
+/// so convolution becomes a single matrix product
+pub fn im2col(input: ArrayView1<f32>, g: &ConvGeometry) -> Array2D {
+    let (out_h, out_w) = (g.out_height(), g.out_width());
+    let mut cols = Array2D::zeros((g.cols_rows(), out_h * out_w));
+
+    for c in 0..g.channels {
+        for ki in 0..g.kernel_h {
+            for kj in 0..g.kernel_w {
+                let row = (c * g.kernel_h + ki) * g.kernel_w + kj;
+                let mut cols_row = cols.row_mut(row);
+
+                for oy in 0..out_h {
+                    for ox in 0..out_w {
+                        if let Some(idx) = g.input_idx(c, ki, kj, oy, ox) {
+                            cols_row[oy * out_w + ox] = input[idx];
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    cols
+}
+
+/// Reverse operation for im2col, accumulates values of cols matrix into the flattened input
+pub fn col2im(cols: &Array2D, g: &ConvGeometry, mut out: ArrayViewMut1<f32>) {
+    let (out_h, out_w) = (g.out_height(), g.out_width());
+
+    out.fill(0.0);
+
+    for c in 0..g.channels {
+        for ki in 0..g.kernel_h {
+            for kj in 0..g.kernel_w {
+                let row = (c * g.kernel_h + ki) * g.kernel_w + kj;
+                let cols_row = cols.row(row);
+
+                for oy in 0..out_h {
+                    for ox in 0..out_w {
+                        if let Some(idx) = g.input_idx(c, ki, kj, oy, ox) {
+                            out[idx] += cols_row[oy * out_w + ox];
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use crate::util::{Array1D, Array2D};
+
+/// Optional per-sample weights and per-output mask of a batch.
+/// Loss layers multiply each loss value and its gradient by sample_weight[row] * mask[row, col]
+#[derive(Clone, Default)]
+pub struct LossWeights {
+    pub sample_weights: Option<Array1D>,
+    pub mask: Option<Array2D>,
+}
+
+impl LossWeights {
+    pub fn new(sample_weights: Option<Array1D>, mask: Option<Array2D>) -> Self {
+        Self {
+            sample_weights,
+            mask,
+        }
+    }
+
+    /// Multiplies sample weights by the factor, for example by the loss weight of a model output
+    pub fn scaled(mut self, factor: f32, batch_size: usize) -> Self {
+        if factor != 1.0 {
+            let sample_weights = self
+                .sample_weights
+                .get_or_insert_with(|| Array1D::ones(batch_size));
+            *sample_weights *= factor;
+        }
+
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sample_weights.is_none() && self.mask.is_none()
+    }
+
+    /// Multipliers for each loss value of (batch_size, outputs) shape
+    pub fn factors(&self, dim: (usize, usize)) -> Result<Array2D, String> {
+        let mut factors = match &self.mask {
+            Some(mask) if mask.dim() != dim => {
+                return Err(format!(
+                    "Invalid loss mask shape : {:?}, expected : {:?}",
+                    mask.dim(),
+                    dim
+                ));
+            }
+            Some(mask) => mask.clone(),
+            None => Array2D::ones(dim),
+        };
+
+        if let Some(sample_weights) = &self.sample_weights {
+            if sample_weights.len() != dim.0 {
+                return Err(format!(
+                    "Invalid sample weights count : {}, expected : {}",
+                    sample_weights.len(),
+                    dim.0
+                ));
+            }
+
+            for (mut row, weight) in factors.rows_mut().into_iter().zip(sample_weights.iter()) {
+                row *= *weight;
+            }
+        }
+
+        Ok(factors)
+    }
+}
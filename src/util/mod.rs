@@ -1,10 +1,12 @@
 mod util;
 mod normalize;
 pub mod array_helpers;
+pub mod conv_helpers;
 pub mod activation;
 #[cfg(feature = "opencl")]
 pub mod activation_ocl;
 pub mod with_params;
+pub mod loss_weights;
 
 #[cfg(feature = "opencl")]
 pub use activation_ocl::*;
@@ -12,4 +14,5 @@ pub use util::*;
 pub use normalize::*;
 pub use activation::*;
 pub use activation_macros::*;
-pub use with_params::*;
\ No newline at end of file
+pub use with_params::*;
+pub use loss_weights::*;
\ No newline at end of file
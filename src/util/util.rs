@@ -18,10 +18,15 @@ pub type WsBlob = Vec< WsMat >;
 pub type WsBlobPtr = Rc<RefCell<WsBlob>>;
 pub type Blob<'a> = Vec< &'a DataVec >;
 pub type Metrics = HashMap<String, f64>;
+/// Batches of named model inputs or targets
+pub type NamedArrays = HashMap<String, Array2D>;
 
 #[derive(Serialize, Deserialize)]
 pub enum Variant {
     Int(i32),
     Float(f32),
     String(String),
+    Bool(bool),
+    IntArray(Vec<i32>),
+    FloatArray(Vec<f32>),
 }